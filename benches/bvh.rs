@@ -0,0 +1,110 @@
+// BVH queries and ray casts against brute force iteration over `Mesh::triangles()`.
+// Run with `cargo bench --bench bvh`.
+
+use glam::{Mat4, Vec3, Vec4Swizzles};
+use rusterizer::{ray_triangle_intersection, Aabb, Bvh, Mesh, Ray};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+fn time(name: &str, iterations: u32, mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let elapsed = start.elapsed() / iterations;
+    println!("{name:<32} {:>10.3}ms", elapsed.as_secs_f64() * 1000.0);
+    elapsed
+}
+
+fn main() {
+    // a grid of spheres, the kind of scene picking and culling deal with
+    let sphere = Mesh::new_icosphere(1.0, 4);
+    let parts: Vec<(&Mesh, Mat4)> = (0..64)
+        .map(|i| {
+            let offset = Vec3::new((i % 8) as f32, 0.0, (i / 8) as f32) * 3.0;
+            (&sphere, Mat4::from_translation(offset))
+        })
+        .collect();
+    let mesh = Mesh::merge(&parts);
+    println!("{} triangles", mesh.triangles().len());
+
+    let aabbs = mesh.triangle_aabbs(mesh.vertices());
+    time("build", 5, || {
+        black_box(Bvh::build(&aabbs));
+    });
+    let mut bvh = Bvh::build(&aabbs);
+    println!("{} nodes", bvh.nodes().len());
+    time("refit", 20, || bvh.refit(&aabbs));
+
+    let queries: Vec<Aabb> = (0..256)
+        .map(|i| {
+            let center = Vec3::new((i % 16) as f32 * 1.5, 0.5, (i / 16) as f32 * 1.5);
+            Aabb::new(center - 0.25, center + 0.25)
+        })
+        .collect();
+
+    let brute_force = time("aabb queries, brute force", 5, || {
+        let vertices = mesh.vertices();
+        for query in &queries {
+            let mut hits = 0;
+            for triangle in mesh.triangles() {
+                let aabb = Aabb::from_points(
+                    triangle
+                        .to_array()
+                        .map(|i| vertices[i as usize].position.xyz()),
+                );
+                if aabb.min.cmple(query.max).all() && aabb.max.cmpge(query.min).all() {
+                    hits += 1;
+                }
+            }
+            black_box(hits);
+        }
+    });
+    let hierarchy = time("aabb queries, bvh", 5, || {
+        for query in &queries {
+            let mut hits = 0;
+            bvh.query_aabb(query, |_| hits += 1);
+            black_box(hits);
+        }
+    });
+    println!(
+        "speedup {:.1}x",
+        brute_force.as_secs_f64() / hierarchy.as_secs_f64()
+    );
+
+    // picking rays cast down at the grid from a slight angle, most of them hit a sphere
+    let rays: Vec<Ray> = (0..256)
+        .map(|i| {
+            let target = Vec3::new((i % 16) as f32 * 1.5, 0.0, (i / 16) as f32 * 1.5);
+            let origin = target + Vec3::new(-2.0, 10.0, -3.0);
+            Ray::new(origin, target - origin)
+        })
+        .collect();
+    let corners = |triangle: usize| {
+        mesh.triangles()[triangle]
+            .to_array()
+            .map(|i| mesh.vertices()[i as usize].position.xyz())
+    };
+
+    let brute_force = time("ray queries, brute force", 5, || {
+        for ray in &rays {
+            let closest = (0..mesh.triangles().len())
+                .filter_map(|t| ray_triangle_intersection(ray, &corners(t)))
+                .map(|(distance, _)| distance)
+                .fold(f32::INFINITY, f32::min);
+            black_box(closest);
+        }
+    });
+    let hierarchy = time("ray queries, bvh", 5, || {
+        for ray in &rays {
+            let closest = bvh.intersect_ray(ray, |t| {
+                ray_triangle_intersection(ray, &corners(t)).map(|(distance, _)| distance)
+            });
+            black_box(closest);
+        }
+    });
+    println!(
+        "speedup {:.1}x",
+        brute_force.as_secs_f64() / hierarchy.as_secs_f64()
+    );
+}
@@ -0,0 +1,162 @@
+use gltf::animation::{util::ReadOutputs, Interpolation, Property};
+use std::collections::HashMap;
+
+/// Keyframes driving the morph target weights of one mesh of a `Model`.
+#[derive(Debug, Clone)]
+pub struct MorphChannel {
+    /// Index into `Model::meshes` (and `Model::weights`).
+    pub mesh: usize,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    // one weight per morph target per keyframe, three per target for cubic splines
+    pub values: Vec<f32>,
+    pub target_count: usize,
+}
+
+impl MorphChannel {
+    pub fn duration(&self) -> f32 {
+        self.times.last().copied().unwrap_or(0.0)
+    }
+
+    // for cubic splines every keyframe stores [in tangents, values, out tangents]
+    fn keyframe(&self, key: usize, element: usize) -> &[f32] {
+        let n = self.target_count;
+        match self.interpolation {
+            Interpolation::CubicSpline => {
+                let start = (key * 3 + element) * n;
+                &self.values[start..start + n]
+            }
+            _ => &self.values[key * n..(key + 1) * n],
+        }
+    }
+
+    pub fn sample(&self, time: f32, weights: &mut [f32]) {
+        if self.times.is_empty() || self.target_count == 0 {
+            return;
+        }
+
+        let count = weights.len().min(self.target_count);
+        let last = self.times.len() - 1;
+
+        if time <= self.times[0] || last == 0 {
+            weights[..count].copy_from_slice(&self.keyframe(0, 1)[..count]);
+            return;
+        }
+        if time >= self.times[last] {
+            weights[..count].copy_from_slice(&self.keyframe(last, 1)[..count]);
+            return;
+        }
+
+        // index of the keyframe right before `time`
+        let key = self.times.partition_point(|t| *t <= time) - 1;
+        let (t0, t1) = (self.times[key], self.times[key + 1]);
+        let dt = t1 - t0;
+        let alpha = if dt > 0.0 { (time - t0) / dt } else { 0.0 };
+
+        match self.interpolation {
+            Interpolation::Step => {
+                weights[..count].copy_from_slice(&self.keyframe(key, 1)[..count]);
+            }
+            Interpolation::Linear => {
+                let (start, end) = (self.keyframe(key, 0), self.keyframe(key + 1, 0));
+                for i in 0..count {
+                    weights[i] = crate::utils::lerp(start[i], end[i], alpha);
+                }
+            }
+            Interpolation::CubicSpline => {
+                let (p0, m0) = (self.keyframe(key, 1), self.keyframe(key, 2));
+                let (p1, m1) = (self.keyframe(key + 1, 1), self.keyframe(key + 1, 0));
+
+                // cubic hermite basis
+                let t2 = alpha * alpha;
+                let t3 = t2 * alpha;
+                let h00 = 2.0 * t3 - 3.0 * t2 + 1.0;
+                let h10 = t3 - 2.0 * t2 + alpha;
+                let h01 = -2.0 * t3 + 3.0 * t2;
+                let h11 = t3 - t2;
+
+                for i in 0..count {
+                    weights[i] = h00 * p0[i] + h10 * dt * m0[i] + h01 * p1[i] + h11 * dt * m1[i];
+                }
+            }
+        }
+    }
+}
+
+/// A glTF animation reduced to the channels that target morph target weights.
+#[derive(Debug, Clone)]
+pub struct MorphAnimation {
+    pub name: Option<String>,
+    pub channels: Vec<MorphChannel>,
+}
+
+impl MorphAnimation {
+    /// Reads every weights channel of `animation` whose node maps to a mesh slot in `node_meshes`.
+    pub fn new_from_gltf(
+        animation: &gltf::Animation,
+        buffers: &[gltf::buffer::Data],
+        node_meshes: &HashMap<usize, usize>,
+        target_counts: &[usize],
+    ) -> Option<Self> {
+        let mut channels = Vec::new();
+
+        for channel in animation.channels() {
+            let target = channel.target();
+            if target.property() != Property::MorphTargetWeights {
+                continue;
+            }
+            let Some(&mesh) = node_meshes.get(&target.node().index()) else {
+                continue;
+            };
+
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let times: Vec<f32> = match reader.read_inputs() {
+                Some(inputs) => inputs.collect(),
+                None => continue,
+            };
+            let values: Vec<f32> = match reader.read_outputs() {
+                Some(ReadOutputs::MorphTargetWeights(weights)) => weights.into_f32().collect(),
+                _ => continue,
+            };
+
+            let interpolation = channel.sampler().interpolation();
+            let per_key = match interpolation {
+                Interpolation::CubicSpline => times.len() * 3,
+                _ => times.len(),
+            };
+            let target_count = values.len().checked_div(per_key).unwrap_or(0);
+
+            if target_count == 0 || target_count != target_counts[mesh] {
+                println!(
+                    "Skipping morph channel for mesh {mesh}: {target_count} weights per key, {} targets",
+                    target_counts[mesh]
+                );
+                continue;
+            }
+
+            channels.push(MorphChannel {
+                mesh,
+                interpolation,
+                times,
+                values,
+                target_count,
+            });
+        }
+
+        if channels.is_empty() {
+            None
+        } else {
+            Some(MorphAnimation {
+                name: animation.name().map(String::from),
+                channels,
+            })
+        }
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.channels
+            .iter()
+            .map(MorphChannel::duration)
+            .fold(0.0, f32::max)
+    }
+}
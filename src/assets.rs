@@ -0,0 +1,306 @@
+use crate::{Model, RusterizerError, Texture, ThreadPool};
+
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Typed reference to an asset stored in `Assets`.
+pub struct Handle<T> {
+    id: usize,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+}
+
+// manual impls, deriving would require `T` itself to implement the traits
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl<T> std::fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Handle<{}>({})", std::any::type_name::<T>(), self.id)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssetStatus {
+    Loading,
+    Ready,
+    Failed(String),
+}
+
+enum Slot<T> {
+    Loading,
+    Ready(Arc<T>),
+    Failed(String),
+}
+
+struct StorageInner<T> {
+    next_id: usize,
+    ids: HashMap<PathBuf, usize>,
+    slots: HashMap<usize, (PathBuf, Slot<T>)>,
+}
+
+/// Assets of a single type, keyed by path.
+pub struct AssetStorage<T> {
+    inner: Arc<Mutex<StorageInner<T>>>,
+}
+
+impl<T: Asset> AssetStorage<T> {
+    fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(StorageInner {
+                next_id: 0,
+                ids: HashMap::new(),
+                slots: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Returns the handle already registered for `path`, or registers a new one.
+    /// The bool tells whether the caller has to (re)load the asset.
+    fn reserve(&self, path: &Path) -> (Handle<T>, bool) {
+        let mut inner = self.inner.lock().unwrap();
+
+        if let Some(&id) = inner.ids.get(path) {
+            let handle = Handle {
+                id,
+                marker: PhantomData,
+            };
+            // failed loads are retried, everything else is deduplicated
+            if let Some((_, slot @ Slot::Failed(_))) = inner.slots.get_mut(&id) {
+                *slot = Slot::Loading;
+                return (handle, true);
+            }
+            return (handle, false);
+        }
+
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner.ids.insert(path.to_path_buf(), id);
+        inner.slots.insert(id, (path.to_path_buf(), Slot::Loading));
+
+        (
+            Handle {
+                id,
+                marker: PhantomData,
+            },
+            true,
+        )
+    }
+
+    fn finish(inner: &Mutex<StorageInner<T>>, id: usize, result: Result<T, RusterizerError>) {
+        let mut inner = inner.lock().unwrap();
+        // the handle may have been removed while loading
+        if let Some((path, slot)) = inner.slots.get_mut(&id) {
+            *slot = match result {
+                Ok(asset) => Slot::Ready(Arc::new(asset)),
+                Err(err) => {
+                    println!("Failed to load {}: {err}", path.display());
+                    Slot::Failed(err.to_string())
+                }
+            };
+        }
+    }
+
+    fn load(&self, thread_pool: &ThreadPool, path: &Path) -> Handle<T> {
+        let path = normalize(path);
+        let (handle, needs_loading) = self.reserve(&path);
+
+        if needs_loading {
+            let inner = Arc::clone(&self.inner);
+            thread_pool.execute(move || {
+                let result = T::load(&path);
+                Self::finish(&inner, handle.id, result);
+            });
+        }
+
+        handle
+    }
+
+    fn load_blocking(&self, path: &Path) -> Handle<T> {
+        let path = normalize(path);
+        let (handle, needs_loading) = self.reserve(&path);
+
+        if needs_loading {
+            let result = T::load(&path);
+            Self::finish(&self.inner, handle.id, result);
+        }
+
+        handle
+    }
+
+    fn status(&self, handle: &Handle<T>) -> Option<AssetStatus> {
+        let inner = self.inner.lock().unwrap();
+        inner.slots.get(&handle.id).map(|(_, slot)| match slot {
+            Slot::Loading => AssetStatus::Loading,
+            Slot::Ready(_) => AssetStatus::Ready,
+            Slot::Failed(message) => AssetStatus::Failed(message.clone()),
+        })
+    }
+
+    fn get(&self, handle: &Handle<T>) -> Option<Arc<T>> {
+        let inner = self.inner.lock().unwrap();
+        match inner.slots.get(&handle.id) {
+            Some((_, Slot::Ready(asset))) => Some(Arc::clone(asset)),
+            _ => None,
+        }
+    }
+
+    fn remove(&self, handle: &Handle<T>) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.slots.remove(&handle.id) {
+            Some((path, _)) => {
+                inner.ids.remove(&path);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // drops the ready assets nobody outside of the storage holds on to
+    fn unload_unused(&self) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let unused: Vec<(usize, PathBuf)> = inner
+            .slots
+            .iter()
+            .filter_map(|(id, (path, slot))| match slot {
+                Slot::Ready(asset) if Arc::strong_count(asset) == 1 => Some((*id, path.clone())),
+                _ => None,
+            })
+            .collect();
+
+        for (id, path) in &unused {
+            inner.slots.remove(id);
+            inner.ids.remove(path);
+        }
+
+        unused.len()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.lock().unwrap().slots.len()
+    }
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Something `Assets` knows how to load from disk.
+pub trait Asset: Sized + Send + Sync + 'static {
+    fn load(path: &Path) -> Result<Self, RusterizerError>;
+    fn storage(assets: &Assets) -> &AssetStorage<Self>;
+}
+
+impl Asset for Model {
+    fn load(path: &Path) -> Result<Self, RusterizerError> {
+        Model::new(path)
+    }
+
+    fn storage(assets: &Assets) -> &AssetStorage<Self> {
+        &assets.models
+    }
+}
+
+impl Asset for Texture {
+    fn load(path: &Path) -> Result<Self, RusterizerError> {
+        Texture::load(path)
+    }
+
+    fn storage(assets: &Assets) -> &AssetStorage<Self> {
+        &assets.textures
+    }
+}
+
+/// Registry of loaded models and textures.
+///
+/// Loading the same path twice returns the same handle, assets are loaded on the
+/// `ThreadPool` and can be polled with `status`/`get`.
+pub struct Assets {
+    models: AssetStorage<Model>,
+    textures: AssetStorage<Texture>,
+}
+
+impl Assets {
+    pub fn new() -> Self {
+        Self {
+            models: AssetStorage::new(),
+            textures: AssetStorage::new(),
+        }
+    }
+
+    /// Starts loading `path` in the background, unless it is already loaded or loading.
+    pub fn load<T: Asset>(&self, thread_pool: &ThreadPool, path: impl AsRef<Path>) -> Handle<T> {
+        T::storage(self).load(thread_pool, path.as_ref())
+    }
+
+    /// Loads `path` on the calling thread, unless it is already loaded or loading.
+    pub fn load_blocking<T: Asset>(&self, path: impl AsRef<Path>) -> Handle<T> {
+        T::storage(self).load_blocking(path.as_ref())
+    }
+
+    /// `None` if the handle was removed or unloaded.
+    pub fn status<T: Asset>(&self, handle: &Handle<T>) -> Option<AssetStatus> {
+        T::storage(self).status(handle)
+    }
+
+    pub fn is_ready<T: Asset>(&self, handle: &Handle<T>) -> bool {
+        self.status(handle) == Some(AssetStatus::Ready)
+    }
+
+    /// The asset, once it finished loading.
+    pub fn get<T: Asset>(&self, handle: &Handle<T>) -> Option<Arc<T>> {
+        T::storage(self).get(handle)
+    }
+
+    /// Forgets the asset, returns false if the handle was unknown.
+    pub fn remove<T: Asset>(&self, handle: &Handle<T>) -> bool {
+        T::storage(self).remove(handle)
+    }
+
+    /// Drops every loaded asset that is not referenced outside of the registry.
+    /// Returns the number of assets dropped.
+    pub fn unload_unused(&self) -> usize {
+        self.models.unload_unused() + self.textures.unload_unused()
+    }
+
+    pub fn len(&self) -> usize {
+        self.models.len() + self.textures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for Assets {
+    fn default() -> Self {
+        Self::new()
+    }
+}
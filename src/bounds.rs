@@ -0,0 +1,193 @@
+use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
+
+/// Axis aligned bounding box in 3D.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// Contains nothing, the union with any box gives that box.
+    pub const EMPTY: Self = Self {
+        min: Vec3::splat(f32::INFINITY),
+        max: Vec3::splat(f32::NEG_INFINITY),
+    };
+
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        points
+            .into_iter()
+            .fold(Self::EMPTY, |aabb, p| aabb.extend(p))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn size(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn extend(&self, point: Vec3) -> Self {
+        Self::new(self.min.min(point), self.max.max(point))
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self::new(self.min.min(other.min), self.max.max(other.max))
+    }
+
+    pub fn contains(&self, point: Vec3) -> bool {
+        point.cmpge(self.min).all() && point.cmple(self.max).all()
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Vec3::new(min.x, min.y, min.z),
+            Vec3::new(max.x, min.y, min.z),
+            Vec3::new(min.x, max.y, min.z),
+            Vec3::new(max.x, max.y, min.z),
+            Vec3::new(min.x, min.y, max.z),
+            Vec3::new(max.x, min.y, max.z),
+            Vec3::new(min.x, max.y, max.z),
+            Vec3::new(max.x, max.y, max.z),
+        ]
+    }
+
+    /// The box around this one after `matrix` (an affine transform) is applied.
+    pub fn transform(&self, matrix: &Mat4) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+        Self::from_points(self.corners().map(|c| matrix.transform_point3(c)))
+    }
+}
+
+/// Sphere enclosing a set of points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    pub fn new(center: Vec3, radius: f32) -> Self {
+        Self { center, radius }
+    }
+
+    /// Centred on the bounding box of the points, not the smallest possible sphere.
+    pub fn from_points(points: impl IntoIterator<Item = Vec3> + Clone) -> Self {
+        let aabb = Aabb::from_points(points.clone());
+        if aabb.is_empty() {
+            return Self::new(Vec3::ZERO, 0.0);
+        }
+
+        let center = aabb.center();
+        let radius = points
+            .into_iter()
+            .map(|p| p.distance(center))
+            .fold(0.0, f32::max);
+        Self::new(center, radius)
+    }
+
+    /// The smallest sphere containing both.
+    pub fn union(&self, other: &BoundingSphere) -> Self {
+        let offset = other.center - self.center;
+        let distance = offset.length();
+        if distance + other.radius <= self.radius {
+            return *self;
+        }
+        if distance + self.radius <= other.radius {
+            return *other;
+        }
+
+        let radius = (distance + self.radius + other.radius) * 0.5;
+        let center = self.center + offset * ((radius - self.radius) / distance);
+        Self::new(center, radius)
+    }
+
+    /// The sphere after `matrix` is applied, non-uniform scales grow it by the largest axis.
+    pub fn transform(&self, matrix: &Mat4) -> Self {
+        let scale = matrix
+            .x_axis
+            .xyz()
+            .length()
+            .max(matrix.y_axis.xyz().length())
+            .max(matrix.z_axis.xyz().length());
+        Self::new(matrix.transform_point3(self.center), self.radius * scale)
+    }
+}
+
+/// Plane of points `p` where `normal.dot(p) + d == 0`, the normal points to the inside.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub d: f32,
+}
+
+impl Plane {
+    // planes extracted from a matrix are not unit length
+    fn from_vec4(plane: Vec4) -> Self {
+        let length = plane.xyz().length();
+        Self {
+            normal: plane.xyz() / length,
+            d: plane.w / length,
+        }
+    }
+
+    /// Positive in front of the plane, negative behind it.
+    pub fn signed_distance(&self, point: Vec3) -> f32 {
+        self.normal.dot(point) + self.d
+    }
+}
+
+/// The six planes of a view volume: left, right, bottom, top, near, far.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a projection matrix (Gribb & Hartmann), clip space
+    /// z going from 0 to w like `Camera::projection`.
+    ///
+    /// The planes are in the space the matrix transforms from: world space for
+    /// `projection * view`, model space for a full model-view-projection.
+    pub fn from_matrix(matrix: &Mat4) -> Self {
+        let (x, y, z, w) = (matrix.row(0), matrix.row(1), matrix.row(2), matrix.row(3));
+        Self {
+            planes: [w + x, w - x, w + y, w - y, z, w - z].map(Plane::from_vec4),
+        }
+    }
+
+    pub fn contains_point(&self, point: Vec3) -> bool {
+        self.planes.iter().all(|p| p.signed_distance(point) >= 0.0)
+    }
+
+    /// False only when the sphere is fully outside, may give false positives near corners.
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|p| p.signed_distance(sphere.center) >= -sphere.radius)
+    }
+
+    /// False only when the box is fully outside, may give false positives near corners.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        if aabb.is_empty() {
+            return false;
+        }
+        self.planes.iter().all(|plane| {
+            // the corner furthest along the plane normal
+            let corner = Vec3::select(plane.normal.cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            plane.signed_distance(corner) >= 0.0
+        })
+    }
+}
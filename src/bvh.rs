@@ -0,0 +1,338 @@
+use crate::bounds::{Aabb, Frustum};
+use crate::geometry::{ray_aabb_intersection, Ray, Vertex};
+use crate::mesh::Mesh;
+use crate::model::Model;
+use crate::transform::Transform;
+
+use glam::{Vec3, Vec4Swizzles};
+
+// number of buckets the centroids are sorted into when looking for a split
+const SAH_BINS: usize = 12;
+// relative cost of visiting a node compared to testing a primitive
+const TRAVERSAL_COST: f32 = 1.0;
+
+/// A node of a `Bvh`. Leaves reference `count` primitives starting at `first` in
+/// `Bvh::primitives`, inner nodes have `count == 0` and their children at `first`
+/// and `first + 1`.
+#[derive(Debug, Clone, Copy)]
+pub struct BvhNode {
+    pub aabb: Aabb,
+    pub first: u32,
+    pub count: u32,
+}
+
+impl BvhNode {
+    pub fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+}
+
+/// Bounding volume hierarchy over anything that has a bounding box: the triangles
+/// of a mesh, the instances of a scene.
+///
+/// Primitives are referred to by their index in the slice of boxes given to `build`.
+/// Children always come after their parent in `nodes`.
+#[derive(Debug, Clone, Default)]
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    primitives: Vec<u32>,
+}
+
+fn surface_area(aabb: &Aabb) -> f32 {
+    if aabb.is_empty() {
+        return 0.0;
+    }
+    let size = aabb.size();
+    2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+}
+
+impl Bvh {
+    /// Builds the hierarchy with the surface area heuristic, binned.
+    pub fn build(aabbs: &[Aabb]) -> Self {
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(aabbs.len() * 2),
+            primitives: (0..aabbs.len() as u32).collect(),
+        };
+        if aabbs.is_empty() {
+            return bvh;
+        }
+
+        let centroids: Vec<Vec3> = aabbs.iter().map(Aabb::center).collect();
+        bvh.nodes.push(BvhNode {
+            aabb: Aabb::EMPTY,
+            first: 0,
+            count: aabbs.len() as u32,
+        });
+
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            if let Some((left, right)) = bvh.subdivide(node, aabbs, &centroids) {
+                stack.push(left);
+                stack.push(right);
+            }
+        }
+
+        bvh
+    }
+
+    // fits the node to its primitives and splits it if that is cheaper by the
+    // surface area heuristic, returns the children
+    fn subdivide(
+        &mut self,
+        node: usize,
+        aabbs: &[Aabb],
+        centroids: &[Vec3],
+    ) -> Option<(usize, usize)> {
+        let BvhNode { first, count, .. } = self.nodes[node];
+        let (first, count) = (first as usize, count as usize);
+        let primitives = &mut self.primitives[first..first + count];
+
+        let aabb = primitives
+            .iter()
+            .fold(Aabb::EMPTY, |a, p| a.union(&aabbs[*p as usize]));
+        self.nodes[node].aabb = aabb;
+        if count <= 2 {
+            return None;
+        }
+
+        let centroid_bounds = Aabb::from_points(primitives.iter().map(|p| centroids[*p as usize]));
+        let extent = centroid_bounds.size();
+
+        // best (cost, axis, bin) over every axis
+        let mut best: Option<(f32, usize, usize)> = None;
+        for axis in 0..3 {
+            if extent[axis] <= 0.0 {
+                continue;
+            }
+            let bin_of = |p: u32| {
+                let offset =
+                    (centroids[p as usize][axis] - centroid_bounds.min[axis]) / extent[axis];
+                ((offset * SAH_BINS as f32) as usize).min(SAH_BINS - 1)
+            };
+
+            let mut bins = [(Aabb::EMPTY, 0usize); SAH_BINS];
+            for p in primitives.iter() {
+                let bin = &mut bins[bin_of(*p)];
+                bin.0 = bin.0.union(&aabbs[*p as usize]);
+                bin.1 += 1;
+            }
+
+            // sweep from the right to know the cost of every right side
+            let mut right_costs = [0.0; SAH_BINS];
+            let (mut right_box, mut right_count) = (Aabb::EMPTY, 0);
+            for split in (1..SAH_BINS).rev() {
+                right_box = right_box.union(&bins[split].0);
+                right_count += bins[split].1;
+                right_costs[split] = surface_area(&right_box) * right_count as f32;
+            }
+
+            let (mut left_box, mut left_count) = (Aabb::EMPTY, 0);
+            for split in 1..SAH_BINS {
+                left_box = left_box.union(&bins[split - 1].0);
+                left_count += bins[split - 1].1;
+                if left_count == 0 || left_count == count {
+                    continue;
+                }
+                let cost = surface_area(&left_box) * left_count as f32 + right_costs[split];
+                if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                    best = Some((cost, axis, split));
+                }
+            }
+        }
+
+        let (cost, axis, split) = best?;
+        // splitting has to beat testing every primitive of this node
+        let leaf_cost = surface_area(&aabb) * count as f32;
+        if cost + TRAVERSAL_COST * surface_area(&aabb) >= leaf_cost {
+            return None;
+        }
+
+        // partition in place, primitives in bins below `split` go left
+        let bin_of = |p: u32| {
+            let offset = (centroids[p as usize][axis] - centroid_bounds.min[axis]) / extent[axis];
+            ((offset * SAH_BINS as f32) as usize).min(SAH_BINS - 1)
+        };
+        let mut left_count = 0;
+        for i in 0..count {
+            if bin_of(primitives[i]) < split {
+                primitives.swap(i, left_count);
+                left_count += 1;
+            }
+        }
+
+        let left = self.nodes.len();
+        self.nodes.push(BvhNode {
+            aabb: Aabb::EMPTY,
+            first: first as u32,
+            count: left_count as u32,
+        });
+        self.nodes.push(BvhNode {
+            aabb: Aabb::EMPTY,
+            first: (first + left_count) as u32,
+            count: (count - left_count) as u32,
+        });
+        self.nodes[node].first = left as u32;
+        self.nodes[node].count = 0;
+
+        Some((left, left + 1))
+    }
+
+    /// Updates the boxes of every node after the primitives moved, keeping the tree.
+    ///
+    /// `aabbs` must have the same length and order as the slice the hierarchy was built
+    /// from. Much faster than a rebuild, but the tree degrades if things move a lot.
+    pub fn refit(&mut self, aabbs: &[Aabb]) {
+        for i in (0..self.nodes.len()).rev() {
+            let node = self.nodes[i];
+            let (first, count) = (node.first as usize, node.count as usize);
+            self.nodes[i].aabb = if node.is_leaf() {
+                self.primitives[first..first + count]
+                    .iter()
+                    .fold(Aabb::EMPTY, |a, p| a.union(&aabbs[*p as usize]))
+            } else {
+                self.nodes[first].aabb.union(&self.nodes[first + 1].aabb)
+            };
+        }
+    }
+
+    pub fn nodes(&self) -> &[BvhNode] {
+        &self.nodes
+    }
+
+    /// Primitive indices in the order the leaves reference them.
+    pub fn primitives(&self) -> &[u32] {
+        &self.primitives
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Box around everything in the hierarchy.
+    pub fn aabb(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::EMPTY, |node| node.aabb)
+    }
+
+    /// Visits every node whose box passes `enter`, calling `visit` with the primitives
+    /// of the leaves reached. The building block of the other queries.
+    pub fn traverse(&self, mut enter: impl FnMut(&Aabb) -> bool, mut visit: impl FnMut(usize)) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut stack = vec![0usize];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !enter(&node.aabb) {
+                continue;
+            }
+            let first = node.first as usize;
+            if node.is_leaf() {
+                for primitive in &self.primitives[first..first + node.count as usize] {
+                    visit(*primitive as usize);
+                }
+            } else {
+                stack.push(first + 1);
+                stack.push(first);
+            }
+        }
+    }
+
+    /// Calls `visit` for every primitive whose box may overlap `aabb`.
+    pub fn query_aabb(&self, aabb: &Aabb, visit: impl FnMut(usize)) {
+        self.traverse(
+            |node| node.min.cmple(aabb.max).all() && node.max.cmpge(aabb.min).all(),
+            visit,
+        );
+    }
+
+    /// Calls `visit` for every primitive whose box may be inside `frustum`.
+    pub fn query_frustum(&self, frustum: &Frustum, visit: impl FnMut(usize)) {
+        self.traverse(|node| frustum.intersects_aabb(node), visit);
+    }
+
+    /// Closest hit along the ray. `hit` tests a primitive and returns its distance,
+    /// nodes further away than the closest hit so far are skipped.
+    pub fn intersect_ray(
+        &self,
+        ray: &Ray,
+        mut hit: impl FnMut(usize) -> Option<f32>,
+    ) -> Option<(usize, f32)> {
+        let mut closest: Option<(usize, f32)> = None;
+        if self.nodes.is_empty() {
+            return closest;
+        }
+
+        let mut stack = vec![0usize];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let Some((near, _)) = ray_aabb_intersection(ray, &node.aabb) else {
+                continue;
+            };
+            if closest.is_some_and(|(_, distance)| near > distance) {
+                continue;
+            }
+
+            let first = node.first as usize;
+            if node.is_leaf() {
+                for primitive in &self.primitives[first..first + node.count as usize] {
+                    let primitive = *primitive as usize;
+                    if let Some(distance) = hit(primitive) {
+                        if closest.is_none_or(|(_, closest)| distance < closest) {
+                            closest = Some((primitive, distance));
+                        }
+                    }
+                }
+            } else {
+                stack.push(first + 1);
+                stack.push(first);
+            }
+        }
+
+        closest
+    }
+}
+
+impl Mesh {
+    /// Box of every triangle for `vertices` (the mesh vertices, or morphed ones),
+    /// empty for triangles referencing missing vertices.
+    pub fn triangle_aabbs(&self, vertices: &[Vertex]) -> Vec<Aabb> {
+        self.triangles()
+            .iter()
+            .map(|triangle| {
+                Aabb::from_points(
+                    triangle
+                        .to_array()
+                        .iter()
+                        .filter_map(|i| vertices.get(*i as usize))
+                        .map(|v| v.position.xyz()),
+                )
+            })
+            .collect()
+    }
+
+    /// Hierarchy over the triangles of the mesh in model space, primitive `i` being
+    /// `triangles()[i]`. Refit it with `triangle_aabbs(&morphed_vertices(..))` when
+    /// the morph weights change.
+    pub fn build_bvh(&self) -> Bvh {
+        Bvh::build(&self.triangle_aabbs(self.vertices()))
+    }
+}
+
+impl Model {
+    /// World space box of the model for every instance, see `draw_instanced`.
+    pub fn instance_aabbs(&self, instances: &[Transform]) -> Vec<Aabb> {
+        let aabb = self.aabb();
+        let local = self.transform.local();
+        instances
+            .iter()
+            .map(|instance| aabb.transform(&(instance.local() * local)))
+            .collect()
+    }
+
+    /// Hierarchy over the instances of the model, primitive `i` being `instances[i]`.
+    /// Refit it with `instance_aabbs` when the instances move.
+    pub fn build_instance_bvh(&self, instances: &[Transform]) -> Bvh {
+        Bvh::build(&self.instance_aabbs(instances))
+    }
+}
@@ -1,6 +1,8 @@
+use crate::bounds::Frustum;
+use crate::geometry::Ray;
 use crate::transform::Transform;
 
-use glam::Mat4;
+use glam::{Mat4, Vec2, Vec3};
 use minifb::{Key, Window};
 
 pub struct Camera {
@@ -43,6 +45,34 @@ impl Camera {
         )
     }
 
+    /// The view volume in world space.
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(&(self.projection() * self.view()))
+    }
+
+    /// Fraction of the viewport height covered by a sphere, 1 or more when it fills the view.
+    pub fn projected_size(&self, center: Vec3, radius: f32) -> f32 {
+        let distance = center.distance(self.transform.translation);
+        if distance <= radius {
+            return f32::INFINITY;
+        }
+        radius / (distance * (self.fov * 0.5).tan())
+    }
+
+    /// World space ray through the point `(x, y)` of the viewport, in pixels from the
+    /// top left corner. Starts on the near plane, the direction is normalized.
+    pub fn screen_ray(&self, x: f32, y: f32, viewport_size: Vec2) -> Ray {
+        let ndc = Vec2::new(
+            x / viewport_size.x * 2.0 - 1.0,
+            1.0 - y / viewport_size.y * 2.0,
+        );
+        let inverse = (self.projection() * self.view()).inverse();
+        // clip space z goes from 0 at the near plane to 1 at the far plane
+        let near = inverse.project_point3(ndc.extend(0.0));
+        let far = inverse.project_point3(ndc.extend(1.0));
+        Ray::new(near, (far - near).normalize())
+    }
+
     pub fn update(&mut self, window: &Window, dt: f32) {
         let mut axis = glam::vec2(0.0, 0.0);
         // we will make registering later
@@ -0,0 +1,284 @@
+use crate::error::RusterizerError;
+use crate::texture::Texture;
+use crate::utils::srgb_to_linear;
+
+use glam::{Vec2, Vec3, Vec4Swizzles};
+use std::f32::consts::{FRAC_1_PI, PI};
+use std::path::Path;
+
+/// Faces of a `CubeMap`, in the usual +X, -X, +Y, -Y, +Z, -Z order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CubeFace {
+    PositiveX,
+    NegativeX,
+    PositiveY,
+    NegativeY,
+    PositiveZ,
+    NegativeZ,
+}
+
+impl CubeFace {
+    pub const ALL: [CubeFace; 6] = [
+        CubeFace::PositiveX,
+        CubeFace::NegativeX,
+        CubeFace::PositiveY,
+        CubeFace::NegativeY,
+        CubeFace::PositiveZ,
+        CubeFace::NegativeZ,
+    ];
+
+    // direction through `uv` in [-1, 1], u going right and v down when looking at the face
+    // from the inside, as in the OpenGL convention
+    fn direction(&self, uv: Vec2) -> Vec3 {
+        match self {
+            CubeFace::PositiveX => Vec3::new(1.0, -uv.y, -uv.x),
+            CubeFace::NegativeX => Vec3::new(-1.0, -uv.y, uv.x),
+            CubeFace::PositiveY => Vec3::new(uv.x, 1.0, uv.y),
+            CubeFace::NegativeY => Vec3::new(uv.x, -1.0, -uv.y),
+            CubeFace::PositiveZ => Vec3::new(uv.x, -uv.y, 1.0),
+            CubeFace::NegativeZ => Vec3::new(-uv.x, -uv.y, -1.0),
+        }
+    }
+
+    // the face `direction` points to, and where in [-1, 1]
+    fn from_direction(direction: Vec3) -> (CubeFace, Vec2) {
+        let a = direction.abs();
+        if a.x >= a.y && a.x >= a.z {
+            if direction.x > 0.0 {
+                (
+                    CubeFace::PositiveX,
+                    Vec2::new(-direction.z, -direction.y) / a.x,
+                )
+            } else {
+                (
+                    CubeFace::NegativeX,
+                    Vec2::new(direction.z, -direction.y) / a.x,
+                )
+            }
+        } else if a.y >= a.z {
+            if direction.y > 0.0 {
+                (
+                    CubeFace::PositiveY,
+                    Vec2::new(direction.x, direction.z) / a.y,
+                )
+            } else {
+                (
+                    CubeFace::NegativeY,
+                    Vec2::new(direction.x, -direction.z) / a.y,
+                )
+            }
+        } else if direction.z > 0.0 {
+            (
+                CubeFace::PositiveZ,
+                Vec2::new(direction.x, -direction.y) / a.z,
+            )
+        } else {
+            (
+                CubeFace::NegativeZ,
+                Vec2::new(-direction.x, -direction.y) / a.z,
+            )
+        }
+    }
+}
+
+/// Six square faces of linear HDR colours, sampled with a direction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CubeMap {
+    size: usize,
+    // one per `CubeFace::ALL`, rows from the top
+    faces: [Vec<Vec3>; 6],
+}
+
+impl CubeMap {
+    /// A cube map of `size`² texels per face, filled by `f` from the direction of each texel.
+    pub fn from_fn(size: usize, f: impl Fn(Vec3) -> Vec3) -> Self {
+        let size = size.max(1);
+        let faces = CubeFace::ALL.map(|face| {
+            (0..size * size)
+                .map(|i| {
+                    let texel = Vec2::new((i % size) as f32, (i / size) as f32) + 0.5;
+                    let uv = texel / size as f32 * 2.0 - 1.0;
+                    f(face.direction(uv).normalize())
+                })
+                .collect()
+        });
+        Self { size, faces }
+    }
+
+    /// Builds the cube map from six square images of the same size, in `CubeFace::ALL`
+    /// order. sRGB textures are decoded to linear.
+    pub fn from_textures(faces: [&Texture; 6]) -> Result<Self, RusterizerError> {
+        let size = faces[0].width;
+        if let Some(face) = faces
+            .iter()
+            .find(|face| face.width != size || face.height != size)
+        {
+            return Err(RusterizerError::Decode(format!(
+                "cube map faces must all be {size}x{size}, found {}x{}",
+                face.width, face.height
+            )));
+        }
+
+        let faces = faces.map(|face| {
+            (0..size * size)
+                .map(|i| {
+                    let uv = (Vec2::new((i % size) as f32, (i / size) as f32) + 0.5) / size as f32;
+                    face.color_at_uv(uv.x, uv.y).xyz()
+                })
+                .collect()
+        });
+        Ok(Self { size, faces })
+    }
+
+    /// Loads six images, in `CubeFace::ALL` order.
+    pub fn load_faces(paths: [&Path; 6]) -> Result<Self, RusterizerError> {
+        let textures = paths
+            .iter()
+            .map(|path| Texture::load(path))
+            .collect::<Result<Vec<Texture>, RusterizerError>>()?;
+        Self::from_textures(std::array::from_fn(|i| &textures[i]))
+    }
+
+    /// Resamples a latitude-longitude image of `width`×`height` linear colours, rows
+    /// from the top, into faces of `size`² texels.
+    pub fn from_equirectangular(width: usize, height: usize, pixels: &[Vec3], size: usize) -> Self {
+        Self::from_fn(size, |direction| {
+            let u = 0.5 + direction.z.atan2(direction.x) * 0.5 * FRAC_1_PI;
+            let v = direction.y.clamp(-1.0, 1.0).acos() * FRAC_1_PI;
+            let x = ((u * width as f32) as usize).min(width - 1);
+            let y = ((v * height as f32) as usize).min(height - 1);
+            pixels[y * width + x]
+        })
+    }
+
+    /// Loads an equirectangular image, usually a Radiance `.hdr` file. 8-bit formats are
+    /// taken as sRGB.
+    pub fn load_equirectangular(path: &Path, size: usize) -> Result<Self, RusterizerError> {
+        let image = image::open(path)?;
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let is_float = matches!(
+            image,
+            image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_)
+        );
+        let pixels: Vec<Vec3> = image
+            .into_rgb32f()
+            .pixels()
+            .map(|pixel| {
+                let color = Vec3::from_array(pixel.0);
+                if is_float {
+                    color
+                } else {
+                    color.to_array().map(srgb_to_linear).into()
+                }
+            })
+            .collect();
+        Ok(Self::from_equirectangular(width, height, &pixels, size))
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn face(&self, face: CubeFace) -> &[Vec3] {
+        &self.faces[face as usize]
+    }
+
+    /// The colour seen in `direction`, which does not need to be normalized. Bilinear
+    /// within a face, the edges are clamped.
+    pub fn sample(&self, direction: Vec3) -> Vec3 {
+        if direction == Vec3::ZERO {
+            return Vec3::ZERO;
+        }
+        let (face, uv) = CubeFace::from_direction(direction);
+        let texels = &self.faces[face as usize];
+        let max = self.size as f32 - 1.0;
+        let position =
+            ((uv + 1.0) * 0.5 * self.size as f32 - 0.5).clamp(Vec2::ZERO, Vec2::splat(max));
+        let low = position.floor();
+        let t = position - low;
+        let (x0, y0) = (low.x as usize, low.y as usize);
+        let (x1, y1) = ((x0 + 1).min(self.size - 1), (y0 + 1).min(self.size - 1));
+        let at = |x: usize, y: usize| texels[y * self.size + x];
+        let top = at(x0, y0).lerp(at(x1, y0), t.x);
+        let bottom = at(x0, y1).lerp(at(x1, y1), t.x);
+        top.lerp(bottom, t.y)
+    }
+
+    /// Half the size, each texel averaging four. `None` once the faces are a single texel.
+    pub fn downsample(&self) -> Option<Self> {
+        if self.size < 2 {
+            return None;
+        }
+        let size = self.size / 2;
+        let faces = std::array::from_fn(|face| {
+            let texels = &self.faces[face];
+            (0..size * size)
+                .map(|i| {
+                    let (x, y) = ((i % size) * 2, (i / size) * 2);
+                    let at = |dx: usize, dy: usize| texels[(y + dy) * self.size + x + dx];
+                    (at(0, 0) + at(1, 0) + at(0, 1) + at(1, 1)) * 0.25
+                })
+                .collect()
+        });
+        Some(Self { size, faces })
+    }
+
+    /// Projects the cube map on the first nine spherical harmonics, convolved with a
+    /// cosine lobe so that `irradiance` can be evaluated from them directly.
+    pub fn irradiance_coefficients(&self) -> [Vec3; 9] {
+        let mut coefficients = [Vec3::ZERO; 9];
+        let mut total_weight = 0.0;
+        for face in CubeFace::ALL {
+            for (i, color) in self.faces[face as usize].iter().enumerate() {
+                let texel = Vec2::new((i % self.size) as f32, (i / self.size) as f32) + 0.5;
+                let uv = texel / self.size as f32 * 2.0 - 1.0;
+                // solid angle of the texel, relative
+                let weight = 1.0 / (1.0 + uv.length_squared()).powf(1.5);
+                let direction = face.direction(uv).normalize();
+                for (coefficient, basis) in coefficients.iter_mut().zip(sh_basis(direction)) {
+                    *coefficient += *color * basis * weight;
+                }
+                total_weight += weight;
+            }
+        }
+
+        // cosine lobe convolution of each band
+        let bands = [PI, 2.0 * PI / 3.0, PI / 4.0];
+        let normalization = 4.0 * PI / total_weight;
+        for (i, coefficient) in coefficients.iter_mut().enumerate() {
+            let band = match i {
+                0 => 0,
+                1..=3 => 1,
+                _ => 2,
+            };
+            *coefficient *= normalization * bands[band] * FRAC_1_PI;
+        }
+        coefficients
+    }
+}
+
+/// Diffuse light reaching a surface facing `normal`, from `CubeMap::irradiance_coefficients`.
+/// Multiply by the albedo for the outgoing colour.
+pub fn irradiance(coefficients: &[Vec3; 9], normal: Vec3) -> Vec3 {
+    coefficients
+        .iter()
+        .zip(sh_basis(normal))
+        .map(|(coefficient, basis)| *coefficient * basis)
+        .sum::<Vec3>()
+        .max(Vec3::ZERO)
+}
+
+// real spherical harmonics up to the second band
+fn sh_basis(d: Vec3) -> [f32; 9] {
+    [
+        0.282_095,
+        0.488_603 * d.y,
+        0.488_603 * d.z,
+        0.488_603 * d.x,
+        1.092_548 * d.x * d.y,
+        1.092_548 * d.y * d.z,
+        0.315_392 * (3.0 * d.z * d.z - 1.0),
+        1.092_548 * d.x * d.z,
+        0.546_274 * (d.x * d.x - d.y * d.y),
+    ]
+}
@@ -0,0 +1,250 @@
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::camera::Camera;
+use crate::geometry::Vertex;
+use crate::lines::{Line, LineStyle};
+use crate::mesh::Mesh;
+use crate::model::Model;
+use crate::transform::Transform;
+use crate::utils::cofactor;
+
+use glam::{Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
+use std::f32::consts::TAU;
+
+// segments of the circles making up spheres
+const CIRCLE_SEGMENTS: usize = 32;
+
+/// Immediate-mode debug drawing: shapes are queued in world space during the frame, then
+/// `DebugDraw::draw` renders them as lines over the finished image and forgets them.
+#[derive(Debug, Clone)]
+pub struct DebugDraw {
+    /// Hides the shapes behind geometry, for the shapes queued from now on.
+    pub depth_test: bool,
+    pub style: LineStyle,
+    lines: Vec<DebugLine>,
+    // infinitely far depths for the shapes drawn on top
+    overlay_depth: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct DebugLine {
+    start: Vec3,
+    end: Vec3,
+    color: Vec4,
+    depth_test: bool,
+}
+
+impl Default for DebugDraw {
+    fn default() -> Self {
+        Self {
+            depth_test: true,
+            style: LineStyle::AntiAliased,
+            lines: vec![],
+            overlay_depth: vec![],
+        }
+    }
+}
+
+impl DebugDraw {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of lines queued since the last `draw`.
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Forgets the queued shapes without drawing them.
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    /// A segment, `color` being linear.
+    pub fn line(&mut self, start: Vec3, end: Vec3, color: Vec4) {
+        self.lines.push(DebugLine {
+            start,
+            end,
+            color,
+            depth_test: self.depth_test,
+        });
+    }
+
+    /// A line from `start` to `end` with a head pointing at `end`.
+    pub fn arrow(&mut self, start: Vec3, end: Vec3, color: Vec4) {
+        self.line(start, end, color);
+        let direction = end - start;
+        let length = direction.length();
+        if length <= f32::EPSILON {
+            return;
+        }
+        let direction = direction / length;
+        let (side, up) = direction.any_orthonormal_pair();
+        let head = length * 0.15;
+        let base = end - direction * head;
+        for offset in [side, -side, up, -up] {
+            self.line(end, base + offset * head * 0.5, color);
+        }
+    }
+
+    pub fn aabb(&mut self, aabb: &Aabb, color: Vec4) {
+        self.box_corners(aabb.corners(), color);
+    }
+
+    /// `aabb` after `model`, as an oriented box rather than the box around it.
+    pub fn oriented_box(&mut self, aabb: &Aabb, model: &Mat4, color: Vec4) {
+        self.box_corners(aabb.corners().map(|c| model.transform_point3(c)), color);
+    }
+
+    /// Three circles around the axes.
+    pub fn sphere(&mut self, center: Vec3, radius: f32, color: Vec4) {
+        self.circle(center, Vec3::X * radius, Vec3::Y * radius, color);
+        self.circle(center, Vec3::Y * radius, Vec3::Z * radius, color);
+        self.circle(center, Vec3::Z * radius, Vec3::X * radius, color);
+    }
+
+    pub fn bounding_sphere(&mut self, sphere: &BoundingSphere, color: Vec4) {
+        self.sphere(sphere.center, sphere.radius, color);
+    }
+
+    /// Lines every `spacing` on the y = 0 plane, up to `extent` from the origin. The axes
+    /// through the origin are drawn in red (x) and blue (z).
+    pub fn grid(&mut self, extent: f32, spacing: f32, color: Vec4) {
+        let lines = (extent / spacing.max(f32::EPSILON)) as i32;
+        let extent = lines as f32 * spacing;
+        for i in -lines..=lines {
+            let offset = i as f32 * spacing;
+            let (x_color, z_color) = if i == 0 {
+                (Vec4::new(0.0, 0.0, 1.0, 1.0), Vec4::new(1.0, 0.0, 0.0, 1.0))
+            } else {
+                (color, color)
+            };
+            self.line(
+                Vec3::new(offset, 0.0, -extent),
+                Vec3::new(offset, 0.0, extent),
+                x_color,
+            );
+            self.line(
+                Vec3::new(-extent, 0.0, offset),
+                Vec3::new(extent, 0.0, offset),
+                z_color,
+            );
+        }
+    }
+
+    /// The local axes of `transform`, x red, y green and z blue, `size` long.
+    pub fn axes(&mut self, transform: &Mat4, size: f32) {
+        let origin = transform.transform_point3(Vec3::ZERO);
+        for (axis, color) in [
+            (Vec3::X, Vec4::new(1.0, 0.0, 0.0, 1.0)),
+            (Vec3::Y, Vec4::new(0.0, 1.0, 0.0, 1.0)),
+            (Vec3::Z, Vec4::new(0.0, 0.0, 1.0, 1.0)),
+        ] {
+            let end = origin + transform.transform_vector3(axis).normalize_or_zero() * size;
+            self.arrow(origin, end, color);
+        }
+    }
+
+    /// The volume seen by `camera`, from the near to the far plane.
+    pub fn frustum(&mut self, camera: &Camera, color: Vec4) {
+        let ndc_to_world = (camera.projection() * camera.view()).inverse();
+        // same order as `Aabb::corners`, clip space z goes from 0 to 1
+        let corners = std::array::from_fn(|i| {
+            let ndc = Vec3::new(
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { 0.0 } else { 1.0 },
+            );
+            ndc_to_world.project_point3(ndc)
+        });
+        self.box_corners(corners, color);
+    }
+
+    /// The normal of every vertex of `mesh` placed by `model`, `length` long.
+    pub fn normals(&mut self, mesh: &Mesh, model: &Mat4, length: f32, color: Vec4) {
+        let cof_mat = cofactor(model);
+        for vertex in mesh.vertices() {
+            let position = model.transform_point3(vertex.position.xyz());
+            let normal = (cof_mat * vertex.normal.extend(0.0))
+                .xyz()
+                .normalize_or_zero();
+            self.line(position, position + normal * length, color);
+        }
+    }
+
+    /// The bounds of every mesh of `model` as oriented boxes, and the box around the whole
+    /// model, once per instance or at the model transform when there are none.
+    pub fn model_bounds(&mut self, model: &Model, instances: &[Transform], color: Vec4) {
+        let local = model.transform.local();
+        let models: Vec<Mat4> = if instances.is_empty() {
+            vec![local]
+        } else {
+            instances.iter().map(|t| t.local() * local).collect()
+        };
+        let aabb = model.aabb();
+        let mesh_color = (color.xyz() * 0.5).extend(color.w);
+        for matrix in models {
+            if model.meshes.len() > 1 {
+                for mesh in &model.meshes {
+                    self.oriented_box(&mesh.aabb(), &matrix, mesh_color);
+                }
+            }
+            self.aabb(&aabb.transform(&matrix), color);
+        }
+    }
+
+    /// Renders and forgets the queued shapes, seen by `camera`. Lines hidden by geometry
+    /// test against `depth_buffer` and write into it.
+    pub fn draw(
+        &mut self,
+        buffer: &mut [u32],
+        depth_buffer: &mut [f32],
+        camera: &Camera,
+        viewport_size: Vec2,
+    ) {
+        let view_projection = camera.projection() * camera.view();
+        self.overlay_depth.clear();
+        self.overlay_depth.resize(depth_buffer.len(), f32::INFINITY);
+        for line in self.lines.drain(..) {
+            let vertex = |position: Vec3| {
+                Vertex::new(position.extend(1.0), Vec3::ZERO, line.color, Vec2::ZERO)
+            };
+            let depth_buffer = if line.depth_test {
+                &mut *depth_buffer
+            } else {
+                &mut self.overlay_depth
+            };
+            Line::new([vertex(line.start), vertex(line.end)]).draw(
+                buffer,
+                depth_buffer,
+                &view_projection,
+                self.style,
+                viewport_size,
+            );
+        }
+    }
+
+    // the 12 edges between corners in the order of `Aabb::corners`
+    fn box_corners(&mut self, corners: [Vec3; 8], color: Vec4) {
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corners[i], corners[i | bit], color);
+                }
+            }
+        }
+    }
+
+    fn circle(&mut self, center: Vec3, u: Vec3, v: Vec3, color: Vec4) {
+        let point = |i: usize| {
+            let (sin, cos) = (i as f32 / CIRCLE_SEGMENTS as f32 * TAU).sin_cos();
+            center + u * cos + v * sin
+        };
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), color);
+        }
+    }
+}
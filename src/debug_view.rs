@@ -0,0 +1,192 @@
+use crate::camera::Camera;
+use crate::gbuffer::{Attachment, GBuffer};
+use crate::postprocess::for_each_band;
+use crate::utils::to_argb8;
+use crate::ThreadPool;
+
+use glam::{Vec3, Vec4};
+
+// overdraw shown fully red from this many fragments
+const OVERDRAW_MAX: f32 = 8.0;
+
+/// What the shading stage shows. `Lit` is the usual image, the others replace it with
+/// one property of the visible surfaces, read from a `GBuffer`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DebugView {
+    #[default]
+    Lit,
+    /// World space normals, each axis mapped from [-1, 1] to [0, 1].
+    Normals,
+    /// Texture coordinates in red and green, repeating past 1.
+    Uvs,
+    /// Weight of each vertex of the triangle in red, green and blue.
+    Barycentrics,
+    /// Linear view depth, black at the near plane and white at the far one.
+    Depth,
+    /// Fragments rasterized per pixel, from blue to red.
+    Overdraw,
+    /// Mipmap level a texture lookup would use, a colour per level from blue (level 0).
+    MipLevel,
+    /// A colour per triangle, and per object and instance when the G-buffer has them.
+    TriangleIds,
+}
+
+impl DebugView {
+    pub const ALL: [DebugView; 8] = [
+        DebugView::Lit,
+        DebugView::Normals,
+        DebugView::Uvs,
+        DebugView::Barycentrics,
+        DebugView::Depth,
+        DebugView::Overdraw,
+        DebugView::MipLevel,
+        DebugView::TriangleIds,
+    ];
+
+    /// The view after this one in `DebugView::ALL`, wrapping around.
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|view| *view == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    /// The attachment the `GBuffer` needs for this view, depth coming from the depth buffer.
+    pub fn attachment(self) -> Option<Attachment> {
+        match self {
+            DebugView::Lit | DebugView::Depth => None,
+            DebugView::Normals => Some(Attachment::Normal),
+            DebugView::Uvs => Some(Attachment::Uv),
+            DebugView::Barycentrics => Some(Attachment::Barycentric),
+            DebugView::Overdraw => Some(Attachment::Overdraw),
+            DebugView::MipLevel => Some(Attachment::MipLevel),
+            DebugView::TriangleIds => Some(Attachment::TriangleId),
+        }
+    }
+
+    /// Replaces every pixel of `buffer` with the view, black where nothing was drawn.
+    /// Does nothing for `Lit`, or when `gbuffer` lacks the attachment.
+    pub fn render(
+        self,
+        thread_pool: &ThreadPool,
+        gbuffer: &GBuffer,
+        depth_buffer: &[f32],
+        camera: &Camera,
+        buffer: &mut [u32],
+    ) {
+        let has_attachment = match self {
+            DebugView::Lit => false,
+            DebugView::Depth => true,
+            DebugView::Normals => gbuffer.normals.is_some(),
+            DebugView::Uvs => gbuffer.uvs.is_some(),
+            DebugView::Barycentrics => gbuffer.barycentrics.is_some(),
+            DebugView::Overdraw => gbuffer.overdraw.is_some(),
+            DebugView::MipLevel => gbuffer.mip_levels.is_some(),
+            DebugView::TriangleIds => gbuffer.triangle_ids.is_some(),
+        };
+        if !has_attachment {
+            return;
+        }
+
+        let view_from_ndc = camera.projection().inverse();
+        let (near, far) = (camera.frustum_near, camera.frustum_far);
+        let width = gbuffer.width;
+        for_each_band(thread_pool, buffer, width, |first_row, band| {
+            let first = first_row * width;
+            for (i, pixel) in band.iter_mut().enumerate() {
+                let index = first + i;
+                let depth = depth_buffer[index];
+                // overdraw counts hidden fragments too, even where nothing is visible
+                if depth == f32::INFINITY && self != DebugView::Overdraw {
+                    *pixel = display(Vec3::ZERO);
+                    continue;
+                }
+
+                let color = match self {
+                    DebugView::Lit => continue,
+                    DebugView::Normals => gbuffer
+                        .normals
+                        .as_ref()
+                        .map(|normals| normals[index] * 0.5 + 0.5),
+                    DebugView::Uvs => gbuffer.uvs.as_ref().map(|uvs| {
+                        let uv = uvs[index];
+                        let repeated = uv - uv.floor();
+                        repeated.extend(0.0)
+                    }),
+                    DebugView::Barycentrics => {
+                        gbuffer.barycentrics.as_ref().map(|weights| weights[index])
+                    }
+                    DebugView::Depth => {
+                        // the w of the unprojected point is one over the view depth
+                        let view = view_from_ndc * Vec4::new(0.0, 0.0, depth, 1.0);
+                        let linear = (1.0 / view.w - near) / (far - near);
+                        Some(Vec3::splat(linear))
+                    }
+                    DebugView::Overdraw => gbuffer.overdraw.as_ref().map(|overdraw| {
+                        let count = overdraw[index];
+                        if count == 0 {
+                            Vec3::ZERO
+                        } else {
+                            ramp(&HEAT, (count - 1) as f32 / (OVERDRAW_MAX - 1.0))
+                        }
+                    }),
+                    DebugView::MipLevel => gbuffer
+                        .mip_levels
+                        .as_ref()
+                        .map(|levels| ramp(&LEVELS, levels[index] / (LEVELS.len() - 1) as f32)),
+                    DebugView::TriangleIds => gbuffer.triangle_ids.as_ref().map(|ids| {
+                        let object = gbuffer.object_ids.as_ref().map_or(0, |ids| ids[index]);
+                        let instance = gbuffer.instance_ids.as_ref().map_or(0, |ids| ids[index]);
+                        id_color(ids[index], object, instance)
+                    }),
+                };
+                if let Some(color) = color {
+                    *pixel = display(color);
+                }
+            }
+        });
+    }
+}
+
+const HEAT: [Vec3; 5] = [
+    Vec3::new(0.0, 0.0, 1.0),
+    Vec3::new(0.0, 1.0, 1.0),
+    Vec3::new(0.0, 1.0, 0.0),
+    Vec3::new(1.0, 1.0, 0.0),
+    Vec3::new(1.0, 0.0, 0.0),
+];
+
+const LEVELS: [Vec3; 8] = [
+    Vec3::new(0.0, 0.0, 1.0),
+    Vec3::new(0.0, 1.0, 1.0),
+    Vec3::new(0.0, 1.0, 0.0),
+    Vec3::new(1.0, 1.0, 0.0),
+    Vec3::new(1.0, 0.5, 0.0),
+    Vec3::new(1.0, 0.0, 0.0),
+    Vec3::new(1.0, 0.0, 1.0),
+    Vec3::new(1.0, 1.0, 1.0),
+];
+
+// `t` in [0, 1] along evenly spaced colours, clamped
+fn ramp(colors: &[Vec3], t: f32) -> Vec3 {
+    let position = t.clamp(0.0, 1.0) * (colors.len() - 1) as f32;
+    let low = (position as usize).min(colors.len() - 2);
+    colors[low].lerp(colors[low + 1], position - low as f32)
+}
+
+// a stable, well spread colour for each id
+fn id_color(triangle: u32, object: u32, instance: u32) -> Vec3 {
+    let mut hash = triangle
+        .wrapping_mul(0x9e37_79b9)
+        .wrapping_add(object.wrapping_mul(0x85eb_ca6b))
+        .wrapping_add(instance.wrapping_mul(0xc2b2_ae35));
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x7feb_352d);
+    hash ^= hash >> 15;
+    let channel = |shift: u32| 0.2 + 0.8 * ((hash >> shift) & 0xff) as f32 / 255.0;
+    Vec3::new(channel(0), channel(8), channel(16))
+}
+
+// the views are data, shown as is rather than encoded to sRGB
+fn display(color: Vec3) -> u32 {
+    let c = color.clamp(Vec3::ZERO, Vec3::ONE) * 255.0;
+    to_argb8(255, c.x as u8, c.y as u8, c.z as u8)
+}
@@ -0,0 +1,306 @@
+use crate::bounds::Aabb;
+use crate::camera::Camera;
+use crate::environment::Environment;
+use crate::gbuffer::GBuffer;
+use crate::material::Material;
+use crate::utils::{linear_to_argb8, map_to_range};
+use crate::ThreadPool;
+
+use glam::{Vec3, Vec4, Vec4Swizzles};
+use std::sync::Arc;
+
+/// Light shining in every direction from a point, fading out completely at `radius`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointLight {
+    pub position: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+    pub radius: f32,
+}
+
+impl PointLight {
+    pub fn new(position: Vec3, color: Vec3, intensity: f32, radius: f32) -> Self {
+        Self {
+            position,
+            color,
+            intensity,
+            radius,
+        }
+    }
+
+    // smooth falloff reaching exactly zero at the radius, so lights outside a tile can be skipped
+    fn attenuation(&self, distance: f32) -> f32 {
+        let ratio = (distance / self.radius).powi(4);
+        let window = (1.0 - ratio).max(0.0);
+        window * window / (distance * distance + 1.0)
+    }
+
+    // the sphere of influence touches the box
+    fn reaches(&self, aabb: &Aabb) -> bool {
+        let closest = self.position.clamp(aabb.min, aabb.max);
+        closest.distance_squared(self.position) <= self.radius * self.radius
+    }
+}
+
+/// Lighting pass of the deferred renderer: the scene is first drawn into a `GBuffer`
+/// (with `Model::draw_gbuffer`), then every covered pixel is lit once, no matter how
+/// much overdraw there was.
+///
+/// The screen is split in tiles, each only shaded by the lights reaching the world
+/// space box around its pixels, and rows of tiles run in parallel on a `ThreadPool`.
+#[derive(Debug, Clone)]
+pub struct DeferredShading {
+    pub lights: Vec<PointLight>,
+    pub ambient: Vec3,
+    /// Image based lighting replacing `ambient` when set.
+    pub environment: Option<Arc<Environment>>,
+    /// Width and height of the tiles in pixels.
+    pub tile_size: usize,
+}
+
+impl Default for DeferredShading {
+    fn default() -> Self {
+        Self {
+            lights: vec![],
+            ambient: Vec3::splat(0.2),
+            environment: None,
+            tile_size: 16,
+        }
+    }
+}
+
+/// Work done by one lighting pass.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LightingStats {
+    /// Tiles with geometry in them.
+    pub tiles: usize,
+    /// Sum over the tiles of the lights shading them.
+    pub light_tiles: usize,
+}
+
+// what a row of tiles needs to read, shared by every job
+struct Inputs<'a> {
+    shading: &'a DeferredShading,
+    gbuffer: &'a GBuffer,
+    normals: &'a [Vec3],
+    albedo: &'a [Vec4],
+    depth_buffer: &'a [f32],
+    camera_position: Vec3,
+}
+
+impl Inputs<'_> {
+    fn position(&self, index: usize) -> Vec3 {
+        if let Some(positions) = &self.gbuffer.positions {
+            return positions[index];
+        }
+        // rebuilt from the depth when there is no position attachment
+        let (width, height) = (self.gbuffer.width, self.gbuffer.height);
+        let (x, y) = (index % width, index / width);
+        let ndc = Vec3::new(
+            map_to_range(x as f32 + 0.5, 0.0, width as f32, -1.0, 1.0),
+            -map_to_range(y as f32 + 0.5, 0.0, height as f32, -1.0, 1.0),
+            self.depth_buffer[index],
+        );
+        self.gbuffer.view_projection().inverse().project_point3(ndc)
+    }
+
+    fn material(&self, index: usize) -> Material {
+        self.gbuffer
+            .materials
+            .as_ref()
+            .map_or(Material::default(), |materials| materials[index])
+    }
+
+    // Blinn-Phong, the roughness picking the exponent and the metalness tinting the highlight
+    fn shade(&self, index: usize, position: Vec3, lights: &[&PointLight]) -> Vec3 {
+        let normal = self.normals[index];
+        let albedo = self.albedo[index].xyz();
+        let material = self.material(index);
+        let to_camera = (self.camera_position - position).normalize_or_zero();
+
+        let roughness = material.roughness.clamp(0.05, 1.0);
+        let shininess = (2.0 / roughness.powi(4) - 2.0).max(1.0);
+        let normalization = (shininess + 8.0) / (8.0 * std::f32::consts::PI);
+        let diffuse = albedo * (1.0 - material.metallic);
+        let specular = Vec3::splat(0.04).lerp(albedo, material.metallic);
+
+        let occlusion = self
+            .gbuffer
+            .occlusion
+            .as_ref()
+            .map_or(1.0, |occlusion| occlusion[index]);
+        let ambient = match &self.shading.environment {
+            Some(environment) => {
+                // Schlick's Fresnel, fading out with roughness
+                let n_dot_v = normal.dot(to_camera).max(0.0);
+                let grazing = Vec3::splat((1.0 - roughness).max(specular.max_element()));
+                let fresnel = specular + (grazing - specular) * (1.0 - n_dot_v).powi(5);
+                let reflected = normal * (2.0 * normal.dot(to_camera)) - to_camera;
+                environment.irradiance(normal) * diffuse
+                    + environment.specular(reflected, roughness) * fresnel
+            }
+            None => self.shading.ambient * albedo,
+        };
+        let mut color = ambient * occlusion;
+        for light in lights {
+            let offset = light.position - position;
+            let distance = offset.length();
+            if distance >= light.radius {
+                continue;
+            }
+            let direction = offset / distance;
+            let n_dot_l = normal.dot(direction);
+            if n_dot_l <= 0.0 {
+                continue;
+            }
+
+            let half = (direction + to_camera).normalize_or_zero();
+            let highlight = normal.dot(half).max(0.0).powf(shininess) * normalization;
+            let radiance = light.color * light.intensity * light.attenuation(distance);
+            color += (diffuse + specular * highlight) * radiance * n_dot_l;
+        }
+        color
+    }
+
+    // lights one row of tiles, `buffer` holding just those rows of pixels
+    fn shade_rows<T>(
+        &self,
+        first_row: usize,
+        buffer: &mut [T],
+        output: fn(Vec3) -> T,
+    ) -> LightingStats {
+        let width = self.gbuffer.width;
+        let tile_size = self.shading.tile_size;
+        let rows = buffer.len() / width;
+        let mut stats = LightingStats::default();
+
+        let mut positions = vec![Vec3::ZERO; tile_size * tile_size];
+        for tile_x in (0..width).step_by(tile_size) {
+            let columns = tile_size.min(width - tile_x);
+            let pixel = |x: usize, y: usize| (first_row + y) * width + tile_x + x;
+            let covered = |index: usize| self.depth_buffer[index] < f32::INFINITY;
+
+            let mut aabb = Aabb::EMPTY;
+            for y in 0..rows {
+                for x in 0..columns {
+                    let index = pixel(x, y);
+                    if covered(index) {
+                        positions[y * tile_size + x] = self.position(index);
+                        aabb = aabb.extend(positions[y * tile_size + x]);
+                    }
+                }
+            }
+            if aabb.is_empty() {
+                continue;
+            }
+
+            let lights: Vec<&PointLight> = self
+                .shading
+                .lights
+                .iter()
+                .filter(|light| light.reaches(&aabb))
+                .collect();
+            stats.tiles += 1;
+            stats.light_tiles += lights.len();
+
+            for y in 0..rows {
+                for x in 0..columns {
+                    let index = pixel(x, y);
+                    if !covered(index) {
+                        continue;
+                    }
+                    let color = self.shade(index, positions[y * tile_size + x], &lights);
+                    buffer[y * width + tile_x + x] = output(color);
+                }
+            }
+        }
+
+        stats
+    }
+}
+
+impl DeferredShading {
+    /// Lights every pixel with geometry (a finite depth) into `buffer`, encoded to sRGB,
+    /// leaving the others.
+    ///
+    /// `gbuffer` needs the normal and albedo attachments, positions are rebuilt from
+    /// `depth_buffer` when it has none and materials default without that attachment.
+    /// The ambient light is scaled by the `Occlusion` attachment, see `Ssao::apply`.
+    pub fn shade(
+        &self,
+        thread_pool: &ThreadPool,
+        gbuffer: &GBuffer,
+        depth_buffer: &[f32],
+        camera: &Camera,
+        buffer: &mut [u32],
+    ) -> LightingStats {
+        self.shade_into(
+            thread_pool,
+            gbuffer,
+            depth_buffer,
+            camera,
+            buffer,
+            |color| linear_to_argb8(color.extend(1.0)),
+        )
+    }
+
+    /// Like `shade`, writing linear unclamped colours for a `PostProcess` chain instead.
+    pub fn shade_hdr(
+        &self,
+        thread_pool: &ThreadPool,
+        gbuffer: &GBuffer,
+        depth_buffer: &[f32],
+        camera: &Camera,
+        colors: &mut [Vec4],
+    ) -> LightingStats {
+        self.shade_into(
+            thread_pool,
+            gbuffer,
+            depth_buffer,
+            camera,
+            colors,
+            |color| color.extend(1.0),
+        )
+    }
+
+    fn shade_into<T: Send>(
+        &self,
+        thread_pool: &ThreadPool,
+        gbuffer: &GBuffer,
+        depth_buffer: &[f32],
+        camera: &Camera,
+        buffer: &mut [T],
+        output: fn(Vec3) -> T,
+    ) -> LightingStats {
+        let (Some(normals), Some(albedo)) = (&gbuffer.normals, &gbuffer.albedo) else {
+            return LightingStats::default();
+        };
+        let inputs = Inputs {
+            shading: self,
+            gbuffer,
+            normals,
+            albedo,
+            depth_buffer,
+            camera_position: camera.transform.translation,
+        };
+
+        let tile_size = self.tile_size.max(1);
+        let stats = std::sync::Mutex::new(LightingStats::default());
+        let jobs: Vec<Box<dyn FnOnce() + Send + '_>> = buffer
+            .chunks_mut(gbuffer.width * tile_size)
+            .enumerate()
+            .map(|(row, rows)| {
+                let (inputs, stats) = (&inputs, &stats);
+                Box::new(move || {
+                    let row_stats = inputs.shade_rows(row * tile_size, rows, output);
+                    let mut stats = stats.lock().unwrap();
+                    stats.tiles += row_stats.tiles;
+                    stats.light_tiles += row_stats.light_tiles;
+                }) as Box<dyn FnOnce() + Send + '_>
+            })
+            .collect();
+        thread_pool.execute_all(jobs);
+
+        stats.into_inner().unwrap()
+    }
+}
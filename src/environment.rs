@@ -0,0 +1,205 @@
+use crate::camera::Camera;
+use crate::cubemap::{irradiance, CubeMap};
+use crate::fog::{Fog, FogColor};
+use crate::postprocess::for_each_band;
+use crate::utils::{from_argb8, linear_to_argb8, map_to_range, srgb_to_linear};
+use crate::ThreadPool;
+
+use glam::{Vec3, Vec4, Vec4Swizzles};
+
+/// What surrounds the scene: a sky drawn behind everything, and the light it casts,
+/// used by `DeferredShading` in place of the flat ambient colour.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    /// Scales the light coming from the sky, not the sky itself.
+    pub intensity: f32,
+    /// Applied by `apply_fog`, `None` keeps the scene clear.
+    pub fog: Option<Fog>,
+    skybox: CubeMap,
+    // successively blurrier copies of the sky, picked by roughness
+    specular: Vec<CubeMap>,
+    irradiance: [Vec3; 9],
+}
+
+impl Environment {
+    /// Precomputes the diffuse and specular lighting of `skybox`.
+    pub fn new(skybox: CubeMap) -> Self {
+        let irradiance = skybox.irradiance_coefficients();
+        let mut specular = vec![skybox.clone()];
+        while let Some(level) = specular.last().and_then(|level| level.downsample()) {
+            specular.push(level);
+        }
+        Self {
+            intensity: 1.0,
+            fog: None,
+            skybox,
+            specular,
+            irradiance,
+        }
+    }
+
+    pub fn skybox(&self) -> &CubeMap {
+        &self.skybox
+    }
+
+    /// Light reaching a diffuse surface facing `normal`, to multiply by its albedo.
+    pub fn irradiance(&self, normal: Vec3) -> Vec3 {
+        irradiance(&self.irradiance, normal) * self.intensity
+    }
+
+    /// Light reflected towards `direction` by a surface of `roughness` in [0, 1], before
+    /// the Fresnel term.
+    pub fn specular(&self, direction: Vec3, roughness: f32) -> Vec3 {
+        let level = roughness.clamp(0.0, 1.0) * (self.specular.len() - 1) as f32;
+        let low = level.floor() as usize;
+        let high = (low + 1).min(self.specular.len() - 1);
+        let color = self.specular[low]
+            .sample(direction)
+            .lerp(self.specular[high].sample(direction), level - low as f32);
+        color * self.intensity
+    }
+
+    /// Draws the sky over every pixel without geometry (an infinite depth), encoded to sRGB.
+    pub fn draw_skybox(
+        &self,
+        thread_pool: &ThreadPool,
+        camera: &Camera,
+        depth_buffer: &[f32],
+        buffer: &mut [u32],
+        width: usize,
+    ) {
+        self.draw_skybox_into(thread_pool, camera, depth_buffer, buffer, width, |color| {
+            linear_to_argb8(color.extend(1.0))
+        });
+    }
+
+    /// Like `draw_skybox`, writing linear colours for a `PostProcess` chain instead.
+    pub fn draw_skybox_hdr(
+        &self,
+        thread_pool: &ThreadPool,
+        camera: &Camera,
+        depth_buffer: &[f32],
+        colors: &mut [Vec4],
+        width: usize,
+    ) {
+        self.draw_skybox_into(thread_pool, camera, depth_buffer, colors, width, |color| {
+            color.extend(1.0)
+        });
+    }
+
+    fn draw_skybox_into<T: Send>(
+        &self,
+        thread_pool: &ThreadPool,
+        camera: &Camera,
+        depth_buffer: &[f32],
+        buffer: &mut [T],
+        width: usize,
+        output: fn(Vec3) -> T,
+    ) {
+        let height = buffer.len() / width;
+        let ndc_to_world = (camera.projection() * camera.view()).inverse();
+        for_each_band(thread_pool, buffer, width, |first_row, band| {
+            for (i, pixel) in band.iter_mut().enumerate() {
+                let (x, y) = (i % width, first_row + i / width);
+                if depth_buffer[y * width + x] != f32::INFINITY {
+                    continue;
+                }
+                let ndc_x = map_to_range(x as f32 + 0.5, 0.0, width as f32, -1.0, 1.0);
+                let ndc_y = -map_to_range(y as f32 + 0.5, 0.0, height as f32, -1.0, 1.0);
+                let near = ndc_to_world.project_point3(Vec3::new(ndc_x, ndc_y, 0.0));
+                let far = ndc_to_world.project_point3(Vec3::new(ndc_x, ndc_y, 1.0));
+                *pixel = output(self.skybox.sample(far - near));
+            }
+        });
+    }
+
+    /// Blends the fog over every pixel with geometry in `buffer`, which holds sRGB colours.
+    /// Does nothing without `fog`.
+    pub fn apply_fog(
+        &self,
+        thread_pool: &ThreadPool,
+        camera: &Camera,
+        depth_buffer: &[f32],
+        buffer: &mut [u32],
+        width: usize,
+    ) {
+        self.apply_fog_into(
+            thread_pool,
+            camera,
+            depth_buffer,
+            buffer,
+            width,
+            |pixel, fog, factor| {
+                let (a, r, g, b) = from_argb8(*pixel);
+                let decode = |c: u8| srgb_to_linear(c as f32 / 255.0);
+                let color = Vec3::new(decode(r), decode(g), decode(b));
+                *pixel = linear_to_argb8(color.lerp(fog, factor).extend(a as f32 / 255.0));
+            },
+        );
+    }
+
+    /// Like `apply_fog`, over the linear colours of a `PostProcess` chain.
+    pub fn apply_fog_hdr(
+        &self,
+        thread_pool: &ThreadPool,
+        camera: &Camera,
+        depth_buffer: &[f32],
+        colors: &mut [Vec4],
+        width: usize,
+    ) {
+        self.apply_fog_into(
+            thread_pool,
+            camera,
+            depth_buffer,
+            colors,
+            width,
+            |color, fog, factor| {
+                *color = color.xyz().lerp(fog, factor).extend(color.w);
+            },
+        );
+    }
+
+    fn apply_fog_into<T: Send>(
+        &self,
+        thread_pool: &ThreadPool,
+        camera: &Camera,
+        depth_buffer: &[f32],
+        buffer: &mut [T],
+        width: usize,
+        blend: fn(&mut T, Vec3, f32),
+    ) {
+        let Some(fog) = &self.fog else {
+            return;
+        };
+        let height = buffer.len() / width;
+        let ndc_to_world = (camera.projection() * camera.view()).inverse();
+        let eye = camera.transform.translation;
+        for_each_band(thread_pool, buffer, width, |first_row, band| {
+            for (i, pixel) in band.iter_mut().enumerate() {
+                let (x, y) = (i % width, first_row + i / width);
+                let depth = depth_buffer[y * width + x];
+                if depth == f32::INFINITY {
+                    continue;
+                }
+                let ndc = Vec4::new(
+                    map_to_range(x as f32 + 0.5, 0.0, width as f32, -1.0, 1.0),
+                    -map_to_range(y as f32 + 0.5, 0.0, height as f32, -1.0, 1.0),
+                    depth,
+                    1.0,
+                );
+                // the w of the unprojected point is one over the view depth
+                let world = ndc_to_world * ndc;
+                let position = world.xyz() / world.w;
+                let factor = fog.factor(1.0 / world.w, position, eye, camera.frustum_far);
+                if factor <= 0.0 {
+                    continue;
+                }
+                let color = match fog.color {
+                    FogColor::Constant(color) => color,
+                    FogColor::Sky => self.skybox.sample(position - eye),
+                };
+                blend(pixel, color, factor);
+            }
+        });
+    }
+}
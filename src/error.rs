@@ -0,0 +1,77 @@
+use std::fmt;
+
+/// Everything that can go wrong while loading or validating assets.
+#[derive(Debug)]
+pub enum RusterizerError {
+    Io(std::io::Error),
+    /// The file was found but its contents could not be decoded.
+    Decode(String),
+    /// The asset could not be written out.
+    Encode(String),
+    /// Valid glTF that uses something the rasterizer cannot handle.
+    UnsupportedGltf(String),
+    /// A vertex attribute is absent, or has fewer values than there are vertices.
+    MissingAttribute(&'static str),
+    IndexOutOfRange {
+        triangle: usize,
+        index: u32,
+        vertex_count: usize,
+    },
+}
+
+impl fmt::Display for RusterizerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RusterizerError::Io(err) => write!(f, "I/O error: {err}"),
+            RusterizerError::Decode(message) => write!(f, "decode error: {message}"),
+            RusterizerError::Encode(message) => write!(f, "encode error: {message}"),
+            RusterizerError::UnsupportedGltf(message) => {
+                write!(f, "unsupported glTF feature: {message}")
+            }
+            RusterizerError::MissingAttribute(attribute) => {
+                write!(f, "missing vertex attribute {attribute}")
+            }
+            RusterizerError::IndexOutOfRange {
+                triangle,
+                index,
+                vertex_count,
+            } => write!(
+                f,
+                "triangle {triangle} references vertex {index} but the mesh has {vertex_count} vertices"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RusterizerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RusterizerError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for RusterizerError {
+    fn from(err: std::io::Error) -> Self {
+        RusterizerError::Io(err)
+    }
+}
+
+impl From<image::ImageError> for RusterizerError {
+    fn from(err: image::ImageError) -> Self {
+        match err {
+            image::ImageError::IoError(err) => RusterizerError::Io(err),
+            err => RusterizerError::Decode(err.to_string()),
+        }
+    }
+}
+
+impl From<gltf::Error> for RusterizerError {
+    fn from(err: gltf::Error) -> Self {
+        match err {
+            gltf::Error::Io(err) => RusterizerError::Io(err),
+            err => RusterizerError::Decode(err.to_string()),
+        }
+    }
+}
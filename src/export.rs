@@ -0,0 +1,502 @@
+use crate::error::RusterizerError;
+use crate::material::Material;
+use crate::mesh::Mesh;
+use crate::model::Model;
+use crate::texture::Texture;
+
+use gltf::json;
+use gltf::json::validation::{Checked::Valid, USize64};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// where the images referenced by the materials end up
+enum Images {
+    // PNG files next to the .gltf, named after it
+    Files { directory: PathBuf, stem: String },
+    // PNG data stored in the binary chunk of a .glb
+    Embedded,
+}
+
+struct Builder {
+    root: json::Root,
+    bin: Vec<u8>,
+    images: Images,
+    // image files to write once the document is complete
+    files: Vec<(PathBuf, Arc<Texture>)>,
+    // textures shared by several meshes are only exported once
+    textures: HashMap<*const Texture, json::Index<json::Texture>>,
+}
+
+fn encode_error(err: impl std::fmt::Display) -> RusterizerError {
+    RusterizerError::Encode(err.to_string())
+}
+
+fn to_image(texture: &Texture) -> Result<image::RgbaImage, RusterizerError> {
+    image::RgbaImage::from_raw(
+        texture.width as u32,
+        texture.height as u32,
+        texture.to_rgba8(),
+    )
+    .ok_or_else(|| encode_error("texture data does not match its size"))
+}
+
+impl Builder {
+    fn new(images: Images) -> Self {
+        Self {
+            root: json::Root::default(),
+            bin: vec![],
+            images,
+            files: vec![],
+            textures: HashMap::new(),
+        }
+    }
+
+    fn push_view(
+        &mut self,
+        bytes: &[u8],
+        target: Option<json::buffer::Target>,
+    ) -> json::Index<json::buffer::View> {
+        // accessors need their data aligned to the component size
+        while !self.bin.len().is_multiple_of(4) {
+            self.bin.push(0);
+        }
+        let offset = self.bin.len();
+        self.bin.extend_from_slice(bytes);
+
+        self.root.push(json::buffer::View {
+            buffer: json::Index::new(0),
+            byte_length: USize64::from(bytes.len()),
+            byte_offset: Some(USize64::from(offset)),
+            byte_stride: None,
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            target: target.map(Valid),
+        })
+    }
+
+    fn push_accessor(
+        &mut self,
+        bytes: &[u8],
+        count: usize,
+        component_type: json::accessor::ComponentType,
+        type_: json::accessor::Type,
+        bounds: Option<(Vec<f32>, Vec<f32>)>,
+    ) -> json::Index<json::Accessor> {
+        let target = match component_type {
+            json::accessor::ComponentType::U32 => json::buffer::Target::ElementArrayBuffer,
+            _ => json::buffer::Target::ArrayBuffer,
+        };
+        let view = self.push_view(bytes, Some(target));
+        let (min, max) = match bounds {
+            Some((min, max)) => (Some(json::Value::from(min)), Some(json::Value::from(max))),
+            None => (None, None),
+        };
+
+        self.root.push(json::Accessor {
+            buffer_view: Some(view),
+            byte_offset: None,
+            count: USize64::from(count),
+            component_type: Valid(json::accessor::GenericComponentType(component_type)),
+            extensions: Default::default(),
+            extras: Default::default(),
+            type_: Valid(type_),
+            min,
+            max,
+            name: None,
+            normalized: false,
+            sparse: None,
+        })
+    }
+
+    fn push_floats(
+        &mut self,
+        values: impl Iterator<Item = f32>,
+        count: usize,
+        type_: json::accessor::Type,
+    ) -> json::Index<json::Accessor> {
+        let bytes: Vec<u8> = values.flat_map(f32::to_le_bytes).collect();
+        self.push_accessor(
+            &bytes,
+            count,
+            json::accessor::ComponentType::F32,
+            type_,
+            None,
+        )
+    }
+
+    fn push_texture(
+        &mut self,
+        texture: &Arc<Texture>,
+    ) -> Result<json::Index<json::Texture>, RusterizerError> {
+        if let Some(index) = self.textures.get(&Arc::as_ptr(texture)) {
+            return Ok(*index);
+        }
+
+        let image = match &self.images {
+            Images::Files { directory, stem } => {
+                let name = format!("{stem}_{}.png", self.textures.len());
+                self.files
+                    .push((directory.join(&name), Arc::clone(texture)));
+                json::Image {
+                    buffer_view: None,
+                    mime_type: None,
+                    name: None,
+                    uri: Some(name),
+                    extensions: Default::default(),
+                    extras: Default::default(),
+                }
+            }
+            Images::Embedded => {
+                let mut png = std::io::Cursor::new(vec![]);
+                to_image(texture)?
+                    .write_to(&mut png, image::ImageOutputFormat::Png)
+                    .map_err(encode_error)?;
+                let view = self.push_view(png.get_ref(), None);
+                json::Image {
+                    buffer_view: Some(view),
+                    mime_type: Some(json::image::MimeType(String::from("image/png"))),
+                    name: None,
+                    uri: None,
+                    extensions: Default::default(),
+                    extras: Default::default(),
+                }
+            }
+        };
+        let image = self.root.push(image);
+
+        let index = self.root.push(json::Texture {
+            name: None,
+            sampler: None,
+            source: image,
+            extensions: Default::default(),
+            extras: Default::default(),
+        });
+        self.textures.insert(Arc::as_ptr(texture), index);
+
+        Ok(index)
+    }
+
+    fn push_mesh(&mut self, mesh: &Mesh) -> Result<json::Index<json::Mesh>, RusterizerError> {
+        use json::accessor::Type;
+
+        let vertices = mesh.vertices();
+        let count = vertices.len();
+
+        let mut min = vertices[0].position.truncate();
+        let mut max = min;
+        for vertex in vertices {
+            min = min.min(vertex.position.truncate());
+            max = max.max(vertex.position.truncate());
+        }
+        let positions: Vec<u8> = vertices
+            .iter()
+            .flat_map(|v| v.position.truncate().to_array())
+            .flat_map(f32::to_le_bytes)
+            .collect();
+        let positions = self.push_accessor(
+            &positions,
+            count,
+            json::accessor::ComponentType::F32,
+            Type::Vec3,
+            Some((min.to_array().to_vec(), max.to_array().to_vec())),
+        );
+
+        let normals = self.push_floats(
+            vertices.iter().flat_map(|v| v.normal.to_array()),
+            count,
+            Type::Vec3,
+        );
+        let uvs = self.push_floats(
+            vertices.iter().flat_map(|v| v.uv.to_array()),
+            count,
+            Type::Vec2,
+        );
+        let colors = self.push_floats(
+            vertices.iter().flat_map(|v| v.color.to_array()),
+            count,
+            Type::Vec4,
+        );
+
+        let indices: Vec<u8> = mesh
+            .triangles()
+            .iter()
+            .flat_map(|t| t.to_array())
+            .flat_map(u32::to_le_bytes)
+            .collect();
+        let indices = self.push_accessor(
+            &indices,
+            mesh.triangles().len() * 3,
+            json::accessor::ComponentType::U32,
+            Type::Scalar,
+            None,
+        );
+
+        let material = if mesh.texture().is_some() || *mesh.material() != Material::default() {
+            let mut material = json::Material::default();
+            let pbr = &mut material.pbr_metallic_roughness;
+            pbr.roughness_factor = json::material::StrengthFactor(mesh.material().roughness);
+            pbr.metallic_factor = json::material::StrengthFactor(mesh.material().metallic);
+            if let Some(texture) = mesh.texture() {
+                pbr.base_color_texture = Some(json::texture::Info {
+                    index: self.push_texture(texture)?,
+                    tex_coord: 0,
+                    extensions: Default::default(),
+                    extras: Default::default(),
+                });
+            }
+            Some(self.root.push(material))
+        } else {
+            None
+        };
+
+        let mut attributes = BTreeMap::new();
+        attributes.insert(Valid(json::mesh::Semantic::Positions), positions);
+        attributes.insert(Valid(json::mesh::Semantic::Normals), normals);
+        attributes.insert(Valid(json::mesh::Semantic::TexCoords(0)), uvs);
+        attributes.insert(Valid(json::mesh::Semantic::Colors(0)), colors);
+
+        Ok(self.root.push(json::Mesh {
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            primitives: vec![json::mesh::Primitive {
+                attributes,
+                extensions: Default::default(),
+                extras: Default::default(),
+                indices: Some(indices),
+                material,
+                mode: Valid(json::mesh::Mode::Triangles),
+                targets: None,
+            }],
+            weights: None,
+        }))
+    }
+
+    fn push_model(&mut self, model: &Model) -> Result<(), RusterizerError> {
+        let transform = model.transform;
+        let mut nodes = vec![];
+
+        // glTF does not allow empty meshes
+        for mesh in model
+            .meshes
+            .iter()
+            .filter(|m| !m.triangles().is_empty() && !m.vertices().is_empty())
+        {
+            let mesh = self.push_mesh(mesh)?;
+            nodes.push(self.root.push(json::Node {
+                mesh: Some(mesh),
+                translation: Some(transform.translation.to_array()),
+                rotation: Some(json::scene::UnitQuaternion(transform.rotation.to_array())),
+                scale: Some(transform.scale.to_array()),
+                ..Default::default()
+            }));
+        }
+
+        let scene = self.root.push(json::Scene {
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            nodes,
+        });
+        self.root.scene = Some(scene);
+
+        Ok(())
+    }
+
+    fn push_buffer(&mut self, uri: Option<String>) {
+        while !self.bin.len().is_multiple_of(4) {
+            self.bin.push(0);
+        }
+        if self.bin.is_empty() {
+            return;
+        }
+
+        self.root.push(json::Buffer {
+            byte_length: USize64::from(self.bin.len()),
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            uri,
+        });
+    }
+}
+
+impl Model {
+    /// Writes the model as a .gltf file, with the vertex data in a .bin file and the
+    /// textures as PNG files next to it.
+    ///
+    /// Every mesh becomes a root node carrying the model transform, loading the file
+    /// back with `Model::new` gives the same meshes and transform.
+    pub fn export_gltf(&self, file_path: &Path) -> Result<(), RusterizerError> {
+        let directory = file_path.parent().unwrap_or_else(|| Path::new(""));
+        let stem = file_path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("model")
+            .to_string();
+
+        let mut builder = Builder::new(Images::Files {
+            directory: directory.to_path_buf(),
+            stem: stem.clone(),
+        });
+        builder.push_model(self)?;
+
+        let bin_name = format!("{stem}.bin");
+        builder.push_buffer(Some(bin_name.clone()));
+
+        let json = json::serialize::to_string_pretty(&builder.root).map_err(encode_error)?;
+        std::fs::write(file_path, json)?;
+        if !builder.bin.is_empty() {
+            std::fs::write(directory.join(bin_name), &builder.bin)?;
+        }
+        for (path, texture) in &builder.files {
+            to_image(texture)?.save(path)?;
+        }
+
+        println!(
+            "Exported {}: {} meshes, {} textures",
+            file_path.display(),
+            builder.root.meshes.len(),
+            builder.root.textures.len()
+        );
+
+        Ok(())
+    }
+
+    /// Writes the model as a single binary .glb file, textures embedded as PNG.
+    pub fn export_glb(&self, file_path: &Path) -> Result<(), RusterizerError> {
+        let mut builder = Builder::new(Images::Embedded);
+        builder.push_model(self)?;
+        builder.push_buffer(None);
+
+        let json = json::serialize::to_vec(&builder.root).map_err(encode_error)?;
+        let glb = gltf::binary::Glb {
+            header: gltf::binary::Header {
+                magic: *b"glTF",
+                version: 2,
+                // recomputed by `to_vec`
+                length: 0,
+            },
+            json: Cow::Owned(json),
+            bin: (!builder.bin.is_empty()).then_some(Cow::Borrowed(builder.bin.as_slice())),
+        };
+        std::fs::write(file_path, glb.to_vec().map_err(encode_error)?)?;
+
+        println!(
+            "Exported {}: {} meshes, {} textures",
+            file_path.display(),
+            builder.root.meshes.len(),
+            builder.root.textures.len()
+        );
+
+        Ok(())
+    }
+
+    /// Writes the model, picking .glb or .gltf from the file extension.
+    pub fn export(&self, file_path: &Path) -> Result<(), RusterizerError> {
+        let extension = file_path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+
+        match extension.as_deref() {
+            Some("glb") => self.export_glb(file_path),
+            _ => self.export_gltf(file_path),
+        }
+    }
+}
+
+impl Mesh {
+    /// Writes the mesh as a .gltf file, see `Model::export_gltf`.
+    pub fn export_gltf(&self, file_path: &Path) -> Result<(), RusterizerError> {
+        Model::from_meshes(vec![self.clone()]).export_gltf(file_path)
+    }
+
+    /// Writes the mesh as a .glb file, see `Model::export_glb`.
+    pub fn export_glb(&self, file_path: &Path) -> Result<(), RusterizerError> {
+        Model::from_meshes(vec![self.clone()]).export_glb(file_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::Transform;
+
+    use glam::{Quat, Vec3, Vec4};
+
+    // a textured cube with per-vertex colours, placed away from the origin
+    fn textured_cube() -> Model {
+        let pixels = [
+            255, 0, 0, 255, 0, 255, 0, 255, //
+            0, 0, 255, 255, 255, 255, 255, 128,
+        ];
+        let mut mesh = Mesh::new_with_texture(Arc::new(Texture::from_rgba8(2, 2, &pixels)));
+
+        let cube = Mesh::new_cube(Vec3::new(1.0, 2.0, 3.0), 2);
+        let mut vertices = cube.vertices().clone();
+        for (i, vertex) in vertices.iter_mut().enumerate() {
+            let t = i as f32 / 8.0;
+            vertex.color = Vec4::new(t.fract(), 0.5, 1.0 - t.fract(), 1.0);
+        }
+        mesh.add_section_from_vertices(cube.triangles(), &vertices);
+
+        let mut model = Model::from_meshes(vec![mesh]);
+        model.transform = Transform::new(
+            Vec3::new(1.0, -2.0, 0.5),
+            Quat::from_rotation_y(0.7) * Quat::from_rotation_x(-0.3),
+            Vec3::new(2.0, 1.0, 0.5),
+        );
+        model
+    }
+
+    type Export = fn(&Model, &Path) -> Result<(), RusterizerError>;
+
+    fn assert_round_trip(model: &Model, file_name: &str, export: Export) {
+        let directory = std::env::temp_dir().join(format!("rusterizer_export_{file_name}"));
+        std::fs::create_dir_all(&directory).unwrap();
+        let file_path = directory.join(file_name);
+
+        export(model, &file_path).unwrap();
+        let loaded = Model::new(&file_path).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(loaded.meshes.len(), 1);
+        let (original, loaded_mesh) = (&model.meshes[0], &loaded.meshes[0]);
+        assert_eq!(loaded_mesh.triangles(), original.triangles());
+        assert_eq!(loaded_mesh.vertices().len(), original.vertices().len());
+        for (a, b) in original.vertices().iter().zip(loaded_mesh.vertices()) {
+            assert!(a.position.abs_diff_eq(b.position, 1e-6));
+            assert!(a.normal.abs_diff_eq(b.normal, 1e-6));
+            assert!(a.color.abs_diff_eq(b.color, 1e-6));
+            assert!(a.uv.abs_diff_eq(b.uv, 1e-6));
+        }
+        assert_eq!(
+            loaded_mesh.texture().map(|t| &t.data),
+            original.texture().map(|t| &t.data)
+        );
+
+        assert!(loaded
+            .transform
+            .translation
+            .abs_diff_eq(model.transform.translation, 1e-6));
+        assert!(loaded
+            .transform
+            .rotation
+            .abs_diff_eq(model.transform.rotation, 1e-6));
+        assert!(loaded
+            .transform
+            .scale
+            .abs_diff_eq(model.transform.scale, 1e-6));
+    }
+
+    #[test]
+    fn gltf_and_glb_round_trip() {
+        let model = textured_cube();
+        assert_round_trip(&model, "cube.gltf", Model::export_gltf);
+        assert_round_trip(&model, "cube.glb", Model::export_glb);
+    }
+}
@@ -0,0 +1,100 @@
+use glam::Vec3;
+
+/// How the fog thickens with the view depth.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FogMode {
+    /// None before `start`, opaque from `end` on.
+    Linear { start: f32, end: f32 },
+    /// `1 - e^(-density * depth)`.
+    Exponential { density: f32 },
+    /// `1 - e^(-(density * depth)²)`, clear longer then thickening quickly.
+    ExponentialSquared { density: f32 },
+}
+
+/// Fog lying low over the ground, thinning out exponentially above `base`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeightFog {
+    /// World height where the fog has its full `density`.
+    pub base: f32,
+    pub density: f32,
+    /// How quickly the density drops with height, per world unit.
+    pub falloff: f32,
+}
+
+/// Colour the fogged pixels tend to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FogColor {
+    Constant(Vec3),
+    /// The sky behind the pixel, so that fully fogged geometry blends into the skybox.
+    Sky,
+}
+
+/// Distance and height fog, set on an `Environment` and applied per pixel from the
+/// depth buffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fog {
+    pub mode: Option<FogMode>,
+    pub height: Option<HeightFog>,
+    pub color: FogColor,
+    /// Fraction of the far plane from which geometry fades completely into the fog,
+    /// hiding where it gets clipped. `None` leaves the far plane visible.
+    pub far_fade: Option<f32>,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self {
+            mode: Some(FogMode::Exponential { density: 0.02 }),
+            height: None,
+            color: FogColor::Sky,
+            far_fade: Some(0.8),
+        }
+    }
+}
+
+impl Fog {
+    /// How much of the fog colour covers a point at `position` and `view_depth`, seen
+    /// from `eye` with a far plane at `far`. 0 is clear, 1 is opaque.
+    pub fn factor(&self, view_depth: f32, position: Vec3, eye: Vec3, far: f32) -> f32 {
+        let distance = match self.mode {
+            Some(FogMode::Linear { start, end }) => {
+                ((view_depth - start) / (end - start).max(f32::EPSILON)).clamp(0.0, 1.0)
+            }
+            Some(FogMode::Exponential { density }) => 1.0 - (-density * view_depth).exp(),
+            Some(FogMode::ExponentialSquared { density }) => {
+                1.0 - (-(density * view_depth).powi(2)).exp()
+            }
+            None => 0.0,
+        };
+        let height = self
+            .height
+            .map_or(0.0, |height| height.factor(position, eye));
+        // both layers let through their own share of the light
+        let fog = 1.0 - (1.0 - distance) * (1.0 - height);
+
+        match self.far_fade {
+            Some(start) => {
+                let start = start.clamp(0.0, 1.0) * far;
+                let t = ((view_depth - start) / (far - start).max(f32::EPSILON)).clamp(0.0, 1.0);
+                fog.max(t * t * (3.0 - 2.0 * t))
+            }
+            None => fog,
+        }
+    }
+}
+
+impl HeightFog {
+    // density integrated along the ray from the eye to the point
+    fn factor(&self, position: Vec3, eye: Vec3) -> f32 {
+        let distance = position.distance(eye);
+        let rise = self.falloff * (position.y - eye.y);
+        let at_eye = self.density * (-self.falloff * (eye.y - self.base)).exp();
+        // (1 - e^-rise) / rise, which tends to 1 along horizontal rays
+        let spread = if rise.abs() < 1e-4 {
+            1.0
+        } else {
+            (1.0 - (-rise).exp()) / rise
+        };
+        (1.0 - (-at_eye * spread * distance).exp()).max(0.0)
+    }
+}
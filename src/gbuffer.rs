@@ -0,0 +1,373 @@
+use crate::bounds::Frustum;
+use crate::camera::Camera;
+use crate::lod::{triangle_count, LodStats};
+use crate::material::Material;
+use crate::model::Model;
+use crate::transform::Transform;
+use crate::utils::map_to_range;
+
+use glam::{Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
+
+/// Per pixel outputs the rasterizer can write next to the colour and depth buffers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Attachment {
+    ObjectId,
+    InstanceId,
+    Normal,
+    Albedo,
+    Position,
+    Motion,
+    Material,
+    /// The lit colour, in linear space and unclamped.
+    Color,
+    Occlusion,
+    Uv,
+    Barycentric,
+    TriangleId,
+    MipLevel,
+    Overdraw,
+}
+
+/// Extra render targets filled by the same rasterization pass as the colour buffer.
+/// Attachments left as `None` are not written and cost nothing.
+#[derive(Debug, Clone)]
+pub struct GBuffer {
+    pub width: usize,
+    pub height: usize,
+    /// Id given to the draw, 0 where nothing was drawn.
+    pub object_ids: Option<Vec<u32>>,
+    pub instance_ids: Option<Vec<u32>>,
+    /// World space, normalized.
+    pub normals: Option<Vec<Vec3>>,
+    /// Vertex colour times texture, before any lighting.
+    pub albedo: Option<Vec<Vec4>>,
+    /// World space.
+    pub positions: Option<Vec<Vec3>>,
+    /// Movement on screen since the previous frame, in pixels.
+    pub motion: Option<Vec<Vec2>>,
+    pub materials: Option<Vec<Material>>,
+    /// Linear, above 1 where lighting is brighter than white. See `PostProcess`.
+    pub colors: Option<Vec<Vec4>>,
+    /// Ambient occlusion, 1 where unoccluded. The rasterizer writes the baked occlusion
+    /// of the meshes, `Ssao::apply` multiplies the screen space term in.
+    pub occlusion: Option<Vec<f32>>,
+    /// Texture coordinates, interpolated like every other vertex attribute.
+    pub uvs: Option<Vec<Vec2>>,
+    /// Weights of the three vertices of the visible triangle, perspective corrected.
+    pub barycentrics: Option<Vec<Vec3>>,
+    /// Index of the visible triangle in its mesh, see `GBufferTarget::triangle_id`.
+    pub triangle_ids: Option<Vec<u32>>,
+    /// Mipmap level a texture lookup would use there, from the screen space derivatives
+    /// of the texture coordinates. 0 without a texture.
+    pub mip_levels: Option<Vec<f32>>,
+    /// Fragments rasterized at each pixel, hidden ones included.
+    pub overdraw: Option<Vec<u32>>,
+    view_projection: Mat4,
+    previous_view_projection: Mat4,
+    frames: usize,
+}
+
+impl GBuffer {
+    pub fn new(width: usize, height: usize, attachments: &[Attachment]) -> Self {
+        let size = width * height;
+        let has = |attachment| attachments.contains(&attachment);
+        Self {
+            width,
+            height,
+            object_ids: has(Attachment::ObjectId).then(|| vec![0; size]),
+            instance_ids: has(Attachment::InstanceId).then(|| vec![0; size]),
+            normals: has(Attachment::Normal).then(|| vec![Vec3::ZERO; size]),
+            albedo: has(Attachment::Albedo).then(|| vec![Vec4::ZERO; size]),
+            positions: has(Attachment::Position).then(|| vec![Vec3::ZERO; size]),
+            motion: has(Attachment::Motion).then(|| vec![Vec2::ZERO; size]),
+            materials: has(Attachment::Material).then(|| vec![Material::default(); size]),
+            colors: has(Attachment::Color).then(|| vec![Vec4::ZERO; size]),
+            occlusion: has(Attachment::Occlusion).then(|| vec![1.0; size]),
+            uvs: has(Attachment::Uv).then(|| vec![Vec2::ZERO; size]),
+            barycentrics: has(Attachment::Barycentric).then(|| vec![Vec3::ZERO; size]),
+            triangle_ids: has(Attachment::TriangleId).then(|| vec![0; size]),
+            mip_levels: has(Attachment::MipLevel).then(|| vec![0.0; size]),
+            overdraw: has(Attachment::Overdraw).then(|| vec![0; size]),
+            view_projection: Mat4::IDENTITY,
+            previous_view_projection: Mat4::IDENTITY,
+            frames: 0,
+        }
+    }
+
+    pub fn viewport_size(&self) -> Vec2 {
+        Vec2::new(self.width as f32, self.height as f32)
+    }
+
+    pub fn clear(&mut self) {
+        if let Some(ids) = &mut self.object_ids {
+            ids.fill(0);
+        }
+        if let Some(ids) = &mut self.instance_ids {
+            ids.fill(0);
+        }
+        if let Some(normals) = &mut self.normals {
+            normals.fill(Vec3::ZERO);
+        }
+        if let Some(albedo) = &mut self.albedo {
+            albedo.fill(Vec4::ZERO);
+        }
+        if let Some(positions) = &mut self.positions {
+            positions.fill(Vec3::ZERO);
+        }
+        if let Some(motion) = &mut self.motion {
+            motion.fill(Vec2::ZERO);
+        }
+        if let Some(materials) = &mut self.materials {
+            materials.fill(Material::default());
+        }
+        if let Some(colors) = &mut self.colors {
+            colors.fill(Vec4::ZERO);
+        }
+        if let Some(occlusion) = &mut self.occlusion {
+            occlusion.fill(1.0);
+        }
+        if let Some(uvs) = &mut self.uvs {
+            uvs.fill(Vec2::ZERO);
+        }
+        if let Some(barycentrics) = &mut self.barycentrics {
+            barycentrics.fill(Vec3::ZERO);
+        }
+        if let Some(ids) = &mut self.triangle_ids {
+            ids.fill(0);
+        }
+        if let Some(levels) = &mut self.mip_levels {
+            levels.fill(0.0);
+        }
+        if let Some(overdraw) = &mut self.overdraw {
+            overdraw.fill(0);
+        }
+    }
+
+    /// Clears the attachments and sets the camera of the frame about to be drawn, the
+    /// one of the frame before is kept for motion vectors.
+    pub fn begin_frame(&mut self, view_projection: Mat4) {
+        self.clear();
+        self.previous_view_projection = if self.frames == 0 {
+            view_projection
+        } else {
+            self.view_projection
+        };
+        self.view_projection = view_projection;
+        self.frames += 1;
+    }
+
+    pub fn view_projection(&self) -> Mat4 {
+        self.view_projection
+    }
+
+    pub fn previous_view_projection(&self) -> Mat4 {
+        self.previous_view_projection
+    }
+}
+
+/// What the rasterizer needs to fill a `GBuffer` for one draw.
+pub struct GBufferTarget<'a> {
+    pub gbuffer: &'a mut GBuffer,
+    pub object_id: u32,
+    pub instance_id: u32,
+    /// Set by every mesh drawn, see `Mesh::material`.
+    pub material: Material,
+    /// Set by meshes before each triangle they draw.
+    pub triangle_id: u32,
+    // normalized device coordinates to world space, and to the previous frame's clip space
+    world_from_ndc: Mat4,
+    reprojection: Mat4,
+}
+
+impl<'a> GBufferTarget<'a> {
+    /// `model` is the model matrix of the draw, `previous_model` the one of the frame before.
+    pub fn new(
+        gbuffer: &'a mut GBuffer,
+        object_id: u32,
+        instance_id: u32,
+        model: &Mat4,
+        previous_model: &Mat4,
+    ) -> Self {
+        let world_from_ndc = gbuffer.view_projection.inverse();
+        let reprojection =
+            gbuffer.previous_view_projection * *previous_model * model.inverse() * world_from_ndc;
+        Self {
+            gbuffer,
+            object_id,
+            instance_id,
+            material: Material::default(),
+            triangle_id: 0,
+            world_from_ndc,
+            reprojection,
+        }
+    }
+
+    /// Writes every attachment of pixel `index`, at `screen` (pixels) and `depth` (ndc z).
+    pub fn write(
+        &mut self,
+        index: usize,
+        screen: Vec2,
+        depth: f32,
+        normal: Vec3,
+        albedo: Vec4,
+        occlusion: f32,
+    ) {
+        let gbuffer = &mut *self.gbuffer;
+        let viewport_size = Vec2::new(gbuffer.width as f32, gbuffer.height as f32);
+
+        if let Some(ids) = &mut gbuffer.object_ids {
+            ids[index] = self.object_id;
+        }
+        if let Some(ids) = &mut gbuffer.instance_ids {
+            ids[index] = self.instance_id;
+        }
+        if let Some(normals) = &mut gbuffer.normals {
+            normals[index] = normal.normalize_or_zero();
+        }
+        if let Some(buffer) = &mut gbuffer.albedo {
+            buffer[index] = albedo;
+        }
+        if let Some(materials) = &mut gbuffer.materials {
+            materials[index] = self.material;
+        }
+        if let Some(buffer) = &mut gbuffer.occlusion {
+            buffer[index] = occlusion;
+        }
+        if gbuffer.positions.is_none() && gbuffer.motion.is_none() {
+            return;
+        }
+
+        let ndc = Vec3::new(
+            map_to_range(screen.x, 0.0, viewport_size.x, -1.0, 1.0),
+            -map_to_range(screen.y, 0.0, viewport_size.y, -1.0, 1.0),
+            depth,
+        );
+        if let Some(positions) = &mut gbuffer.positions {
+            positions[index] = self.world_from_ndc.project_point3(ndc);
+        }
+        if let Some(motion) = &mut gbuffer.motion {
+            let previous = self.reprojection * ndc.extend(1.0);
+            let previous = previous.xy() / previous.w;
+            let previous = Vec2::new(
+                map_to_range(previous.x, -1.0, 1.0, 0.0, viewport_size.x),
+                map_to_range(-previous.y, -1.0, 1.0, 0.0, viewport_size.y),
+            );
+            motion[index] = screen - previous;
+        }
+    }
+}
+
+impl GBufferTarget<'_> {
+    /// Stores the shaded colour of pixel `index`, written after lighting unlike the rest.
+    pub fn write_color(&mut self, index: usize, color: Vec4) {
+        if let Some(colors) = &mut self.gbuffer.colors {
+            colors[index] = color;
+        }
+    }
+
+    /// Writes the attachments describing the rasterization itself rather than the surface.
+    pub fn write_raster(&mut self, index: usize, uv: Vec2, barycentric: Vec3, mip_level: f32) {
+        let gbuffer = &mut *self.gbuffer;
+        if let Some(uvs) = &mut gbuffer.uvs {
+            uvs[index] = uv;
+        }
+        if let Some(barycentrics) = &mut gbuffer.barycentrics {
+            barycentrics[index] = barycentric;
+        }
+        if let Some(ids) = &mut gbuffer.triangle_ids {
+            ids[index] = self.triangle_id;
+        }
+        if let Some(levels) = &mut gbuffer.mip_levels {
+            levels[index] = mip_level;
+        }
+    }
+
+    /// Counts a fragment at pixel `index`, before the depth test.
+    pub fn count_fragment(&mut self, index: usize) {
+        if let Some(overdraw) = &mut self.gbuffer.overdraw {
+            overdraw[index] += 1;
+        }
+    }
+
+    /// Whether the rasterizer needs to work out mipmap levels.
+    pub fn needs_mip_level(&self) -> bool {
+        self.gbuffer.mip_levels.is_some()
+    }
+}
+
+impl Model {
+    /// Draws the model like `draw_instanced`, or like `draw` when there are no instances,
+    /// also filling `gbuffer` with `object_id` and the instance indices.
+    ///
+    /// `previous_instances` are the transforms of the frame before, one per instance, so
+    /// moving instances get motion vectors. Call `GBuffer::begin_frame` first.
+    ///
+    /// With a `camera`, instances outside of its view are skipped and far away ones use
+    /// the LODs, like `draw_instanced_lod`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_gbuffer(
+        &self,
+        buffer: &mut [u32],
+        depth_buffer: &mut [f32],
+        gbuffer: &mut GBuffer,
+        object_id: u32,
+        instances: &[Transform],
+        previous_instances: Option<&[Transform]>,
+        camera: Option<&Camera>,
+    ) -> LodStats {
+        let local = self.transform.local();
+        let models: Vec<(Mat4, Mat4)> = if instances.is_empty() {
+            vec![(local, local)]
+        } else {
+            instances
+                .iter()
+                .enumerate()
+                .map(|(i, instance)| {
+                    let previous = previous_instances
+                        .and_then(|p| p.get(i))
+                        .unwrap_or(instance);
+                    (instance.local() * local, previous.local() * local)
+                })
+                .collect()
+        };
+
+        let view_projection = gbuffer.view_projection();
+        let viewport_size = gbuffer.viewport_size();
+        let aabb = self.aabb();
+        let full_triangles = triangle_count(&self.meshes);
+        let mut stats = LodStats::default();
+        for (instance, (model, previous_model)) in models.iter().enumerate() {
+            let mvp = view_projection * *model;
+            let level = match camera {
+                Some(camera) => {
+                    if !Frustum::from_matrix(&mvp).intersects_aabb(&aabb) {
+                        continue;
+                    }
+                    self.select_lod(camera, model)
+                }
+                None => 0,
+            };
+            let meshes = self.lod_meshes(level);
+            let triangles = triangle_count(meshes);
+            stats.triangles_drawn += triangles;
+            stats.triangles_saved += full_triangles.saturating_sub(triangles);
+
+            let mut target =
+                GBufferTarget::new(gbuffer, object_id, instance as u32, model, previous_model);
+            for (i, mesh) in meshes.iter().enumerate() {
+                mesh.draw_with_target(
+                    self.weights.get(i).unwrap_or(mesh.weights()),
+                    self.polygon_mode,
+                    buffer,
+                    depth_buffer,
+                    model,
+                    &mvp,
+                    Some(&mut target),
+                    viewport_size,
+                );
+            }
+        }
+
+        stats
+    }
+}
@@ -1,3 +1,6 @@
+use crate::bounds::Aabb;
+use crate::gbuffer::GBufferTarget;
+use crate::oit::OitBuffer;
 use crate::texture::*;
 use crate::utils::*;
 use glam::{Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
@@ -37,11 +40,11 @@ impl AABB {
             f32::max(f32::max(v0.position.y, v1.position.y), v2.position.y),
             v3.position.y,
         );
-        let xmin = f32::max(
+        let xmin = f32::min(
             f32::min(f32::min(v0.position.x, v1.position.x), v2.position.x),
             v3.position.x,
         );
-        let ymin = f32::max(
+        let ymin = f32::min(
             f32::min(f32::min(v0.position.y, v1.position.y), v2.position.y),
             v3.position.y,
         );
@@ -172,6 +175,8 @@ impl MulAssign<f32> for Vertex {
 pub struct Triangle {
     pub vertices: [Vertex; 3],
     pub texture: Option<Arc<Texture>>,
+    /// Baked ambient occlusion, in the red channel.
+    pub occlusion: Option<Arc<Texture>>,
 }
 
 pub enum VerticesOrder {
@@ -188,6 +193,7 @@ impl Triangle {
         Triangle {
             vertices,
             texture: None,
+            occlusion: None,
         }
     }
 
@@ -195,9 +201,15 @@ impl Triangle {
         Triangle {
             vertices,
             texture: Some(texture),
+            occlusion: None,
         }
     }
 
+    pub fn with_occlusion(mut self, occlusion: Option<Arc<Texture>>) -> Self {
+        self.occlusion = occlusion;
+        self
+    }
+
     pub fn transform(&self, matrix: &Mat4) -> Self {
         let p0 = *matrix * self.vertices[0].position.xyz().extend(1.0);
         let p1 = *matrix * self.vertices[1].position.xyz().extend(1.0);
@@ -212,63 +224,33 @@ impl Triangle {
     }
 
     pub fn reorder(&self, order: VerticesOrder) -> Self {
-        match order {
-            VerticesOrder::ABC => self.clone(),
-            VerticesOrder::ACB => {
-                if self.texture.is_some() {
-                    Self::new_with_texture(
-                        [self.vertices[0], self.vertices[2], self.vertices[1]],
-                        self.texture.clone().unwrap(),
-                    )
-                } else {
-                    Self::new([self.vertices[0], self.vertices[2], self.vertices[1]])
-                }
-            }
-
-            VerticesOrder::BAC => {
-                if self.texture.is_some() {
-                    Self::new_with_texture(
-                        [self.vertices[1], self.vertices[0], self.vertices[2]],
-                        self.texture.clone().unwrap(),
-                    )
-                } else {
-                    Self::new([self.vertices[1], self.vertices[0], self.vertices[2]])
-                }
-            }
-            VerticesOrder::BCA => {
-                if self.texture.is_some() {
-                    Self::new_with_texture(
-                        [self.vertices[1], self.vertices[2], self.vertices[0]],
-                        self.texture.clone().unwrap(),
-                    )
-                } else {
-                    Self::new([self.vertices[1], self.vertices[2], self.vertices[0]])
-                }
-            }
-            VerticesOrder::CAB => {
-                if self.texture.is_some() {
-                    Self::new_with_texture(
-                        [self.vertices[2], self.vertices[0], self.vertices[1]],
-                        self.texture.clone().unwrap(),
-                    )
-                } else {
-                    Self::new([self.vertices[2], self.vertices[0], self.vertices[1]])
-                }
-            }
-            VerticesOrder::CBA => {
-                if self.texture.is_some() {
-                    Self::new_with_texture(
-                        [self.vertices[2], self.vertices[1], self.vertices[0]],
-                        self.texture.clone().unwrap(),
-                    )
-                } else {
-                    Self::new([self.vertices[2], self.vertices[1], self.vertices[0]])
-                }
-            }
+        let [a, b, c] = self.vertices;
+        let vertices = match order {
+            VerticesOrder::ABC => [a, b, c],
+            VerticesOrder::ACB => [a, c, b],
+            VerticesOrder::BAC => [b, a, c],
+            VerticesOrder::BCA => [b, c, a],
+            VerticesOrder::CAB => [c, a, b],
+            VerticesOrder::CBA => [c, b, a],
+        };
+        Triangle {
+            vertices,
+            ..self.clone()
         }
     }
 
     pub fn draw_clipped(&self, buffer: &mut [u32], depth_buffer: &mut [f32], viewport_size: Vec2) {
+        self.draw_clipped_to(buffer, depth_buffer, None, viewport_size);
+    }
+
+    /// Same as `draw_clipped`, also filling the G-buffer attachments of `target`.
+    pub fn draw_clipped_to(
+        &self,
+        buffer: &mut [u32],
+        depth_buffer: &mut [f32],
+        mut target: Option<&mut GBufferTarget>,
+        viewport_size: Vec2,
+    ) {
         let rec0 = 1.0 / self.vertices[0].position.w;
         let rec1 = 1.0 / self.vertices[1].position.w;
         let rec2 = 1.0 / self.vertices[2].position.w;
@@ -310,37 +292,39 @@ impl Triangle {
                         let correction = bary.x * rec0 + bary.y * rec1 + bary.z * rec2;
                         let depth = bary.x * ndc0.z + bary.y * ndc1.z + bary.z * ndc2.z;
                         let correction = 1.0 / correction;
+                        if let Some(target) = target.as_deref_mut() {
+                            target.count_fragment(pixel_id);
+                        }
 
                         if depth < depth_buffer[pixel_id] {
                             depth_buffer[pixel_id] = depth;
 
-                            let normal =
-                                bary.x * v0.normal + bary.y * v1.normal + bary.z * v2.normal;
-                            let normal = normal * correction;
-                            let n_dot_1 = normal.dot(Vec3::ONE.normalize());
-
-                            let color = bary.x * v0.color + bary.y * v1.color + bary.z * v2.color;
-                            let mut color = color * correction;
-
-                            if let Some(tex) = &self.texture {
-                                let tex_coords = bary.x * v0.uv + bary.y * v1.uv + bary.z * v2.uv;
-                                let tex_coords = tex_coords * correction;
-                                color = tex
-                                    .argb_at_uvf(tex_coords.x, tex_coords.y)
-                                    .yzw()
-                                    .extend(1.0);
+                            let (color, normal, occlusion) =
+                                self.surface(&[v0, v1, v2], bary, correction);
+
+                            if let Some(target) = target.as_deref_mut() {
+                                target.write(pixel_id, coords, depth, normal, color, occlusion);
+
+                                let uv =
+                                    (bary.x * v0.uv + bary.y * v1.uv + bary.z * v2.uv) * correction;
+                                let rec = Vec3::new(rec0, rec1, rec2);
+                                let weights = bary * rec * correction;
+                                let mip_level = if target.needs_mip_level() {
+                                    let sc = [sc0, sc1, sc2];
+                                    self.mip_level(&[v0, v1, v2], rec, sc, area, coords)
+                                } else {
+                                    0.0
+                                };
+                                target.write_raster(pixel_id, uv, weights, mip_level);
                             }
 
-                            let ambient = glam::vec4(0.2, 0.2, 0.2, 1.0);
-
-                            color = color * n_dot_1 + ambient;
+                            let color = lit(color, normal, occlusion).extend(1.0);
+                            if let Some(target) = target.as_deref_mut() {
+                                target.write_color(pixel_id, color);
+                            }
 
-                            let out_color = to_argb8(
-                                255,
-                                (color.x * 255.0) as u8,
-                                (color.y * 255.0) as u8,
-                                (color.z * 255.0) as u8,
-                            );
+                            // encoded to sRGB for display
+                            let out_color = linear_to_argb8(color);
 
                             buffer[pixel_id] = out_color;
                         }
@@ -351,13 +335,202 @@ impl Triangle {
     }
 }
 
-impl Object for Triangle {
-    fn draw(
+impl Triangle {
+    // interpolated colour, normal and baked occlusion of a fragment, from vertices already
+    // divided by w. Vertex colours (and instance tints) modulate the texture, alpha included
+    fn surface(&self, v: &[Vertex; 3], bary: Vec3, correction: f32) -> (Vec4, Vec3, f32) {
+        let normal =
+            (bary.x * v[0].normal + bary.y * v[1].normal + bary.z * v[2].normal) * correction;
+        let mut color =
+            (bary.x * v[0].color + bary.y * v[1].color + bary.z * v[2].color) * correction;
+        let uv = (bary.x * v[0].uv + bary.y * v[1].uv + bary.z * v[2].uv) * correction;
+
+        if let Some(tex) = &self.texture {
+            color *= tex.color_at_uv(uv.x, uv.y);
+        }
+        let occlusion = self
+            .occlusion
+            .as_ref()
+            .map_or(1.0, |occlusion| occlusion.color_at_uv(uv.x, uv.y).x);
+        (color, normal, occlusion)
+    }
+}
+
+impl Triangle {
+    // level of detail of the texture at `coords`, from how far the texture coordinates
+    // move to the neighbouring pixels. Vertices are divided by their w, `rec` holds 1 / w
+    fn mip_level(&self, v: &[Vertex; 3], rec: Vec3, sc: [Vec2; 3], area: f32, coords: Vec2) -> f32 {
+        let Some(texture) = &self.texture else {
+            return 0.0;
+        };
+        // the weights keep going outside of the triangle, unlike `barycentric_coordinates`
+        let uv_at = |point: Vec2| {
+            let bary = Vec3::new(
+                edge_fn(sc[1], sc[2], point),
+                edge_fn(sc[2], sc[0], point),
+                edge_fn(sc[0], sc[1], point),
+            ) / area;
+            (bary.x * v[0].uv + bary.y * v[1].uv + bary.z * v[2].uv) / bary.dot(rec)
+        };
+        let size = Vec2::new(texture.width as f32, texture.height as f32);
+        let uv = uv_at(coords);
+        let dx = (uv_at(coords + Vec2::X) - uv) * size;
+        let dy = (uv_at(coords + Vec2::Y) - uv) * size;
+        dx.length()
+            .max(dy.length())
+            .max(f32::MIN_POSITIVE)
+            .log2()
+            .max(0.0)
+    }
+}
+
+// ambient light of the forward shading, scaled by the baked occlusion
+pub(crate) const FORWARD_AMBIENT: f32 = 0.2;
+
+// the forward lighting, in linear space
+fn lit(color: Vec4, normal: Vec3, occlusion: f32) -> Vec3 {
+    let n_dot_1 = normal.dot(Vec3::ONE.normalize());
+    color.xyz() * n_dot_1 + Vec3::splat(FORWARD_AMBIENT) * occlusion
+}
+
+impl Triangle {
+    /// Transforms, clips and rasterizes the triangle into `oit`, to be blended over the
+    /// opaque geometry in any order. Fragments behind `depth_buffer` are hidden, nothing
+    /// is written to it.
+    pub fn draw_transparent(
         &self,
-        buffer: &mut Vec<u32>,
-        depth_buffer: &mut Vec<f32>,
+        oit: &mut OitBuffer,
+        depth_buffer: &[f32],
         model: &Mat4,
         mvp: &Mat4,
+    ) {
+        let cof_mat = cofactor(model);
+        let mut clip_triangle = self.transform(mvp);
+        for vertex in &mut clip_triangle.vertices {
+            vertex.normal = (cof_mat * vertex.normal.extend(0.0)).xyz();
+        }
+
+        match clip_cull_triangle(&clip_triangle) {
+            ClipResult::None => {}
+            ClipResult::One(tri) => {
+                tri.draw_clipped_transparent(oit, depth_buffer);
+            }
+            ClipResult::Two(tri) => {
+                tri.0.draw_clipped_transparent(oit, depth_buffer);
+                tri.1.draw_clipped_transparent(oit, depth_buffer);
+            }
+        }
+    }
+
+    /// Same coverage and shading as `draw_clipped`, accumulated into `oit`.
+    pub fn draw_clipped_transparent(&self, oit: &mut OitBuffer, depth_buffer: &[f32]) {
+        let viewport_size = oit.viewport_size();
+        let rec = self.vertices.map(|v| 1.0 / v.position.w);
+        let ndc = [0, 1, 2].map(|i| self.vertices[i].position * rec[i]);
+        let v = [0, 1, 2].map(|i| self.vertices[i] * rec[i]);
+        let sc = ndc.map(|ndc| {
+            glam::vec2(
+                map_to_range(ndc.x, -1.0, 1.0, 0.0, viewport_size.x),
+                map_to_range(-ndc.y, -1.0, 1.0, 0.0, viewport_size.y),
+            )
+        });
+
+        let Some(bounding_box) = triangle_screen_bounding_box(&sc, viewport_size) else {
+            return;
+        };
+        let area = edge_fn(sc[0], sc[1], sc[2]);
+
+        for x in (bounding_box.min.x as usize)..=bounding_box.max.x as usize {
+            for y in (bounding_box.min.y as usize)..=bounding_box.max.y as usize {
+                let coords = glam::vec2(x as f32, y as f32) + 0.5;
+                let pixel_id = coords_to_index(x, y, viewport_size.x as usize);
+
+                if let Some(bary) = barycentric_coordinates(coords, sc[0], sc[1], sc[2], area) {
+                    let depth = bary.x * ndc[0].z + bary.y * ndc[1].z + bary.z * ndc[2].z;
+                    if depth >= depth_buffer[pixel_id] {
+                        continue;
+                    }
+                    // the perspective correction is the interpolated w, the view depth
+                    let correction = 1.0 / (bary.x * rec[0] + bary.y * rec[1] + bary.z * rec[2]);
+                    let (color, normal, occlusion) = self.surface(&v, bary, correction);
+                    let color = lit(color, normal, occlusion).extend(color.w);
+                    oit.add(pixel_id, color, correction);
+                }
+            }
+        }
+    }
+}
+
+impl Triangle {
+    /// Transforms, clips and rasterizes the triangle into an id buffer instead of a colour
+    /// buffer, `id` is written wherever it passes the depth test.
+    pub fn draw_id(
+        &self,
+        ids: &mut [u32],
+        depth_buffer: &mut [f32],
+        mvp: &Mat4,
+        id: u32,
+        viewport_size: Vec2,
+    ) {
+        match clip_cull_triangle(&self.transform(mvp)) {
+            ClipResult::None => {}
+            ClipResult::One(tri) => {
+                tri.draw_clipped_id(ids, depth_buffer, id, viewport_size);
+            }
+            ClipResult::Two(tri) => {
+                tri.0.draw_clipped_id(ids, depth_buffer, id, viewport_size);
+                tri.1.draw_clipped_id(ids, depth_buffer, id, viewport_size);
+            }
+        }
+    }
+
+    /// Same coverage and depth as `draw_clipped`, without any shading.
+    pub fn draw_clipped_id(
+        &self,
+        ids: &mut [u32],
+        depth_buffer: &mut [f32],
+        id: u32,
+        viewport_size: Vec2,
+    ) {
+        let ndc = self.vertices.map(|v| v.position / v.position.w);
+        let sc = ndc.map(|ndc| {
+            glam::vec2(
+                map_to_range(ndc.x, -1.0, 1.0, 0.0, viewport_size.x),
+                map_to_range(-ndc.y, -1.0, 1.0, 0.0, viewport_size.y),
+            )
+        });
+
+        let Some(bounding_box) = triangle_screen_bounding_box(&sc, viewport_size) else {
+            return;
+        };
+        let area = edge_fn(sc[0], sc[1], sc[2]);
+
+        for x in (bounding_box.min.x as usize)..=bounding_box.max.x as usize {
+            for y in (bounding_box.min.y as usize)..=bounding_box.max.y as usize {
+                let coords = glam::vec2(x as f32, y as f32) + 0.5;
+                let pixel_id = coords_to_index(x, y, viewport_size.x as usize);
+
+                if let Some(bary) = barycentric_coordinates(coords, sc[0], sc[1], sc[2], area) {
+                    let depth = bary.x * ndc[0].z + bary.y * ndc[1].z + bary.z * ndc[2].z;
+                    if depth < depth_buffer[pixel_id] {
+                        depth_buffer[pixel_id] = depth;
+                        ids[pixel_id] = id;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Triangle {
+    /// Same as `Object::draw`, also filling the G-buffer attachments of `target`.
+    pub fn draw_to(
+        &self,
+        buffer: &mut [u32],
+        depth_buffer: &mut [f32],
+        model: &Mat4,
+        mvp: &Mat4,
+        mut target: Option<&mut GBufferTarget>,
         viewport_size: Vec2,
     ) {
         let cof_mat = cofactor(model);
@@ -373,14 +546,29 @@ impl Object for Triangle {
         match clip_cull_triangle(&clip_triangle) {
             ClipResult::None => {}
             ClipResult::One(tri) => {
-                tri.draw_clipped(buffer, depth_buffer, viewport_size);
+                tri.draw_clipped_to(buffer, depth_buffer, target, viewport_size);
             }
             ClipResult::Two(tri) => {
-                tri.0.draw_clipped(buffer, depth_buffer, viewport_size);
-                tri.1.draw_clipped(buffer, depth_buffer, viewport_size);
+                tri.0
+                    .draw_clipped_to(buffer, depth_buffer, target.as_deref_mut(), viewport_size);
+                tri.1
+                    .draw_clipped_to(buffer, depth_buffer, target, viewport_size);
             }
         }
     }
+}
+
+impl Object for Triangle {
+    fn draw(
+        &self,
+        buffer: &mut Vec<u32>,
+        depth_buffer: &mut Vec<f32>,
+        model: &Mat4,
+        mvp: &Mat4,
+        viewport_size: Vec2,
+    ) {
+        self.draw_to(buffer, depth_buffer, model, mvp, None, viewport_size);
+    }
 
     fn get_area(&self) -> f32 {
         edge_fn(
@@ -391,6 +579,9 @@ impl Object for Triangle {
     }
 }
 
+// only ever a short lived return value, boxing `Two` would allocate for every clipped
+// triangle just to shrink it
+#[allow(clippy::large_enum_variant)]
 pub enum ClipResult {
     None,
     One(Triangle),
@@ -470,22 +661,16 @@ pub fn clip_triangle_one(triangle: &Triangle) -> Triangle {
         / (triangle.vertices[2].position.z - triangle.vertices[1].position.z);
 
     // interpolate to get v0a and v0b
-    let mut v0 = lerp(triangle.vertices[0], triangle.vertices[2], alpha_a);
-    let mut v1 = lerp(triangle.vertices[1], triangle.vertices[2], alpha_b);
-
-    let mut v2 = triangle.vertices[2];
+    let v0 = lerp(triangle.vertices[0], triangle.vertices[2], alpha_a);
+    let v1 = lerp(triangle.vertices[1], triangle.vertices[2], alpha_b);
 
-    let red = Vec3::new(1.0, 0.0, 0.0);
-
-    v0.color = Vec4::new(red.x, red.y, red.z, 1.0);
-    v1.color = Vec4::new(red.x, red.y, red.z, 1.0);
-    v2.color = Vec4::new(red.x, red.y, red.z, 1.0);
+    let v2 = triangle.vertices[2];
 
     //println!("out tri: {:?}, {:?}, {:?},", v0, v1, v2);
     // draw triangles
     Triangle {
         vertices: [v0, v1, v2],
-        texture: triangle.texture.clone(),
+        ..triangle.clone()
     }
 }
 
@@ -577,21 +762,15 @@ impl Object for Quad {
             self.vertices[self.indices[5] as usize],
         ];
 
-        if self.texture.is_none() {
-            let triangle1 = Triangle::new(triangle_vertices1);
-            let triangle2 = Triangle::new(triangle_vertices2);
+        if let Some(texture) = &self.texture {
+            let triangle1 = Triangle::new_with_texture(triangle_vertices1, texture.clone());
+            let triangle2 = Triangle::new_with_texture(triangle_vertices2, texture.clone());
 
             triangle1.draw(buffer, depth_buffer, model, mvp, viewport_size);
             triangle2.draw(buffer, depth_buffer, model, mvp, viewport_size);
         } else {
-            let triangle1 = Triangle::new_with_texture(
-                triangle_vertices1,
-                self.texture.as_ref().unwrap().clone(),
-            );
-            let triangle2 = Triangle::new_with_texture(
-                triangle_vertices2,
-                self.texture.as_ref().unwrap().clone(),
-            );
+            let triangle1 = Triangle::new(triangle_vertices1);
+            let triangle2 = Triangle::new(triangle_vertices2);
 
             triangle1.draw(buffer, depth_buffer, model, mvp, viewport_size);
             triangle2.draw(buffer, depth_buffer, model, mvp, viewport_size);
@@ -645,3 +824,83 @@ impl Object for Circle {
         std::f32::consts::PI * f32::powf(self.radius, 2.0)
     }
 }
+
+/// Half line starting at `origin`. Hit distances are in multiples of `direction`,
+/// so they are world units when it is normalized.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Self { origin, direction }
+    }
+
+    pub fn at(&self, distance: f32) -> Vec3 {
+        self.origin + self.direction * distance
+    }
+
+    /// The ray after `matrix` is applied. The direction is not renormalized, distances
+    /// along the transformed ray match the ones along this ray.
+    pub fn transform(&self, matrix: &Mat4) -> Self {
+        Self::new(
+            matrix.transform_point3(self.origin),
+            matrix.transform_vector3(self.direction),
+        )
+    }
+}
+
+/// Distance along the ray and barycentric coordinates of the hit with a triangle,
+/// both faces count (Möller & Trumbore).
+pub fn ray_triangle_intersection(ray: &Ray, positions: &[Vec3; 3]) -> Option<(f32, Vec3)> {
+    let edge1 = positions[1] - positions[0];
+    let edge2 = positions[2] - positions[0];
+    let p = ray.direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    // parallel to the triangle, or degenerate. The determinant scales with the edges and
+    // the direction, so tiny (or huge) triangles are compared against their own size
+    let scale = edge1.length() * edge2.length() * ray.direction.length();
+    if determinant.abs() <= f32::EPSILON * scale || !determinant.is_finite() {
+        return None;
+    }
+
+    let inverse = 1.0 / determinant;
+    let offset = ray.origin - positions[0];
+    let u = offset.dot(p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = offset.cross(edge1);
+    let v = ray.direction.dot(q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let distance = edge2.dot(q) * inverse;
+    (distance >= 0.0).then_some((distance, Vec3::new(1.0 - u - v, u, v)))
+}
+
+/// Distances along the ray where it enters and leaves the box (slab test), the entry
+/// is 0 when the ray starts inside.
+pub fn ray_aabb_intersection(ray: &Ray, aabb: &Aabb) -> Option<(f32, f32)> {
+    if aabb.is_empty() {
+        return None;
+    }
+    // a ray parallel to a slab is either always or never inside it, and would give
+    // 0 * infinity = NaN when starting on its border
+    let parallel = ray.direction.cmpeq(Vec3::ZERO);
+    if (parallel & (ray.origin.cmplt(aabb.min) | ray.origin.cmpgt(aabb.max))).any() {
+        return None;
+    }
+
+    let inverse = ray.direction.recip();
+    let t0 = (aabb.min - ray.origin) * inverse;
+    let t1 = (aabb.max - ray.origin) * inverse;
+    let near = Vec3::select(parallel, Vec3::splat(f32::NEG_INFINITY), t0.min(t1))
+        .max_element()
+        .max(0.0);
+    let far = Vec3::select(parallel, Vec3::splat(f32::INFINITY), t0.max(t1)).min_element();
+    (near <= far).then_some((near, far))
+}
@@ -1,23 +1,69 @@
 //use glam::{Vec2, Vec3Swizzles};
 
 use std::{
-    sync::{mpsc, Arc, Mutex},
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc, Condvar, Mutex, MutexGuard},
     thread,
 };
 
+pub mod animation;
+pub mod assets;
+pub mod bounds;
+pub mod bvh;
 pub mod camera;
+pub mod cubemap;
+pub mod debug_draw;
+pub mod debug_view;
+pub mod deferred;
+pub mod environment;
+pub mod error;
+pub mod export;
+pub mod fog;
+pub mod gbuffer;
 pub mod geometry;
+pub mod lines;
+pub mod lod;
+pub mod material;
 pub mod mesh;
 pub mod model;
+pub mod obj;
+pub mod oit;
+pub mod picking;
+pub mod ply;
+pub mod postprocess;
+pub mod ssao;
+pub mod stl;
 pub mod texture;
 pub mod transform;
 pub mod utils;
 pub use {
+    animation::{MorphAnimation, MorphChannel},
+    assets::{Asset, AssetStatus, Assets, Handle},
+    bounds::{Aabb, BoundingSphere, Frustum, Plane},
+    bvh::{Bvh, BvhNode},
     camera::Camera,
+    cubemap::{irradiance, CubeFace, CubeMap},
+    debug_draw::DebugDraw,
+    debug_view::DebugView,
+    deferred::{DeferredShading, LightingStats, PointLight},
+    environment::Environment,
+    error::RusterizerError,
+    fog::{Fog, FogColor, FogMode, HeightFog},
+    gbuffer::{Attachment, GBuffer, GBufferTarget},
     geometry::*,
-    mesh::Mesh,
+    lines::{Line, LineStyle, Point},
+    lod::{Lod, LodStats},
+    material::Material,
+    mesh::{Mesh, MorphTarget, NormalWeighting, PolygonMode, WeldTolerance},
     model::Model,
-    texture::Texture,
+    oit::OitBuffer,
+    picking::{pick, IdBuffer, IdHit, Pick, RayHit},
+    postprocess::{
+        argb8_to_colors, colors_to_argb8, Lut3d, PostEffect, PostPass, PostProcess, ToneMapping,
+    },
+    ssao::Ssao,
+    texture::{ColorSpace, Texture},
     transform::{Transform, TransformInitialParams},
     utils::*,
 };
@@ -62,6 +108,87 @@ impl ThreadPool {
 
         self.sender.as_ref().unwrap().send(job).unwrap();
     }
+
+    /// Runs every job on the pool and only returns once all of them are done, which
+    /// lets the jobs borrow from the caller. The calling thread runs jobs too while it
+    /// waits, so jobs can call `execute_all` themselves. If a job panics, the panic is
+    /// propagated here once the others have finished.
+    pub fn execute_all<'a>(&self, jobs: Vec<Box<dyn FnOnce() + Send + 'a>>) {
+        let count = jobs.len();
+        // SAFETY: the jobs only leave the batch to be run, and every job that was taken
+        // is counted in `remaining` until it has run (or panicked) and been dropped. We
+        // run the jobs left in the queue ourselves and wait for `remaining` to reach zero
+        // before returning, and nothing in between can unwind, so no job outlives 'a
+        let jobs: Vec<Job> = jobs
+            .into_iter()
+            .map(|job| unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + 'a>, Job>(job) })
+            .collect();
+        let batch = Arc::new(Batch {
+            state: Mutex::new(BatchState {
+                jobs,
+                remaining: count,
+                panic: None,
+            }),
+            done: Condvar::new(),
+        });
+
+        // the caller takes a share, the workers help with the rest
+        for _ in 1..self.workers.len().min(count) {
+            let batch = Arc::clone(&batch);
+            if let Some(sender) = &self.sender {
+                let _ = sender.send(Box::new(move || batch.run()));
+            }
+        }
+        batch.run();
+
+        let mut state = batch.lock();
+        while state.remaining > 0 {
+            state = batch.done.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        if let Some(panic) = state.panic.take() {
+            drop(state);
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+// jobs of one `execute_all` call, shared by the threads running them
+struct Batch {
+    state: Mutex<BatchState>,
+    done: Condvar,
+}
+
+struct BatchState {
+    jobs: Vec<Job>,
+    // jobs not finished yet, queued or running
+    remaining: usize,
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+impl Batch {
+    // jobs never poison the lock, they run outside of it
+    fn lock(&self) -> MutexGuard<'_, BatchState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // runs queued jobs until there are none left
+    fn run(&self) {
+        loop {
+            let Some(job) = self.lock().jobs.pop() else {
+                return;
+            };
+            let result = panic::catch_unwind(AssertUnwindSafe(job));
+
+            let mut state = self.lock();
+            if let Err(panic) = result {
+                state.panic.get_or_insert(panic);
+            }
+            state.remaining -= 1;
+            if state.remaining == 0 {
+                self.done.notify_all();
+            }
+        }
+    }
 }
 
 impl Drop for ThreadPool {
@@ -0,0 +1,275 @@
+use crate::geometry::Vertex;
+use crate::utils::{from_argb8, linear_to_argb8, map_to_range, srgb_to_linear};
+
+use glam::{Mat4, Vec2, Vec3, Vec3Swizzles, Vec4, Vec4Swizzles};
+
+// lines and points are pulled this much towards the camera, in depth buffer units,
+// so that they win against the faces they lie on
+const DEPTH_BIAS: f32 = 2e-5;
+
+/// How lines are turned into pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineStyle {
+    /// One fully covered pixel per step.
+    Bresenham,
+    /// Xiaolin Wu's anti-aliased lines, blending two pixels per step by their coverage.
+    AntiAliased,
+}
+
+/// A segment between two vertices, drawn with their interpolated colours and no lighting.
+#[derive(Debug, Clone, Copy)]
+pub struct Line {
+    pub vertices: [Vertex; 2],
+}
+
+impl Line {
+    pub fn new(vertices: [Vertex; 2]) -> Self {
+        Self { vertices }
+    }
+
+    /// Transforms the line by `mvp`, clips it to the view and draws it, depth tested.
+    pub fn draw(
+        &self,
+        buffer: &mut [u32],
+        depth_buffer: &mut [f32],
+        mvp: &Mat4,
+        style: LineStyle,
+        viewport_size: Vec2,
+    ) {
+        let mut clip = *self;
+        for vertex in &mut clip.vertices {
+            vertex.position = *mvp * vertex.position.xyz().extend(1.0);
+        }
+        if let Some(line) = clip.clip() {
+            line.draw_clipped(buffer, depth_buffer, style, viewport_size);
+        }
+    }
+
+    /// The part of a clip space line inside the view volume, `None` when it is all outside.
+    pub fn clip(&self) -> Option<Line> {
+        let [a, b] = self.vertices.map(|v| v.position);
+        // Liang-Barsky against the six planes, x and y in [-w, w] and z in [0, w]
+        let planes = [
+            (a.w + a.x, b.w + b.x),
+            (a.w - a.x, b.w - b.x),
+            (a.w + a.y, b.w + b.y),
+            (a.w - a.y, b.w - b.y),
+            (a.z, b.z),
+            (a.w - a.z, b.w - b.z),
+        ];
+        let (mut enter, mut exit) = (0.0f32, 1.0f32);
+        for (da, db) in planes {
+            if da < 0.0 && db < 0.0 {
+                return None;
+            }
+            if da < 0.0 {
+                enter = enter.max(da / (da - db));
+            } else if db < 0.0 {
+                exit = exit.min(da / (da - db));
+            }
+        }
+        if enter > exit {
+            return None;
+        }
+
+        let [v0, v1] = self.vertices;
+        let lerp = |t: f32| v0 + (v1 - v0) * t;
+        Some(Line::new([lerp(enter), lerp(exit)]))
+    }
+
+    /// Draws a line already clipped by `Line::clip`, still in clip space.
+    pub fn draw_clipped(
+        &self,
+        buffer: &mut [u32],
+        depth_buffer: &mut [f32],
+        style: LineStyle,
+        viewport_size: Vec2,
+    ) {
+        let [p0, p1] = self.vertices.map(|v| to_screen(v.position, viewport_size));
+        // colours are interpolated perspective correct, depth is linear on screen
+        let rec = self.vertices.map(|v| 1.0 / v.position.w);
+        let colors = [0, 1].map(|i| self.vertices[i].color * rec[i]);
+        let fragment = |t: f32| {
+            let correction = 1.0 / (rec[0] + (rec[1] - rec[0]) * t);
+            let color = colors[0].lerp(colors[1], t) * correction;
+            let depth = p0.z + (p1.z - p0.z) * t - DEPTH_BIAS;
+            (color, depth)
+        };
+
+        let width = viewport_size.x as usize;
+        let height = viewport_size.y as usize;
+        match style {
+            LineStyle::Bresenham => {
+                let (x0, y0) = (p0.x.floor() as i64, p0.y.floor() as i64);
+                let (x1, y1) = (p1.x.floor() as i64, p1.y.floor() as i64);
+                let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+                let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+                let steps = dx.max(-dy).max(1) as f32;
+                let (mut x, mut y, mut error) = (x0, y0, dx + dy);
+                for step in 0.. {
+                    let (color, depth) = fragment(step as f32 / steps);
+                    plot(
+                        buffer,
+                        depth_buffer,
+                        (x, y),
+                        (width, height),
+                        color,
+                        depth,
+                        1.0,
+                    );
+                    if x == x1 && y == y1 {
+                        break;
+                    }
+                    let doubled = 2 * error;
+                    if doubled >= dy {
+                        error += dy;
+                        x += sx;
+                    }
+                    if doubled <= dx {
+                        error += dx;
+                        y += sy;
+                    }
+                }
+            }
+            LineStyle::AntiAliased => {
+                // walk along the major axis, splitting each step between the two pixels
+                // straddling the line
+                let steep = (p1.y - p0.y).abs() > (p1.x - p0.x).abs();
+                let swap = |p: Vec3| if steep { Vec2::new(p.y, p.x) } else { p.xy() };
+                let (mut a, mut b, mut reversed) = (swap(p0), swap(p1), false);
+                if a.x > b.x {
+                    std::mem::swap(&mut a, &mut b);
+                    reversed = true;
+                }
+                let gradient = if b.x - a.x > 0.0 {
+                    (b.y - a.y) / (b.x - a.x)
+                } else {
+                    1.0
+                };
+
+                let first = (a.x - 0.5).round() as i64;
+                let last = (b.x - 0.5).round() as i64;
+                for major in first..=last {
+                    let center = major as f32 + 0.5;
+                    // pixels at the ends are only covered as far as the line goes
+                    let along = ((center - a.x) / (b.x - a.x).max(f32::EPSILON)).clamp(0.0, 1.0);
+                    let end_coverage =
+                        (b.x.min(center + 0.5) - a.x.max(center - 0.5)).clamp(0.0, 1.0);
+                    let t = if reversed { 1.0 - along } else { along };
+                    let (color, depth) = fragment(t);
+
+                    let minor = a.y + gradient * (center - a.x) - 0.5;
+                    let low = minor.floor();
+                    let fraction = minor - low;
+                    for (offset, coverage) in [(0, 1.0 - fraction), (1, fraction)] {
+                        let minor = low as i64 + offset;
+                        let pixel = if steep {
+                            (minor, major)
+                        } else {
+                            (major, minor)
+                        };
+                        let coverage = coverage * end_coverage;
+                        plot(
+                            buffer,
+                            depth_buffer,
+                            pixel,
+                            (width, height),
+                            color,
+                            depth,
+                            coverage,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A vertex drawn as a square of `size` pixels facing the camera, depth tested.
+#[derive(Debug, Clone, Copy)]
+pub struct Point {
+    pub vertex: Vertex,
+    pub size: f32,
+}
+
+impl Point {
+    pub fn new(vertex: Vertex, size: f32) -> Self {
+        Self { vertex, size }
+    }
+
+    /// Transforms the point by `mvp` and draws it, unless it is outside the view volume.
+    pub fn draw(
+        &self,
+        buffer: &mut [u32],
+        depth_buffer: &mut [f32],
+        mvp: &Mat4,
+        viewport_size: Vec2,
+    ) {
+        let clip = *mvp * self.vertex.position.xyz().extend(1.0);
+        if clip.w <= 0.0 || clip.z < 0.0 || clip.z > clip.w {
+            return;
+        }
+        let center = to_screen(clip, viewport_size);
+        let depth = center.z - DEPTH_BIAS;
+
+        let half = self.size.max(1.0) * 0.5;
+        let low = (center.xy() - half).round();
+        let high = (center.xy() + half).round();
+        let size = (viewport_size.x as usize, viewport_size.y as usize);
+        for y in low.y as i64..high.y as i64 {
+            for x in low.x as i64..high.x as i64 {
+                plot(
+                    buffer,
+                    depth_buffer,
+                    (x, y),
+                    size,
+                    self.vertex.color,
+                    depth,
+                    1.0,
+                );
+            }
+        }
+    }
+}
+
+// screen coordinates and depth of a clip space position
+fn to_screen(position: Vec4, viewport_size: Vec2) -> Vec3 {
+    let ndc = position.xyz() / position.w;
+    Vec3::new(
+        map_to_range(ndc.x, -1.0, 1.0, 0.0, viewport_size.x),
+        map_to_range(-ndc.y, -1.0, 1.0, 0.0, viewport_size.y),
+        ndc.z,
+    )
+}
+
+// blends a linear colour over the pixel by `coverage`. Only mostly covered pixels
+// write their depth, so anti-aliased edges do not hide what is drawn behind them later
+fn plot(
+    buffer: &mut [u32],
+    depth_buffer: &mut [f32],
+    (x, y): (i64, i64),
+    (width, height): (usize, usize),
+    color: Vec4,
+    depth: f32,
+    coverage: f32,
+) {
+    if x < 0 || y < 0 || x as usize >= width || y as usize >= height || coverage <= 0.0 {
+        return;
+    }
+    let index = y as usize * width + x as usize;
+    if depth > depth_buffer[index] {
+        return;
+    }
+    if coverage >= 0.5 {
+        depth_buffer[index] = depth;
+    }
+
+    let color = if coverage >= 1.0 {
+        color.xyz()
+    } else {
+        let (_, r, g, b) = from_argb8(buffer[index]);
+        let decode = |c: u8| srgb_to_linear(c as f32 / 255.0);
+        Vec3::new(decode(r), decode(g), decode(b)).lerp(color.xyz(), coverage)
+    };
+    buffer[index] = linear_to_argb8(color.extend(1.0));
+}
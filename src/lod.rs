@@ -0,0 +1,152 @@
+use crate::bounds::BoundingSphere;
+use crate::camera::Camera;
+use crate::mesh::Mesh;
+use crate::model::Model;
+use crate::transform::Transform;
+
+use glam::{Mat4, Vec2, Vec4};
+use std::ops::AddAssign;
+use std::sync::Arc;
+
+/// A simplified version of the meshes of a `Model`.
+#[derive(Debug, Clone)]
+pub struct Lod {
+    /// One mesh per mesh of the model, in the same order.
+    pub meshes: Vec<Arc<Mesh>>,
+    /// Used once the model covers less than this fraction of the viewport height.
+    pub screen_size: f32,
+}
+
+/// Triangles submitted by LOD drawing, and how many the full meshes would have added.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LodStats {
+    pub triangles_drawn: usize,
+    pub triangles_saved: usize,
+}
+
+impl AddAssign for LodStats {
+    fn add_assign(&mut self, other: Self) {
+        self.triangles_drawn += other.triangles_drawn;
+        self.triangles_saved += other.triangles_saved;
+    }
+}
+
+pub(crate) fn triangle_count(meshes: &[Arc<Mesh>]) -> usize {
+    meshes.iter().map(|m| m.triangles().len()).sum()
+}
+
+impl Model {
+    /// Builds `levels` LODs, each with half the triangles of the one before and used
+    /// below half the screen size of the one before, starting at half the viewport height.
+    pub fn generate_lods(&mut self, levels: usize) {
+        let chains: Vec<Vec<Mesh>> = self
+            .meshes
+            .iter()
+            .map(|mesh| mesh.lod_chain(levels, 0.5))
+            .collect();
+
+        self.lods = (0..levels)
+            .map(|level| Lod {
+                meshes: chains
+                    .iter()
+                    .map(|chain| Arc::new(chain[level].clone()))
+                    .collect(),
+                screen_size: 0.5_f32.powi(level as i32 + 1),
+            })
+            .collect();
+    }
+
+    /// The meshes of LOD `level`, 0 being the full resolution meshes.
+    pub fn lod_meshes(&self, level: usize) -> &[Arc<Mesh>] {
+        match level {
+            0 => &self.meshes,
+            level => self
+                .lods
+                .get(level - 1)
+                .map_or(self.meshes.as_slice(), |lod| lod.meshes.as_slice()),
+        }
+    }
+
+    // the LOD for a sphere placed by `model`, given in model space
+    fn lod_for_sphere(&self, camera: &Camera, model: &Mat4, sphere: &BoundingSphere) -> usize {
+        let sphere = sphere.transform(model);
+        let size = camera.projected_size(sphere.center, sphere.radius);
+
+        self.lods
+            .iter()
+            .take_while(|lod| size < lod.screen_size)
+            .count()
+    }
+
+    /// The LOD the model should use when placed by `model` (a model matrix), 0 being full detail.
+    pub fn select_lod(&self, camera: &Camera, model: &Mat4) -> usize {
+        if self.lods.is_empty() {
+            return 0;
+        }
+        self.lod_for_sphere(camera, model, &self.bounding_sphere())
+    }
+
+    /// Same as `draw_instanced`, but every instance uses the LOD matching its size on screen.
+    pub fn draw_instanced_lod(
+        &self,
+        buffer: &mut Vec<u32>,
+        depth_buffer: &mut Vec<f32>,
+        camera: &Camera,
+        instances: &[Transform],
+        tints: Option<&[Vec4]>,
+        viewport_size: Vec2,
+    ) -> LodStats {
+        let view_projection = camera.projection() * camera.view();
+        let local = self.transform.local();
+        let sphere = self.bounding_sphere();
+        let aabb = self.aabb();
+        let frustum = camera.frustum();
+        let full_triangles = triangle_count(&self.meshes);
+
+        // visible instance models and tints, grouped by level
+        let mut levels: Vec<(Vec<Mat4>, Vec<Vec4>)> = vec![(vec![], vec![]); self.lods.len() + 1];
+        for (i, instance) in instances.iter().enumerate() {
+            let model = instance.local() * local;
+            if !frustum.intersects_aabb(&aabb.transform(&model)) {
+                continue;
+            }
+            let level = if self.lods.is_empty() {
+                0
+            } else {
+                self.lod_for_sphere(camera, &model, &sphere)
+            };
+            levels[level].0.push(model);
+            if let Some(tints) = tints {
+                levels[level]
+                    .1
+                    .push(tints.get(i).copied().unwrap_or(Vec4::ONE));
+            }
+        }
+
+        let mut stats = LodStats::default();
+        for (level, (models, level_tints)) in levels.iter().enumerate() {
+            if models.is_empty() {
+                continue;
+            }
+            let meshes = self.lod_meshes(level);
+            let triangles = triangle_count(meshes);
+            stats.triangles_drawn += triangles * models.len();
+            stats.triangles_saved += full_triangles.saturating_sub(triangles) * models.len();
+
+            for (i, mesh) in meshes.iter().enumerate() {
+                mesh.draw_instanced(
+                    self.weights.get(i).unwrap_or(mesh.weights()),
+                    self.polygon_mode,
+                    buffer,
+                    depth_buffer,
+                    &view_projection,
+                    models,
+                    tints.map(|_| level_tints.as_slice()),
+                    viewport_size,
+                );
+            }
+        }
+
+        stats
+    }
+}
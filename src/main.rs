@@ -1,8 +1,7 @@
-use glam::{Vec2, Vec3, Vec4};
-use minifb::{Key, Window, WindowOptions};
+use glam::{Mat4, UVec3, Vec2, Vec3, Vec4};
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window, WindowOptions};
 use std::cell::UnsafeCell;
-use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::sync::{mpsc, Arc};
 
 use rusterizer::*;
 
@@ -46,27 +45,32 @@ fn main() {
 
     let thread_pool = ThreadPool::new(32);
 
-    let texture = Arc::new(Texture::load(std::path::Path::new(
-        "resources/models/SciFiHelmet/SciFiHelmet_BaseColor.png",
-    )));
+    let assets = Assets::new();
 
-    // let mut model = Model::new(Path::new("resources/models/SciFiHelmet/SciFiHelmet.gltf"));
-    // model.meshes[0].add_texture(Arc::clone(&texture));
+    // Background loading on the thread pool, the helmet is parsed once and drawn as instances
+    let texture_handle: Handle<Texture> = assets.load(
+        &thread_pool,
+        "resources/models/SciFiHelmet/SciFiHelmet_BaseColor.png",
+    );
+    let helmet_handle: Handle<Model> = assets.load(
+        &thread_pool,
+        "resources/models/SciFiHelmet/SciFiHelmet.gltf",
+    );
+    // not referenced by the glTF, set by hand
+    let occlusion_handle: Handle<Texture> = assets.load(
+        &thread_pool,
+        "resources/models/SciFiHelmet/SciFiHelmet_AmbientOcclusion.png",
+    );
+    // the textured helmet and its LODs are prepared on the pool, simplifying takes a while
+    let mut helmet_requested = false;
+    let (helmet_sender, helmet_receiver) = mpsc::channel::<Model>();
 
-    let objects: Arc<Mutex<Vec<Model>>> = Arc::new(Mutex::new(vec![]));
+    let mut objects: Vec<Model> = vec![];
 
-    // Multi-threaded model loading
+    let mut instances: Vec<Transform> = vec![];
     let mut model_trans = Vec3::new(0.0, 0.0, 0.0);
     for _i in 0..15 {
-        let objects = Arc::clone(&objects);
-        let texture = Arc::clone(&texture);
-        thread_pool.execute(move || {
-            let mut helm = Model::new(Path::new("resources/models/SciFiHelmet/SciFiHelmet.gltf"));
-            helm.transform = Transform::from_translation(model_trans);
-            helm.meshes[0].add_texture(texture);
-
-            objects.lock().unwrap().push(helm);
-        });
+        instances.push(Transform::from_translation(model_trans));
         model_trans.x += 1.0;
     }
 
@@ -82,7 +86,149 @@ fn main() {
     // Limit to max ~60 fps update rate
     window.limit_update_rate(Some(std::time::Duration::from_micros(16600)));
 
+    let mut mouse_was_down = false;
+
+    // M cycles how the models are drawn, to look at their topology
+    let polygon_modes = [
+        PolygonMode::Fill,
+        PolygonMode::WireframeOverFill(LineStyle::AntiAliased),
+        PolygonMode::Wireframe(LineStyle::AntiAliased),
+        PolygonMode::Wireframe(LineStyle::Bresenham),
+        PolygonMode::Points(2.0),
+    ];
+    let mut polygon_mode = 0;
+
+    // Tab switches to deferred shading, lit by a few hundred small point lights
+    let mut deferred_mode = false;
+    let mut tab_was_down = false;
+    let attachments = [
+        Attachment::Normal,
+        Attachment::Albedo,
+        Attachment::Position,
+        Attachment::Material,
+        Attachment::Occlusion,
+    ];
+    let mut gbuffer = GBuffer::new(WIDTH, HEIGHT, &attachments);
+
+    // C cycles through the debug views, drawn through the G-buffer in both modes
+    let mut debug_view = DebugView::Lit;
+    // Procedural sky behind the scene, also lighting the deferred mode
+    // with a fog fading into it before the far plane, F switches it
+    let mut environment = Environment::new(CubeMap::from_fn(64, |direction| {
+        let horizon = Vec3::new(0.8, 0.85, 0.9);
+        let zenith = Vec3::new(0.15, 0.35, 0.8);
+        let ground = Vec3::new(0.2, 0.18, 0.15);
+        let sun = Vec3::new(0.4, 0.6, -0.7).normalize();
+        let sky = if direction.y >= 0.0 {
+            horizon.lerp(zenith, direction.y.sqrt())
+        } else {
+            horizon.lerp(ground, (-direction.y * 4.0).min(1.0))
+        };
+        sky + Vec3::new(20.0, 18.0, 15.0) * direction.dot(sun).max(0.0).powf(2000.0)
+    }));
+    environment.fog = Some(Fog {
+        height: Some(HeightFog {
+            base: -1.0,
+            density: 0.15,
+            falloff: 1.5,
+        }),
+        ..Default::default()
+    });
+    let environment = Arc::new(environment);
+    let mut fog_enabled = true;
+    let mut deferred = DeferredShading {
+        ambient: Vec3::splat(0.05),
+        environment: Some(environment.clone()),
+        ..Default::default()
+    };
+    let light_count = 200;
+
+    // Two crossing panes of tinted glass, T switches them
+    let glass = {
+        let pane = |corners: [Vec3; 4], color: Vec4| {
+            let normal = (corners[1] - corners[0])
+                .cross(corners[3] - corners[0])
+                .normalize();
+            corners.map(|corner| Vertex::new(corner.extend(1.0), normal, color, Vec2::ZERO))
+        };
+        let red = pane(
+            [
+                Vec3::new(3.0, -1.5, 4.0),
+                Vec3::new(7.0, -1.5, 4.0),
+                Vec3::new(7.0, 1.5, 4.0),
+                Vec3::new(3.0, 1.5, 4.0),
+            ],
+            Vec4::new(1.0, 0.2, 0.1, 0.4),
+        );
+        let blue = pane(
+            [
+                Vec3::new(5.0, -1.5, 6.0),
+                Vec3::new(5.0, -1.5, 2.0),
+                Vec3::new(5.0, 1.5, 2.0),
+                Vec3::new(5.0, 1.5, 6.0),
+            ],
+            Vec4::new(0.1, 0.3, 1.0, 0.4),
+        );
+        let vertices: Vec<Vertex> = red.into_iter().chain(blue).collect();
+        // both windings, the panes are seen from either side
+        let triangles: Vec<UVec3> = [0, 4]
+            .iter()
+            .flat_map(|&i| {
+                [
+                    UVec3::new(i, i + 1, i + 2),
+                    UVec3::new(i, i + 2, i + 3),
+                    UVec3::new(i, i + 2, i + 1),
+                    UVec3::new(i, i + 3, i + 2),
+                ]
+            })
+            .collect();
+        Model::from_meshes(vec![Mesh::from_vertices(&triangles, &vertices)])
+    };
+    // G shows a grid, the axes and the bounds of the models, N their normals
+    let mut debug_draw = DebugDraw::new();
+    let mut debug_enabled = false;
+    let mut normals_enabled = false;
+
+    let mut oit = OitBuffer::new(WIDTH, HEIGHT);
+    let mut glass_enabled = true;
+
+    // O switches the screen space ambient occlusion, V shows it alone
+    let mut ssao = Ssao::default();
+    let mut ssao_enabled = true;
+    let mut show_occlusion = false;
+
+    // P switches the post-processing on and off, 1 to 7 the passes of the chain. Colours
+    // are linear up to the sRGB encode
+    let mut post_enabled = true;
+    let mut post = PostProcess::new(vec![
+        PostEffect::Exposure(0.0),
+        PostEffect::Bloom {
+            threshold: 1.0,
+            intensity: 0.6,
+            radius: 6,
+        },
+        PostEffect::ToneMap(ToneMapping::Aces),
+        PostEffect::SrgbEncode,
+        PostEffect::Fxaa,
+        PostEffect::Sharpen(0.2),
+        PostEffect::Vignette {
+            strength: 0.4,
+            radius: 0.5,
+        },
+    ]);
+    let pass_keys = [
+        Key::Key1,
+        Key::Key2,
+        Key::Key3,
+        Key::Key4,
+        Key::Key5,
+        Key::Key6,
+        Key::Key7,
+    ];
+    let mut colors = vec![Vec4::ZERO; WIDTH * HEIGHT];
+
     let mut current_time = std::time::Instant::now();
+    let start_time = current_time;
 
     while window.is_open() && !window.is_key_down(Key::Escape) {
         // Calculate frame time (delta time)
@@ -117,23 +263,199 @@ fn main() {
         // Update
         camera.update(&window, delta_time);
 
-        if !objects.lock().unwrap().is_empty() {
-            let objects = Arc::clone(&objects);
-            for object in &mut *objects.lock().unwrap() {
-                let mvp = camera.projection() * camera.view() * object.transform.local();
+        // the occlusion is optional, but it is only looked at once so wait for it to settle
+        let occlusion_settled = assets.status(&occlusion_handle) != Some(AssetStatus::Loading);
+        if !helmet_requested && occlusion_settled {
+            if let (Some(helmet), Some(texture)) =
+                (assets.get(&helmet_handle), assets.get(&texture_handle))
+            {
+                let occlusion = assets.get(&occlusion_handle);
+                let helmet_sender = helmet_sender.clone();
+                thread_pool.execute(move || {
+                    let mut helm = (*helmet).clone();
+                    let mesh = Arc::make_mut(&mut helm.meshes[0]);
+                    mesh.add_texture(texture);
+                    if let Some(occlusion) = occlusion {
+                        let occlusion = (*occlusion).clone().with_color_space(ColorSpace::Linear);
+                        mesh.set_occlusion_texture(Some(Arc::new(occlusion)));
+                    }
+                    helm.generate_lods(3);
+                    let _ = helmet_sender.send(helm);
+                });
+                helmet_requested = true;
+            }
+        }
+        if let Ok(mut helm) = helmet_receiver.try_recv() {
+            helm.polygon_mode = polygon_modes[polygon_mode];
+            objects.push(helm);
+        }
 
-                // Draw objects
-                object.draw(
+        let tab_down = window.is_key_down(Key::Tab);
+        if tab_down && !tab_was_down {
+            deferred_mode = !deferred_mode;
+        }
+        tab_was_down = tab_down;
+
+        if window.is_key_pressed(Key::M, KeyRepeat::No) {
+            polygon_mode = (polygon_mode + 1) % polygon_modes.len();
+            for object in &mut objects {
+                object.polygon_mode = polygon_modes[polygon_mode];
+            }
+            println!("Polygon mode: {:?}", polygon_modes[polygon_mode]);
+        }
+
+        if window.is_key_pressed(Key::C, KeyRepeat::No) {
+            debug_view = debug_view.next();
+            let mut attachments = attachments.to_vec();
+            attachments.extend(debug_view.attachment());
+            gbuffer = GBuffer::new(WIDTH, HEIGHT, &attachments);
+            println!("Debug view: {debug_view:?}");
+        }
+        if window.is_key_pressed(Key::O, KeyRepeat::No) {
+            ssao_enabled = !ssao_enabled;
+        }
+        // the forward mode needs the baked occlusion of the G-buffer to add SSAO
+        let gbuffer_pass = deferred_mode || debug_view != DebugView::Lit || ssao_enabled;
+
+        let elapsed = start_time.elapsed().as_secs_f32();
+        let mut lod_stats = LodStats::default();
+        if gbuffer_pass {
+            gbuffer.begin_frame(camera.projection() * camera.view());
+        }
+        for (i, object) in objects.iter_mut().enumerate() {
+            // Play the first morph target animation, if any
+            object.animate(0, elapsed);
+
+            if gbuffer_pass {
+                lod_stats += object.draw_gbuffer(
                     buffer.get_mut(),
                     depth_buffer.get_mut(),
-                    &mvp,
-                    Vec2 {
-                        x: WIDTH as f32,
-                        y: HEIGHT as f32,
-                    },
+                    &mut gbuffer,
+                    i as u32 + 1,
+                    &instances,
+                    None,
+                    Some(&camera),
                 );
+                continue;
+            }
+
+            // Draw objects, far away instances use simplified meshes
+            lod_stats += object.draw_instanced_lod(
+                buffer.get_mut(),
+                depth_buffer.get_mut(),
+                &camera,
+                &instances,
+                None,
+                Vec2 {
+                    x: WIDTH as f32,
+                    y: HEIGHT as f32,
+                },
+            );
+        }
+
+        if window.is_key_pressed(Key::P, KeyRepeat::No) {
+            post_enabled = !post_enabled;
+        }
+        for (i, key) in pass_keys.iter().enumerate() {
+            if window.is_key_pressed(*key, KeyRepeat::No) {
+                if let Some(enabled) = post.toggle(i) {
+                    println!("{:?} enabled: {enabled}", post.passes[i].effect);
+                }
             }
         }
+
+        // The sky fills whatever the geometry left uncovered. With post-processing the
+        // image switches to linear colours here, so the sky is not clamped before the tone
+        // mapping
+        if post_enabled {
+            argb8_to_colors(buffer.get_mut(), &mut colors);
+            environment.draw_skybox_hdr(
+                &thread_pool,
+                &camera,
+                depth_buffer.get_mut(),
+                &mut colors,
+                WIDTH,
+            );
+        } else {
+            environment.draw_skybox(
+                &thread_pool,
+                &camera,
+                depth_buffer.get_mut(),
+                buffer.get_mut(),
+                WIDTH,
+            );
+        }
+
+        if window.is_key_pressed(Key::V, KeyRepeat::No) {
+            show_occlusion = !show_occlusion;
+        }
+
+        // Without the G-buffer there are no normals, they are rebuilt from the depth
+        let mut ssao_computed = false;
+        if ssao_enabled || show_occlusion {
+            let normals = if gbuffer_pass {
+                gbuffer.normals.as_deref()
+            } else {
+                None
+            };
+            ssao.compute(
+                &thread_pool,
+                depth_buffer.get_mut(),
+                normals,
+                &camera,
+                WIDTH,
+            );
+            ssao_computed = true;
+        }
+
+        // forward shaded pixels lose the occluded part of their ambient light
+        if !deferred_mode && ssao_enabled && ssao_computed {
+            if post_enabled {
+                ssao.apply_forward_hdr(&thread_pool, &gbuffer, &mut colors);
+            } else {
+                ssao.apply_forward(&thread_pool, &gbuffer, buffer.get_mut());
+            }
+        }
+
+        let mut lighting_stats = LightingStats::default();
+        if deferred_mode {
+            if ssao_enabled && ssao_computed {
+                ssao.apply(&mut gbuffer);
+            }
+
+            // Lights orbiting along the row of helmets
+            deferred.lights = (0..light_count)
+                .map(|i| {
+                    let t = i as f32 / light_count as f32;
+                    let angle = t * 40.0 + elapsed;
+                    let position = Vec3::new(t * 16.0 - 0.5, angle.sin() * 1.2, angle.cos() * 1.2);
+                    let color = Vec3::new(
+                        (t * 6.0).sin() * 0.5 + 0.5,
+                        (t * 6.0 + 2.0).sin() * 0.5 + 0.5,
+                        (t * 6.0 + 4.0).sin() * 0.5 + 0.5,
+                    );
+                    PointLight::new(position, color, 1.5, 1.0)
+                })
+                .collect();
+            lighting_stats = if post_enabled {
+                // HDR lighting over the background, tone mapped by the post-processing
+                deferred.shade_hdr(
+                    &thread_pool,
+                    &gbuffer,
+                    depth_buffer.get_mut(),
+                    &camera,
+                    &mut colors,
+                )
+            } else {
+                deferred.shade(
+                    &thread_pool,
+                    &gbuffer,
+                    depth_buffer.get_mut(),
+                    &camera,
+                    buffer.get_mut(),
+                )
+            };
+        }
         // let mvp = camera.projection() * camera.view() * model.transform.local();
         // model.draw(
         //     buffer.get_mut(),
@@ -145,9 +467,130 @@ fn main() {
         //     },
         // );
 
+        // Left click prints which helmet is under the cursor
+        let mouse_down = window.get_mouse_down(MouseButton::Left);
+        if mouse_down && !mouse_was_down {
+            if let Some((x, y)) = window.get_mouse_pos(MouseMode::Discard) {
+                let scene: Vec<(&Model, &[Transform])> = objects
+                    .iter()
+                    .map(|object| (object, instances.as_slice()))
+                    .collect();
+                let viewport_size = Vec2::new(WIDTH as f32, HEIGHT as f32);
+                if let Some(hit) = pick(&camera, &scene, x, y, viewport_size) {
+                    println!(
+                        "Picked model {} instance {} triangle {} at {}",
+                        hit.model, hit.instance, hit.hit.triangle, hit.position
+                    );
+                }
+            }
+        }
+        mouse_was_down = mouse_down;
+
+        if window.is_key_pressed(Key::F, KeyRepeat::No) {
+            fog_enabled = !fog_enabled;
+        }
+        if fog_enabled {
+            // The image is already in linear colours for the post-processing
+            if post_enabled {
+                environment.apply_fog_hdr(
+                    &thread_pool,
+                    &camera,
+                    depth_buffer.get_mut(),
+                    &mut colors,
+                    WIDTH,
+                );
+            } else {
+                environment.apply_fog(
+                    &thread_pool,
+                    &camera,
+                    depth_buffer.get_mut(),
+                    buffer.get_mut(),
+                    WIDTH,
+                );
+            }
+        }
+
+        if window.is_key_pressed(Key::T, KeyRepeat::No) {
+            glass_enabled = !glass_enabled;
+        }
+        if glass_enabled {
+            // Transparent geometry last, in no particular order
+            oit.clear();
+            glass.draw_transparent(
+                &mut oit,
+                depth_buffer.get_mut(),
+                &(camera.projection() * camera.view()),
+                &[],
+                None,
+            );
+            if post_enabled {
+                oit.resolve_hdr(&thread_pool, &mut colors);
+            } else {
+                oit.resolve(&thread_pool, buffer.get_mut());
+            }
+        }
+
+        if post_enabled {
+            post.apply(&thread_pool, &mut colors, WIDTH);
+            colors_to_argb8(&colors, buffer.get_mut());
+        }
+        if window.is_key_pressed(Key::G, KeyRepeat::No) {
+            debug_enabled = !debug_enabled;
+        }
+        if window.is_key_pressed(Key::N, KeyRepeat::No) {
+            normals_enabled = !normals_enabled;
+        }
+        if debug_enabled {
+            debug_draw.grid(20.0, 1.0, Vec4::splat(0.3));
+            debug_draw.bounding_sphere(&glass.bounding_sphere(), Vec4::new(0.2, 0.6, 1.0, 1.0));
+            for object in &objects {
+                debug_draw.model_bounds(object, &instances, Vec4::new(1.0, 1.0, 0.0, 1.0));
+            }
+            // the gizmo stays visible through the geometry
+            debug_draw.depth_test = false;
+            debug_draw.axes(&Mat4::IDENTITY, 1.0);
+            debug_draw.depth_test = true;
+        }
+        if normals_enabled {
+            if let (Some(object), Some(instance)) = (objects.first(), instances.first()) {
+                let model = instance.local() * object.transform.local();
+                for mesh in &object.meshes {
+                    debug_draw.normals(mesh, &model, 0.05, Vec4::new(0.0, 1.0, 1.0, 1.0));
+                }
+            }
+        }
+        // Replaces the whole image, the debug shapes stay on top
+        debug_view.render(
+            &thread_pool,
+            &gbuffer,
+            depth_buffer.get_mut(),
+            &camera,
+            buffer.get_mut(),
+        );
+        debug_draw.draw(
+            buffer.get_mut(),
+            depth_buffer.get_mut(),
+            &camera,
+            Vec2::new(WIDTH as f32, HEIGHT as f32),
+        );
+
+        if show_occlusion {
+            ssao.debug_view(buffer.get_mut());
+        }
+
         // Render-only time
         let raster_time = raster_time.elapsed().as_millis();
-        println!("Render time: {raster_time}ms");
+        if deferred_mode {
+            println!(
+                "Render time: {raster_time}ms, {} lights over {} tiles",
+                lighting_stats.light_tiles, lighting_stats.tiles
+            );
+        } else {
+            println!(
+                "Render time: {raster_time}ms, {} triangles, {} saved by LODs",
+                lod_stats.triangles_drawn, lod_stats.triangles_saved
+            );
+        }
 
         // We unwrap here as we want this code to exit if it fails. Real applications may want to handle this in a different way
         unsafe {
@@ -0,0 +1,32 @@
+/// Surface parameters of a mesh, used by the deferred lighting pass.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Material {
+    /// 0 is a mirror, 1 fully diffuse.
+    pub roughness: f32,
+    /// 0 for dielectrics, 1 for metals tinting their reflections with the albedo.
+    pub metallic: f32,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            roughness: 0.5,
+            metallic: 0.0,
+        }
+    }
+}
+
+impl Material {
+    /// The factors of a glTF material. They only scale the metallic-roughness texture
+    /// when there is one, which is not sampled, so the defaults are used instead.
+    pub fn from_gltf(material: &gltf::Material) -> Self {
+        let pbr = material.pbr_metallic_roughness();
+        if material.index().is_none() || pbr.metallic_roughness_texture().is_some() {
+            return Self::default();
+        }
+        Self {
+            roughness: pbr.roughness_factor(),
+            metallic: pbr.metallic_factor(),
+        }
+    }
+}
@@ -1,14 +1,87 @@
+use crate::bounds::{Aabb, BoundingSphere, Frustum};
+use crate::bvh::Bvh;
+use crate::error::RusterizerError;
+use crate::gbuffer::GBufferTarget;
 use crate::geometry::*;
+use crate::lines::{Line, LineStyle, Point};
+use crate::material::Material;
+use crate::oit::OitBuffer;
 use crate::texture::*;
 
-use glam::{Mat4, UVec3, Vec2, Vec3, Vec4};
-use std::sync::Arc;
+use glam::{Mat4, UVec3, Vec2, Vec3, Vec4, Vec4Swizzles};
+use std::sync::{Arc, OnceLock};
+
+mod normals;
+mod primitives;
+mod processing;
+mod simplify;
+
+pub use normals::NormalWeighting;
+pub use processing::WeldTolerance;
+
+/// Per-vertex displacements of one glTF morph target (blend shape).
+///
+/// Every buffer has one entry per mesh vertex, missing attributes are stored as zeros.
+#[derive(Debug, Clone, Default)]
+pub struct MorphTarget {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub tangents: Vec<Vec3>,
+}
+
+impl MorphTarget {
+    fn zeroed(count: usize) -> Self {
+        Self {
+            positions: vec![Vec3::ZERO; count],
+            normals: vec![Vec3::ZERO; count],
+            tangents: vec![Vec3::ZERO; count],
+        }
+    }
+
+    // overwrites the deltas of vertices `offset..`, as far as `target` provides them
+    fn write(&mut self, offset: usize, target: &MorphTarget) {
+        let copy = |buffer: &mut Vec<Vec3>, deltas: &[Vec3]| {
+            let count = deltas.len().min(buffer.len().saturating_sub(offset));
+            buffer[offset..offset + count].copy_from_slice(&deltas[..count]);
+        };
+        copy(&mut self.positions, &target.positions);
+        copy(&mut self.normals, &target.normals);
+        copy(&mut self.tangents, &target.tangents);
+    }
+}
+
+/// How the triangles of a `Mesh` are drawn, passed to its draw calls. See
+/// `Model::polygon_mode`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PolygonMode {
+    Fill,
+    /// Every edge once, in the vertex colours and without lighting. Back faces included.
+    Wireframe(LineStyle),
+    /// Every vertex, as a square of that many pixels.
+    Points(f32),
+    /// The filled triangles with their edges drawn over in black.
+    WireframeOverFill(LineStyle),
+}
+
+impl PolygonMode {
+    // whether the triangles themselves are rasterized
+    fn fills(self) -> bool {
+        matches!(self, PolygonMode::Fill | PolygonMode::WireframeOverFill(_))
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Mesh {
     triangles: Vec<UVec3>,
     vertices: Vec<Vertex>,
     texture: Option<Arc<Texture>>,
+    occlusion_texture: Option<Arc<Texture>>,
+    material: Material,
+    morph_targets: Vec<MorphTarget>,
+    weights: Vec<f32>,
+    // computed on first use, reset whenever positions or triangles change
+    bounds: OnceLock<(Aabb, BoundingSphere)>,
+    bvh: OnceLock<Bvh>,
 }
 
 impl Mesh {
@@ -17,6 +90,12 @@ impl Mesh {
             triangles: Vec::new(),
             vertices: Vec::new(),
             texture: None,
+            occlusion_texture: None,
+            material: Material::default(),
+            morph_targets: Vec::new(),
+            weights: Vec::new(),
+            bounds: OnceLock::new(),
+            bvh: OnceLock::new(),
         }
     }
 
@@ -25,24 +104,52 @@ impl Mesh {
             triangles: Vec::new(),
             vertices: Vec::new(),
             texture: Some(texture),
+            occlusion_texture: None,
+            material: Material::default(),
+            morph_targets: Vec::new(),
+            weights: Vec::new(),
+            bounds: OnceLock::new(),
+            bvh: OnceLock::new(),
         }
     }
 
-    pub fn new_from_gltf(mesh: &gltf::Mesh, buffers: &[gltf::buffer::Data]) -> Mesh {
-        let mut positions: Vec<Vec3> = Vec::new();
-        let mut normals: Vec<Vec3> = Vec::new();
-        let mut tex_coords: Vec<Vec2> = Vec::new();
-        let mut indices = vec![];
-
+    pub fn new_from_gltf(
+        mesh: &gltf::Mesh,
+        buffers: &[gltf::buffer::Data],
+    ) -> Result<Mesh, RusterizerError> {
         let mut result = Mesh::new();
 
         for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                return Err(RusterizerError::UnsupportedGltf(format!(
+                    "primitive mode {:?}",
+                    primitive.mode()
+                )));
+            }
+
+            let mut positions: Vec<Vec3> = Vec::new();
+            let mut normals: Vec<Vec3> = Vec::new();
+            let mut tex_coords: Vec<Vec2> = Vec::new();
+            let mut colors: Vec<Vec4> = Vec::new();
+            let mut indices = vec![];
+
             let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-            if let Some(indices_reader) = reader.read_indices() {
-                indices_reader.into_u32().for_each(|i| indices.push(i));
+            match reader.read_positions() {
+                Some(positions_reader) => {
+                    positions_reader.for_each(|p| positions.push(Vec3::new(p[0], p[1], p[2])))
+                }
+                None => return Err(RusterizerError::MissingAttribute("POSITION")),
             }
-            if let Some(positions_reader) = reader.read_positions() {
-                positions_reader.for_each(|p| positions.push(Vec3::new(p[0], p[1], p[2])));
+            match reader.read_indices() {
+                Some(indices_reader) => indices_reader.into_u32().for_each(|i| indices.push(i)),
+                // non-indexed geometry, every three vertices make a triangle
+                None => indices.extend(0..positions.len() as u32),
+            }
+            if indices.len() % 3 != 0 {
+                return Err(RusterizerError::Decode(format!(
+                    "index count {} is not a multiple of 3",
+                    indices.len()
+                )));
             }
             if let Some(normals_reader) = reader.read_normals() {
                 normals_reader.for_each(|n| normals.push(Vec3::new(n[0], n[1], n[2])));
@@ -52,8 +159,38 @@ impl Mesh {
                     .into_f32()
                     .for_each(|tc| tex_coords.push(Vec2::new(tc[0], tc[1])))
             }
+            if let Some(colors_reader) = reader.read_colors(0) {
+                colors_reader
+                    .into_rgba_f32()
+                    .for_each(|c| colors.push(Vec4::from_array(c)));
+            }
 
-            let colors: Vec<Vec4> = positions.iter().map(|_| Vec4::ONE).collect();
+            let to_vec3 = |v: [f32; 3]| Vec3::from_array(v);
+            let targets: Vec<MorphTarget> = reader
+                .read_morph_targets()
+                .map(|(positions, normals, tangents)| MorphTarget {
+                    positions: positions.map_or(vec![], |p| p.map(to_vec3).collect()),
+                    normals: normals.map_or(vec![], |n| n.map(to_vec3).collect()),
+                    tangents: tangents.map_or(vec![], |t| t.map(to_vec3).collect()),
+                })
+                .collect();
+
+            // like the texture, the material of the first primitive is used for the whole mesh
+            if result.triangles.is_empty() {
+                result.material = Material::from_gltf(&primitive.material());
+            }
+
+            // the base colour factor is folded into the vertex colours
+            let base_color = Vec4::from_array(
+                primitive
+                    .material()
+                    .pbr_metallic_roughness()
+                    .base_color_factor(),
+            );
+            if colors.is_empty() {
+                colors = vec![Vec4::ONE; positions.len()];
+            }
+            colors.iter_mut().for_each(|c| *c *= base_color);
             println!("Num vertices: {:?}", positions.len() * 3);
             println!("Num indices: {:?}", indices.len());
             println!("tex_coords: {:?}", tex_coords.len());
@@ -63,10 +200,25 @@ impl Mesh {
                 .chunks_exact(3)
                 .map(|tri| UVec3::new(tri[0], tri[1], tri[2]))
                 .collect();
-            result.add_section_from_buffers(&triangles, &positions, &normals, &colors, &tex_coords)
+            result.add_section_from_buffers(
+                &triangles,
+                &positions,
+                &normals,
+                &colors,
+                &tex_coords,
+            )?;
+            result.add_morph_targets(&targets, positions.len());
         }
 
-        result
+        if !result.morph_targets.is_empty() {
+            result.weights = match mesh.weights() {
+                Some(weights) => weights.to_vec(),
+                None => vec![0.0; result.morph_targets.len()],
+            };
+            result.weights.resize(result.morph_targets.len(), 0.0);
+        }
+
+        Ok(result)
     }
 
     pub fn triangles(&self) -> &Vec<UVec3> {
@@ -85,23 +237,180 @@ impl Mesh {
         ]
     }
 
+    /// Checks that every triangle references existing vertices.
+    pub fn validate(&self) -> Result<(), RusterizerError> {
+        validate_triangles(&self.triangles, 0, self.vertices.len())
+    }
+
     pub fn from_vertices(triangles: &[UVec3], vertices: &[Vertex]) -> Self {
         let mut mesh = Mesh::new();
         mesh.add_section_from_vertices(triangles, vertices);
         mesh
     }
 
+    pub fn texture(&self) -> Option<&Arc<Texture>> {
+        self.texture.as_ref()
+    }
+
     pub fn add_texture(&mut self, texture: Arc<Texture>) {
         self.texture = Some(texture);
     }
 
+    /// Baked ambient occlusion, read from the red channel of the texture.
+    pub fn occlusion_texture(&self) -> Option<&Arc<Texture>> {
+        self.occlusion_texture.as_ref()
+    }
+
+    pub fn set_occlusion_texture(&mut self, texture: Option<Arc<Texture>>) {
+        self.occlusion_texture = texture;
+    }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+
+    pub fn set_material(&mut self, material: Material) {
+        self.material = material;
+    }
+
+    fn reset_caches(&mut self) {
+        self.bounds.take();
+        self.bvh.take();
+    }
+
+    fn bounds(&self) -> &(Aabb, BoundingSphere) {
+        self.bounds.get_or_init(|| {
+            // morph targets can move every vertex by up to the sum of its deltas
+            // (for weights between 0 and 1), the bounds cover all of those positions
+            let extents: Vec<(Vec3, Vec3)> = self
+                .vertices
+                .iter()
+                .enumerate()
+                .map(|(i, vertex)| {
+                    let position = vertex.position.xyz();
+                    self.morph_targets
+                        .iter()
+                        .fold((position, position), |(low, high), target| {
+                            let delta = target.positions[i];
+                            (low + delta.min(Vec3::ZERO), high + delta.max(Vec3::ZERO))
+                        })
+                })
+                .collect();
+
+            let points = extents.iter().flat_map(|(low, high)| [*low, *high]);
+            (
+                Aabb::from_points(points.clone()),
+                BoundingSphere::from_points(points),
+            )
+        })
+    }
+
+    /// Hierarchy over the triangles without morph targets applied, built on first use.
+    /// See `Mesh::build_bvh`.
+    pub fn bvh(&self) -> &Bvh {
+        self.bvh.get_or_init(|| self.build_bvh())
+    }
+
+    /// Bounding box of the vertices in model space, including every morph target.
+    pub fn aabb(&self) -> Aabb {
+        self.bounds().0
+    }
+
+    /// Bounding sphere of the vertices in model space, including every morph target.
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        self.bounds().1
+    }
+
+    pub fn morph_targets(&self) -> &Vec<MorphTarget> {
+        &self.morph_targets
+    }
+
+    /// The default morph target weights of the mesh, as stored in the glTF file.
+    pub fn weights(&self) -> &Vec<f32> {
+        &self.weights
+    }
+
+    pub fn set_weights(&mut self, weights: Vec<f32>) {
+        self.weights = weights;
+    }
+
+    /// Sets the morph targets of the section made of the last `count` vertices.
+    ///
+    /// Targets are matched by index, sections without a given target get zero displacements.
+    pub fn add_morph_targets(&mut self, targets: &[MorphTarget], count: usize) {
+        self.reset_caches();
+        let offset = self.vertices.len() - count;
+        while self.morph_targets.len() < targets.len() {
+            self.morph_targets
+                .push(MorphTarget::zeroed(self.vertices.len()));
+            self.weights.push(0.0);
+        }
+
+        for (morph_target, target) in self.morph_targets.iter_mut().zip(targets) {
+            morph_target.write(offset, target);
+        }
+    }
+
+    // keeps every morph target buffer as long as the vertex buffer
+    fn pad_morph_targets(&mut self) {
+        let count = self.vertices.len();
+        for target in &mut self.morph_targets {
+            target.positions.resize(count, Vec3::ZERO);
+            target.normals.resize(count, Vec3::ZERO);
+            target.tangents.resize(count, Vec3::ZERO);
+        }
+    }
+
+    // copies a vertex (and its morph target deltas) to the end of the buffer
+    fn duplicate_vertex(&mut self, index: usize) -> u32 {
+        self.vertices.push(self.vertices[index]);
+        for target in &mut self.morph_targets {
+            target.positions.push(target.positions[index]);
+            target.normals.push(target.normals[index]);
+            target.tangents.push(target.tangents[index]);
+        }
+        (self.vertices.len() - 1) as u32
+    }
+
+    /// Blends the morph targets into a copy of the vertices using `weights`.
+    pub fn morphed_vertices(&self, weights: &[f32]) -> Vec<Vertex> {
+        let mut vertices = self.vertices.clone();
+        let mut morphed = false;
+
+        for (target, weight) in self.morph_targets.iter().zip(weights) {
+            if *weight == 0.0 {
+                continue;
+            }
+            morphed = true;
+            let deltas = target.positions.iter().zip(&target.normals);
+            for (vertex, (position, normal)) in vertices.iter_mut().zip(deltas) {
+                vertex.position += (*position * *weight).extend(0.0);
+                vertex.normal += *normal * *weight;
+            }
+        }
+
+        if morphed {
+            vertices
+                .iter_mut()
+                .for_each(|v| v.normal = v.normal.normalize_or_zero());
+        }
+
+        vertices
+    }
+
     pub fn add_section_from_vertices(&mut self, triangles: &[UVec3], vertices: &[Vertex]) {
         let offset = self.vertices.len() as u32;
         let triangles: Vec<UVec3> = triangles.iter().map(|tri| *tri + offset).collect();
         self.triangles.extend_from_slice(&triangles);
         self.vertices.extend_from_slice(vertices);
+        self.pad_morph_targets();
+        self.reset_caches();
     }
 
+    /// Appends a section built from separate attribute buffers, indices are relative to it.
+    ///
+    /// `colors` and `uvs` may be empty, every other buffer needs one value per position.
+    /// Empty `normals` are generated from the triangles (area weighted).
     pub fn add_section_from_buffers(
         &mut self,
         triangles: &[UVec3],
@@ -109,12 +418,33 @@ impl Mesh {
         normals: &[Vec3],
         colors: &[Vec4],
         uvs: &[Vec2],
-    ) {
-        self.triangles.extend_from_slice(triangles);
-
+    ) -> Result<(), RusterizerError> {
         let has_uvs = !uvs.is_empty();
         let has_colors = !colors.is_empty();
 
+        let generated;
+        let normals = if normals.is_empty() {
+            generated = normals::smooth_normals(triangles, positions);
+            &generated
+        } else {
+            normals
+        };
+
+        if normals.len() < positions.len() {
+            return Err(RusterizerError::MissingAttribute("NORMAL"));
+        }
+        if has_colors && colors.len() < positions.len() {
+            return Err(RusterizerError::MissingAttribute("COLOR_0"));
+        }
+        if has_uvs && uvs.len() < positions.len() {
+            return Err(RusterizerError::MissingAttribute("TEXCOORD_0"));
+        }
+        validate_triangles(triangles, self.triangles.len(), positions.len())?;
+
+        let offset = self.vertices.len() as u32;
+        let triangles: Vec<UVec3> = triangles.iter().map(|tri| *tri + offset).collect();
+        self.triangles.extend_from_slice(&triangles);
+
         for i in 0..positions.len() {
             let vertex = Vertex::new(
                 positions[i].extend(1.0),
@@ -124,7 +454,40 @@ impl Mesh {
             );
             self.vertices.push(vertex);
         }
+        self.pad_morph_targets();
+        self.reset_caches();
+
+        Ok(())
+    }
+}
+
+// `first` is the number of triangles before this section, used for error reporting
+fn validate_triangles(
+    triangles: &[UVec3],
+    first: usize,
+    vertex_count: usize,
+) -> Result<(), RusterizerError> {
+    for (i, triangle) in triangles.iter().enumerate() {
+        for index in triangle.to_array() {
+            if index as usize >= vertex_count {
+                return Err(RusterizerError::IndexOutOfRange {
+                    triangle: first + i,
+                    index,
+                    vertex_count,
+                });
+            }
+        }
     }
+    Ok(())
+}
+
+// `None` for triangles referencing missing vertices, so malformed meshes are skipped when drawing
+fn triangle_vertices(vertices: &[Vertex], triangle: &UVec3) -> Option<[Vertex; 3]> {
+    Some([
+        *vertices.get(triangle.x as usize)?,
+        *vertices.get(triangle.y as usize)?,
+        *vertices.get(triangle.z as usize)?,
+    ])
 }
 
 impl Default for Mesh {
@@ -133,36 +496,340 @@ impl Default for Mesh {
     }
 }
 
-impl Object for Mesh {
-    fn draw(
+impl Mesh {
+    /// Draws the mesh with its morph targets blended by `weights`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_with_weights(
+        &self,
+        weights: &[f32],
+        polygon_mode: PolygonMode,
+        buffer: &mut [u32],
+        depth_buffer: &mut [f32],
+        model: &Mat4,
+        mvp: &Mat4,
+        viewport_size: Vec2,
+    ) {
+        self.draw_with_target(
+            weights,
+            polygon_mode,
+            buffer,
+            depth_buffer,
+            model,
+            mvp,
+            None,
+            viewport_size,
+        );
+    }
+
+    /// Same as `draw_with_weights`, also filling the G-buffer attachments of `target`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_with_target(
+        &self,
+        weights: &[f32],
+        polygon_mode: PolygonMode,
+        buffer: &mut [u32],
+        depth_buffer: &mut [f32],
+        model: &Mat4,
+        mvp: &Mat4,
+        mut target: Option<&mut GBufferTarget>,
+        viewport_size: Vec2,
+    ) {
+        // cheaper than sending every triangle to be clipped away
+        if !Frustum::from_matrix(mvp).intersects_aabb(&self.aabb()) {
+            return;
+        }
+        if let Some(target) = target.as_deref_mut() {
+            target.material = self.material;
+        }
+
+        let morphed;
+        let vertices = if self.morph_targets.is_empty() {
+            &self.vertices
+        } else {
+            morphed = self.morphed_vertices(weights);
+            &morphed
+        };
+        self.draw_vertices(
+            vertices,
+            polygon_mode,
+            buffer,
+            depth_buffer,
+            model,
+            mvp,
+            target,
+            viewport_size,
+        );
+    }
+
+    /// Draws one copy of the mesh per entry of `instances` (model matrices), sharing
+    /// a single morphed vertex buffer. `tints`, when given, multiplies the colour of each instance.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_instanced(
+        &self,
+        weights: &[f32],
+        polygon_mode: PolygonMode,
+        buffer: &mut Vec<u32>,
+        depth_buffer: &mut Vec<f32>,
+        view_projection: &Mat4,
+        instances: &[Mat4],
+        tints: Option<&[Vec4]>,
+        viewport_size: Vec2,
+    ) {
+        // skip the instances outside of the view before morphing anything
+        let aabb = self.aabb();
+        let visible: Vec<usize> = (0..instances.len())
+            .filter(|i| {
+                Frustum::from_matrix(&(*view_projection * instances[*i])).intersects_aabb(&aabb)
+            })
+            .collect();
+        if visible.is_empty() {
+            return;
+        }
+        let instances: Vec<Mat4> = visible.iter().map(|i| instances[*i]).collect();
+        let tints: Option<Vec<Vec4>> = tints.map(|tints| {
+            visible
+                .iter()
+                .map(|i| tints.get(*i).copied().unwrap_or(Vec4::ONE))
+                .collect()
+        });
+
+        let morphed;
+        let vertices = if self.morph_targets.is_empty() {
+            &self.vertices
+        } else {
+            morphed = self.morphed_vertices(weights);
+            &morphed
+        };
+        self.draw_instances(
+            vertices,
+            polygon_mode,
+            buffer,
+            depth_buffer,
+            view_projection,
+            &instances,
+            tints.as_deref(),
+            viewport_size,
+        );
+    }
+
+    /// Draws one copy of the mesh per entry of `instances` into `oit`, see
+    /// `Model::draw_transparent`. The alpha comes from the vertex colours, tints and texture.
+    pub fn draw_transparent(
+        &self,
+        weights: &[f32],
+        oit: &mut OitBuffer,
+        depth_buffer: &[f32],
+        view_projection: &Mat4,
+        instances: &[Mat4],
+        tints: Option<&[Vec4]>,
+    ) {
+        // skip the instances outside of the view before morphing anything
+        let aabb = self.aabb();
+        let visible: Vec<usize> = (0..instances.len())
+            .filter(|i| {
+                Frustum::from_matrix(&(*view_projection * instances[*i])).intersects_aabb(&aabb)
+            })
+            .collect();
+        if visible.is_empty() {
+            return;
+        }
+
+        let morphed;
+        let vertices = if self.morph_targets.is_empty() {
+            &self.vertices
+        } else {
+            morphed = self.morphed_vertices(weights);
+            &morphed
+        };
+        for i in visible {
+            let model = &instances[i];
+            let mvp = *view_projection * *model;
+            let tint = tints.and_then(|t| t.get(i)).copied().unwrap_or(Vec4::ONE);
+
+            for triangle_indices in &self.triangles {
+                let Some(mut triangle_vertices) = triangle_vertices(vertices, triangle_indices)
+                else {
+                    continue;
+                };
+                triangle_vertices.iter_mut().for_each(|v| v.color *= tint);
+
+                let triangle = match &self.texture {
+                    Some(texture) => Triangle::new_with_texture(triangle_vertices, texture.clone()),
+                    None => Triangle::new(triangle_vertices),
+                }
+                .with_occlusion(self.occlusion_texture.clone());
+                triangle.draw_transparent(oit, depth_buffer, model, &mvp);
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn draw_instances(
         &self,
+        vertices: &[Vertex],
+        polygon_mode: PolygonMode,
         buffer: &mut Vec<u32>,
         depth_buffer: &mut Vec<f32>,
+        view_projection: &Mat4,
+        instances: &[Mat4],
+        tints: Option<&[Vec4]>,
+        viewport_size: Vec2,
+    ) {
+        for (i, model) in instances.iter().enumerate() {
+            let mvp = *view_projection * *model;
+            let tint = tints.and_then(|t| t.get(i)).copied().unwrap_or(Vec4::ONE);
+
+            if polygon_mode.fills() {
+                for triangle_indices in &self.triangles {
+                    let Some(mut triangle_vertices) = triangle_vertices(vertices, triangle_indices)
+                    else {
+                        continue;
+                    };
+                    triangle_vertices.iter_mut().for_each(|v| v.color *= tint);
+
+                    let triangle = match &self.texture {
+                        Some(texture) => {
+                            Triangle::new_with_texture(triangle_vertices, texture.clone())
+                        }
+                        None => Triangle::new(triangle_vertices),
+                    }
+                    .with_occlusion(self.occlusion_texture.clone());
+                    triangle.draw(buffer, depth_buffer, model, &mvp, viewport_size);
+                }
+            }
+            self.draw_topology(
+                vertices,
+                polygon_mode,
+                buffer,
+                depth_buffer,
+                &mvp,
+                tint,
+                viewport_size,
+            );
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn draw_vertices(
+        &self,
+        vertices: &[Vertex],
+        polygon_mode: PolygonMode,
+        buffer: &mut [u32],
+        depth_buffer: &mut [f32],
         model: &Mat4,
         mvp: &Mat4,
+        mut target: Option<&mut GBufferTarget>,
         viewport_size: Vec2,
     ) {
-        for triangle_indices in self.triangles.clone() {
-            let triangle_vertices: [Vertex; 3] = self.get_vertices_from_triangle(triangle_indices);
-            if self.texture.is_some() {
-                let triangle = Triangle::new_with_texture(
-                    triangle_vertices,
-                    self.texture.as_ref().unwrap().clone(),
+        if polygon_mode.fills() {
+            for (i, triangle_indices) in self.triangles.iter().enumerate() {
+                let Some(triangle_vertices) = triangle_vertices(vertices, triangle_indices) else {
+                    continue;
+                };
+                if let Some(target) = target.as_deref_mut() {
+                    target.triangle_id = i as u32;
+                }
+                let triangle = match &self.texture {
+                    Some(texture) => Triangle::new_with_texture(triangle_vertices, texture.clone()),
+                    None => Triangle::new(triangle_vertices),
+                }
+                .with_occlusion(self.occlusion_texture.clone());
+                triangle.draw_to(
+                    buffer,
+                    depth_buffer,
+                    model,
+                    mvp,
+                    target.as_deref_mut(),
+                    viewport_size,
                 );
-                triangle.draw(buffer, depth_buffer, model, mvp, viewport_size);
-            } else {
-                let triangle = Triangle::new(triangle_vertices);
-                triangle.draw(buffer, depth_buffer, model, mvp, viewport_size);
             }
         }
+        self.draw_topology(
+            vertices,
+            polygon_mode,
+            buffer,
+            depth_buffer,
+            mvp,
+            Vec4::ONE,
+            viewport_size,
+        );
+    }
+
+    // the edges or the vertices of the polygon modes drawing them, unlit
+    #[allow(clippy::too_many_arguments)]
+    fn draw_topology(
+        &self,
+        vertices: &[Vertex],
+        polygon_mode: PolygonMode,
+        buffer: &mut [u32],
+        depth_buffer: &mut [f32],
+        mvp: &Mat4,
+        tint: Vec4,
+        viewport_size: Vec2,
+    ) {
+        let (style, wire_color) = match polygon_mode {
+            PolygonMode::Fill => return,
+            PolygonMode::Wireframe(style) => (style, None),
+            PolygonMode::WireframeOverFill(style) => (style, Some(Vec4::new(0.0, 0.0, 0.0, 1.0))),
+            PolygonMode::Points(size) => {
+                for vertex in vertices {
+                    let vertex = Vertex {
+                        color: vertex.color * tint,
+                        ..*vertex
+                    };
+                    Point::new(vertex, size).draw(buffer, depth_buffer, mvp, viewport_size);
+                }
+                return;
+            }
+        };
+
+        // edges shared by two triangles are drawn once
+        let mut edges: Vec<(u32, u32)> = self
+            .triangles
+            .iter()
+            .filter(|t| t.max_element() < vertices.len() as u32)
+            .flat_map(|t| [(t.x, t.y), (t.y, t.z), (t.z, t.x)])
+            .map(|(a, b)| (a.min(b), a.max(b)))
+            .collect();
+        edges.sort_unstable();
+        edges.dedup();
+
+        for (a, b) in edges {
+            let mut line = Line::new([vertices[a as usize], vertices[b as usize]]);
+            for vertex in &mut line.vertices {
+                vertex.color = wire_color.unwrap_or(vertex.color * tint);
+            }
+            line.draw(buffer, depth_buffer, mvp, style, viewport_size);
+        }
+    }
+}
+
+impl Object for Mesh {
+    fn draw(
+        &self,
+        buffer: &mut Vec<u32>,
+        depth_buffer: &mut Vec<f32>,
+        model: &Mat4,
+        mvp: &Mat4,
+        viewport_size: Vec2,
+    ) {
+        self.draw_with_weights(
+            &self.weights,
+            PolygonMode::Fill,
+            buffer,
+            depth_buffer,
+            model,
+            mvp,
+            viewport_size,
+        );
     }
 
     fn get_area(&self) -> f32 {
         let mut area: f32 = 0.0;
-        for triangle_indices in self.triangles.clone() {
-            let triangle_vertices: [Vertex; 3] = self.get_vertices_from_triangle(triangle_indices);
-            let triangle = Triangle::new(triangle_vertices);
-            area += triangle.get_area();
+        for triangle_indices in &self.triangles {
+            if let Some(triangle_vertices) = triangle_vertices(&self.vertices, triangle_indices) {
+                area += Triangle::new(triangle_vertices).get_area();
+            }
         }
         area
     }
@@ -0,0 +1,180 @@
+use super::Mesh;
+
+use glam::{UVec3, Vec3, Vec4Swizzles};
+use std::collections::HashMap;
+
+/// How face normals contribute to a smooth vertex normal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalWeighting {
+    /// Larger triangles pull harder.
+    Area,
+    /// Each face contributes by the angle of its corner at the vertex,
+    /// independent of how the surface is tessellated.
+    Angle,
+}
+
+/// Area-weighted smooth normals for indexed positions, used by the loaders when a
+/// section has no normals of its own.
+pub(crate) fn smooth_normals(triangles: &[UVec3], positions: &[Vec3]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::ZERO; positions.len()];
+
+    for triangle in triangles {
+        let [a, b, c] = triangle.to_array().map(|i| i as usize);
+        if a >= positions.len() || b >= positions.len() || c >= positions.len() {
+            continue;
+        }
+        // the length of the cross product is twice the triangle area
+        let normal = (positions[b] - positions[a]).cross(positions[c] - positions[a]);
+        normals[a] += normal;
+        normals[b] += normal;
+        normals[c] += normal;
+    }
+
+    normals.iter().map(|n| n.normalize_or_zero()).collect()
+}
+
+impl Mesh {
+    pub(super) fn corner_positions(&self, triangle: UVec3) -> [Vec3; 3] {
+        triangle
+            .to_array()
+            .map(|i| self.vertices[i as usize].position.xyz())
+    }
+
+    // drops the triangles referencing missing vertices, meshes built in code are not checked
+    fn remove_invalid_triangles(&mut self) {
+        let count = self.vertices.len();
+        self.triangles
+            .retain(|t| t.to_array().iter().all(|i| (*i as usize) < count));
+        self.reset_caches();
+    }
+
+    /// Gives every triangle its own vertices, all facing along the triangle normal.
+    ///
+    /// Triangles referencing missing vertices are dropped.
+    pub fn compute_flat_normals(&mut self) {
+        self.remove_invalid_triangles();
+        let triangles = std::mem::take(&mut self.triangles);
+
+        let mut vertices = Vec::with_capacity(triangles.len() * 3);
+        let mut corners = Vec::with_capacity(triangles.len() * 3);
+
+        for triangle in &triangles {
+            let [p0, p1, p2] = self.corner_positions(*triangle);
+            let normal = (p1 - p0).cross(p2 - p0).normalize_or_zero();
+
+            for index in triangle.to_array() {
+                let mut vertex = self.vertices[index as usize];
+                vertex.normal = normal;
+                vertices.push(vertex);
+                corners.push(index as usize);
+            }
+        }
+
+        for target in &mut self.morph_targets {
+            target.positions = corners.iter().map(|i| target.positions[*i]).collect();
+            target.normals = corners.iter().map(|i| target.normals[*i]).collect();
+            target.tangents = corners.iter().map(|i| target.tangents[*i]).collect();
+        }
+
+        self.vertices = vertices;
+        self.triangles = (0..triangles.len() as u32)
+            .map(|i| UVec3::new(i * 3, i * 3 + 1, i * 3 + 2))
+            .collect();
+    }
+
+    /// Recomputes vertex normals by averaging the normals of the faces around each vertex.
+    ///
+    /// Vertices sharing a position are smoothed together even if they are split (UV seams).
+    /// With a `crease_angle` (radians), faces meeting at a sharper angle keep a hard edge,
+    /// splitting vertices where needed.
+    ///
+    /// Triangles referencing missing vertices are dropped.
+    pub fn compute_smooth_normals(
+        &mut self,
+        weighting: NormalWeighting,
+        crease_angle: Option<f32>,
+    ) {
+        self.remove_invalid_triangles();
+        let face_normals: Vec<Vec3> = self
+            .triangles
+            .iter()
+            .map(|triangle| {
+                let [p0, p1, p2] = self.corner_positions(*triangle);
+                (p1 - p0).cross(p2 - p0)
+            })
+            .collect();
+
+        // contribution of every corner (triangle * 3 + k) to the normals around it
+        let mut contributions = Vec::with_capacity(self.triangles.len() * 3);
+        for (triangle, face_normal) in self.triangles.iter().zip(&face_normals) {
+            let positions = self.corner_positions(*triangle);
+            for k in 0..3 {
+                let weight = match weighting {
+                    NormalWeighting::Area => *face_normal,
+                    NormalWeighting::Angle => {
+                        let e0 = positions[(k + 1) % 3] - positions[k];
+                        let e1 = positions[(k + 2) % 3] - positions[k];
+                        face_normal.normalize_or_zero() * e0.angle_between(e1)
+                    }
+                };
+                contributions.push(if weight.is_nan() { Vec3::ZERO } else { weight });
+            }
+        }
+
+        // corners grouped by position, so split vertices still get smoothed together
+        let mut groups: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+        for (t, triangle) in self.triangles.iter().enumerate() {
+            for (k, position) in self.corner_positions(*triangle).iter().enumerate() {
+                let key = position.to_array().map(f32::to_bits);
+                groups.entry(key).or_default().push(t * 3 + k);
+            }
+        }
+
+        let cos_crease = crease_angle.map(f32::cos);
+        let unit_normals: Vec<Vec3> = face_normals.iter().map(|n| n.normalize_or_zero()).collect();
+
+        let mut corner_normals = vec![Vec3::ZERO; contributions.len()];
+        for corners in groups.values() {
+            for &corner in corners {
+                let face = unit_normals[corner / 3];
+                let mut normal = Vec3::ZERO;
+                for &other in corners {
+                    let smooth = match cos_crease {
+                        Some(cos) => face.dot(unit_normals[other / 3]) >= cos,
+                        None => true,
+                    };
+                    if smooth {
+                        normal += contributions[other];
+                    }
+                }
+                corner_normals[corner] = normal.normalize_or_zero();
+            }
+        }
+
+        // write back, splitting vertices whose corners ended up with different normals
+        let mut assigned: Vec<Vec<(Vec3, u32)>> = vec![vec![]; self.vertices.len()];
+        for t in 0..self.triangles.len() {
+            for k in 0..3 {
+                let index = self.triangles[t][k];
+                let normal = corner_normals[t * 3 + k];
+                let slots = &mut assigned[index as usize];
+
+                let target = match slots.iter().find(|(n, _)| n.dot(normal) > 0.9999) {
+                    Some((_, target)) => *target,
+                    None if slots.is_empty() => {
+                        self.vertices[index as usize].normal = normal;
+                        slots.push((normal, index));
+                        index
+                    }
+                    None => {
+                        let copy = self.duplicate_vertex(index as usize);
+                        self.vertices[copy as usize].normal = normal;
+                        assigned[index as usize].push((normal, copy));
+                        copy
+                    }
+                };
+                self.triangles[t][k] = target;
+            }
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use super::Mesh;
+
+use glam::{UVec2, UVec3, Vec2, Vec3};
+use std::collections::HashMap;
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+// collects the buffers of a generated mesh
+#[derive(Default)]
+struct Builder {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<Vec2>,
+    triangles: Vec<UVec3>,
+}
+
+impl Builder {
+    fn vertex(&mut self, position: Vec3, normal: Vec3, uv: Vec2) -> u32 {
+        self.positions.push(position);
+        self.normals.push(normal);
+        self.uvs.push(uv);
+        (self.positions.len() - 1) as u32
+    }
+
+    // pushes the triangle unless it collapsed to a line, as happens at the poles
+    fn triangle(&mut self, a: u32, b: u32, c: u32) {
+        let [pa, pb, pc] = [a, b, c].map(|i| self.positions[i as usize]);
+        let edge = (pb - pa).length_squared().max((pc - pa).length_squared());
+        if (pb - pa).cross(pc - pa).length_squared() > edge * edge * 1e-10 {
+            self.triangles.push(UVec3::new(a, b, c));
+        }
+    }
+
+    // a parametric surface sampled on a `columns` x `rows` grid of quads
+    //
+    // `surface` maps `(u, v)` in `[0, 1]` to position, normal and texture coordinate.
+    // Seen from the side the normal points to, u has to grow to the right and v
+    // downwards, like texture coordinates, for the triangles to face outwards.
+    fn grid(&mut self, columns: u32, rows: u32, surface: impl Fn(f32, f32) -> (Vec3, Vec3, Vec2)) {
+        let first = self.positions.len() as u32;
+
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let (position, normal, uv) =
+                    surface(column as f32 / columns as f32, row as f32 / rows as f32);
+                self.vertex(position, normal, uv);
+            }
+        }
+
+        let index = |column: u32, row: u32| first + row * (columns + 1) + column;
+        for row in 0..rows {
+            for column in 0..columns {
+                let (top_left, top_right) = (index(column, row), index(column + 1, row));
+                let (bottom_left, bottom_right) =
+                    (index(column, row + 1), index(column + 1, row + 1));
+                self.triangle(top_left, bottom_left, top_right);
+                self.triangle(top_right, bottom_left, bottom_right);
+            }
+        }
+    }
+
+    // a flat disc of `segments` triangles around `center`, facing up or down
+    fn disc(&mut self, center: Vec3, radius: f32, segments: u32, facing_up: bool) {
+        let normal = if facing_up { Vec3::Y } else { Vec3::NEG_Y };
+        // seen from the facing side, -z is up in the texture when looking down
+        let flip = if facing_up { 1.0 } else { -1.0 };
+        let center_index = self.vertex(center, normal, Vec2::splat(0.5));
+
+        let first = self.positions.len() as u32;
+        for segment in 0..=segments {
+            let angle = segment as f32 / segments as f32 * TAU;
+            let (sin, cos) = angle.sin_cos();
+            self.vertex(
+                center + Vec3::new(sin, 0.0, cos) * radius,
+                normal,
+                Vec2::new(0.5 + 0.5 * sin, 0.5 + 0.5 * cos * flip),
+            );
+        }
+
+        for segment in 0..segments {
+            let (a, b) = (first + segment, first + segment + 1);
+            if facing_up {
+                self.triangle(center_index, a, b);
+            } else {
+                self.triangle(center_index, b, a);
+            }
+        }
+    }
+
+    fn build(self) -> Mesh {
+        let mut mesh = Mesh::new();
+        mesh.add_section_from_buffers(
+            &self.triangles,
+            &self.positions,
+            &self.normals,
+            &[],
+            &self.uvs,
+        )
+        .expect("generated buffers are consistent");
+        mesh
+    }
+}
+
+// unit vector around the y axis, facing +z at angle 0 and +x at a quarter turn
+fn around_y(angle: f32) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    Vec3::new(sin, 0.0, cos)
+}
+
+// generated shapes are centred on the origin with +y up, triangles wind
+// counter-clockwise seen from outside and texture coordinates wrap once around
+impl Mesh {
+    /// A plane in the xz plane facing +y, split into `subdivisions` quads.
+    pub fn new_plane(size: Vec2, subdivisions: UVec2) -> Mesh {
+        let subdivisions = subdivisions.max(UVec2::ONE);
+        let mut builder = Builder::default();
+        builder.grid(subdivisions.x, subdivisions.y, |u, v| {
+            let position = Vec3::new((u - 0.5) * size.x, 0.0, (v - 0.5) * size.y);
+            (position, Vec3::Y, Vec2::new(u, v))
+        });
+        builder.build()
+    }
+
+    /// A box with hard edges, every face split into `subdivisions` x `subdivisions`
+    /// quads and mapped to the whole texture.
+    pub fn new_cube(size: Vec3, subdivisions: u32) -> Mesh {
+        let subdivisions = subdivisions.max(1);
+        // normal, texture right and texture down of each face
+        let faces = [
+            (Vec3::X, Vec3::NEG_Z, Vec3::NEG_Y),
+            (Vec3::NEG_X, Vec3::Z, Vec3::NEG_Y),
+            (Vec3::Y, Vec3::X, Vec3::Z),
+            (Vec3::NEG_Y, Vec3::X, Vec3::NEG_Z),
+            (Vec3::Z, Vec3::X, Vec3::NEG_Y),
+            (Vec3::NEG_Z, Vec3::NEG_X, Vec3::NEG_Y),
+        ];
+
+        let mut builder = Builder::default();
+        for (normal, right, down) in faces {
+            builder.grid(subdivisions, subdivisions, |u, v| {
+                let position = normal * 0.5 + right * (u - 0.5) + down * (v - 0.5);
+                (position * size, normal, Vec2::new(u, v))
+            });
+        }
+        builder.build()
+    }
+
+    /// A sphere of `segments` slices around the y axis and `rings` stacks from pole to pole.
+    pub fn new_uv_sphere(radius: f32, segments: u32, rings: u32) -> Mesh {
+        let mut builder = Builder::default();
+        builder.grid(segments.max(3), rings.max(2), |u, v| {
+            let (sin, cos) = (v * PI).sin_cos();
+            let normal = around_y(u * TAU) * sin + Vec3::Y * cos;
+            (normal * radius, normal, Vec2::new(u, v))
+        });
+        builder.build()
+    }
+
+    /// A sphere made by splitting the faces of an icosahedron `subdivisions` times,
+    /// the triangles are close to equal in size unlike on a UV sphere.
+    pub fn new_icosphere(radius: f32, subdivisions: u32) -> Mesh {
+        let t = (1.0 + 5.0_f32.sqrt()) / 2.0;
+        let mut points: Vec<Vec3> = [
+            (-1.0, t, 0.0),
+            (1.0, t, 0.0),
+            (-1.0, -t, 0.0),
+            (1.0, -t, 0.0),
+            (0.0, -1.0, t),
+            (0.0, 1.0, t),
+            (0.0, -1.0, -t),
+            (0.0, 1.0, -t),
+            (t, 0.0, -1.0),
+            (t, 0.0, 1.0),
+            (-t, 0.0, -1.0),
+            (-t, 0.0, 1.0),
+        ]
+        .iter()
+        .map(|&(x, y, z)| Vec3::new(x, y, z).normalize())
+        .collect();
+
+        let mut faces: Vec<[u32; 3]> = vec![
+            [0, 11, 5],
+            [0, 5, 1],
+            [0, 1, 7],
+            [0, 7, 10],
+            [0, 10, 11],
+            [1, 5, 9],
+            [5, 11, 4],
+            [11, 10, 2],
+            [10, 7, 6],
+            [7, 1, 8],
+            [3, 9, 4],
+            [3, 4, 2],
+            [3, 2, 6],
+            [3, 6, 8],
+            [3, 8, 9],
+            [4, 9, 5],
+            [2, 4, 11],
+            [6, 2, 10],
+            [8, 6, 7],
+            [9, 8, 1],
+        ];
+
+        for _ in 0..subdivisions {
+            // edges are shared by two faces, their midpoint must be too
+            let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+            let mut midpoint = |a: u32, b: u32| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    let point = (points[a as usize] + points[b as usize]).normalize();
+                    points.push(point);
+                    (points.len() - 1) as u32
+                })
+            };
+
+            faces = faces
+                .iter()
+                .flat_map(|&[a, b, c]| {
+                    let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                    [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+                })
+                .collect();
+        }
+
+        let uv = |point: Vec3| {
+            let u = point.x.atan2(point.z) / TAU;
+            Vec2::new(u.rem_euclid(1.0), point.y.clamp(-1.0, 1.0).acos() / PI)
+        };
+
+        let mut builder = Builder::default();
+        for point in &points {
+            builder.vertex(*point * radius, *point, uv(*point));
+        }
+
+        // triangles crossing the texture seam get their own copies of the vertices
+        // on the u = 0 side, shifted to u > 1 so the texture does not wrap backwards
+        let mut wrapped: HashMap<u32, u32> = HashMap::new();
+        for face in faces {
+            let us = face.map(|i| builder.uvs[i as usize].x);
+            let crosses_seam = us.iter().any(|u| *u > 0.75) && us.iter().any(|u| *u < 0.25);
+
+            let [a, b, c] = face.map(|i| {
+                if !crosses_seam || builder.uvs[i as usize].x >= 0.5 {
+                    return i;
+                }
+                *wrapped.entry(i).or_insert_with(|| {
+                    let (position, normal) =
+                        (builder.positions[i as usize], builder.normals[i as usize]);
+                    let uv = builder.uvs[i as usize] + Vec2::X;
+                    builder.vertex(position, normal, uv)
+                })
+            });
+            builder.triangle(a, b, c);
+        }
+        builder.build()
+    }
+
+    /// A closed cylinder along the y axis, `rings` splits the side into stacks.
+    pub fn new_cylinder(radius: f32, height: f32, segments: u32, rings: u32) -> Mesh {
+        let segments = segments.max(3);
+        let mut builder = Builder::default();
+        builder.grid(segments, rings.max(1), |u, v| {
+            let normal = around_y(u * TAU);
+            let position = normal * radius + Vec3::Y * (0.5 - v) * height;
+            (position, normal, Vec2::new(u, v))
+        });
+        builder.disc(Vec3::Y * height * 0.5, radius, segments, true);
+        builder.disc(Vec3::NEG_Y * height * 0.5, radius, segments, false);
+        builder.build()
+    }
+
+    /// A closed cone along the y axis with the tip at the top.
+    pub fn new_cone(radius: f32, height: f32, segments: u32, rings: u32) -> Mesh {
+        let segments = segments.max(3);
+        let mut builder = Builder::default();
+        builder.grid(segments, rings.max(1), |u, v| {
+            let around = around_y(u * TAU);
+            // the slope leans the normal up by the same angle the side leans in
+            let normal = (around * height + Vec3::Y * radius).normalize_or_zero();
+            let position = around * radius * v + Vec3::Y * (0.5 - v) * height;
+            (position, normal, Vec2::new(u, v))
+        });
+        builder.disc(Vec3::NEG_Y * height * 0.5, radius, segments, false);
+        builder.build()
+    }
+
+    /// A torus around the y axis, `major_radius` from the centre to the middle of the
+    /// tube and `minor_radius` the radius of the tube.
+    pub fn new_torus(
+        major_radius: f32,
+        minor_radius: f32,
+        major_segments: u32,
+        minor_segments: u32,
+    ) -> Mesh {
+        let mut builder = Builder::default();
+        builder.grid(major_segments.max(3), minor_segments.max(3), |u, v| {
+            let around = around_y(u * TAU);
+            // v starts at the top of the tube and goes over the outside first
+            let (sin, cos) = (FRAC_PI_2 - v * TAU).sin_cos();
+            let normal = around * cos + Vec3::Y * sin;
+            let position = around * major_radius + normal * minor_radius;
+            (position, normal, Vec2::new(u, v))
+        });
+        builder.build()
+    }
+
+    /// A cylinder with hemispheres for caps, `height` includes the caps.
+    /// `rings` is the number of stacks in each hemisphere.
+    pub fn new_capsule(radius: f32, height: f32, segments: u32, rings: u32) -> Mesh {
+        let rings = rings.max(1);
+        let half_length = (height * 0.5 - radius).max(0.0);
+        // texture v follows the length of the profile
+        let arc = FRAC_PI_2 * radius;
+        let total = 2.0 * arc + 2.0 * half_length;
+
+        // one stack per hemisphere ring plus the straight part in the middle
+        let rows = 2 * rings + 1;
+        let mut builder = Builder::default();
+        builder.grid(segments.max(3), rows, |u, v| {
+            let row = (v * rows as f32).round() as u32;
+            let (angle, offset, distance) = if row <= rings {
+                let angle = row as f32 / rings as f32 * FRAC_PI_2;
+                (angle, half_length, angle * radius)
+            } else {
+                let angle = FRAC_PI_2 + (row - rings - 1) as f32 / rings as f32 * FRAC_PI_2;
+                (angle, -half_length, angle * radius + 2.0 * half_length)
+            };
+
+            let (sin, cos) = angle.sin_cos();
+            let normal = around_y(u * TAU) * sin + Vec3::Y * cos;
+            let position = normal * radius + Vec3::Y * offset;
+            let v = if total > 0.0 { distance / total } else { v };
+            (position, normal, Vec2::new(u, v))
+        });
+        builder.build()
+    }
+}
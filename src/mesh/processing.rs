@@ -0,0 +1,548 @@
+use super::{Mesh, MorphTarget};
+
+use glam::{IVec3, Mat3, Mat4, UVec3, Vec3Swizzles, Vec4Swizzles};
+use std::collections::HashMap;
+
+/// How far apart the attributes of two vertices may be for `Mesh::weld_vertices`
+/// to merge them. Distances are euclidean.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WeldTolerance {
+    pub position: f32,
+    pub normal: f32,
+    pub uv: f32,
+    pub color: f32,
+}
+
+impl Default for WeldTolerance {
+    fn default() -> Self {
+        Self {
+            position: 1e-5,
+            normal: 1e-3,
+            uv: 1e-5,
+            color: 1e-3,
+        }
+    }
+}
+
+// vertex cache optimization after Tom Forsyth, "Linear-Speed Vertex Cache Optimisation"
+const CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+fn vertex_score(cache_position: Option<usize>, remaining_triangles: usize) -> f32 {
+    if remaining_triangles == 0 {
+        return -1.0;
+    }
+
+    let cache_score = match cache_position {
+        None => 0.0,
+        // the vertices of the last triangle get a fixed score, so the next one
+        // does not simply reuse the same edge over and over
+        Some(position) if position < 3 => LAST_TRIANGLE_SCORE,
+        Some(position) => {
+            let scale = 1.0 / (CACHE_SIZE - 3) as f32;
+            (1.0 - (position - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        }
+    };
+    // vertices with few triangles left are finished off first
+    let valence_boost =
+        VALENCE_BOOST_SCALE * (remaining_triangles as f32).powf(-VALENCE_BOOST_POWER);
+
+    cache_score + valence_boost
+}
+
+impl Mesh {
+    // rebuilds the vertex buffer from `order` (old indices) and remaps the triangles with
+    // `remap` (old index -> new index), dropping triangles that reference missing vertices
+    fn reorder_vertices(&mut self, order: &[usize], remap: &[Option<u32>]) {
+        self.vertices = order.iter().map(|i| self.vertices[*i]).collect();
+        self.reset_caches();
+        for target in &mut self.morph_targets {
+            target.positions = order.iter().map(|i| target.positions[*i]).collect();
+            target.normals = order.iter().map(|i| target.normals[*i]).collect();
+            target.tangents = order.iter().map(|i| target.tangents[*i]).collect();
+        }
+
+        self.triangles = self
+            .triangles
+            .iter()
+            .filter_map(|triangle| {
+                let [a, b, c] = triangle.to_array();
+                let remapped = |i: u32| remap.get(i as usize).copied().flatten();
+                Some(UVec3::new(remapped(a)?, remapped(b)?, remapped(c)?))
+            })
+            .collect();
+    }
+
+    // whether vertex `b` can stand in for vertex `a`, morph target deltas included
+    fn can_weld(&self, a: usize, b: usize, tolerance: &WeldTolerance) -> bool {
+        let (va, vb) = (&self.vertices[a], &self.vertices[b]);
+        let close = va.position.xyz().distance(vb.position.xyz()) <= tolerance.position
+            && va.normal.distance(vb.normal) <= tolerance.normal
+            && va.uv.distance(vb.uv) <= tolerance.uv
+            && va.color.distance(vb.color) <= tolerance.color;
+
+        close
+            && self.morph_targets.iter().all(|target| {
+                target.positions[a].distance(target.positions[b]) <= tolerance.position
+                    && target.normals[a].distance(target.normals[b]) <= tolerance.normal
+            })
+    }
+
+    /// Merges vertices whose attributes all lie within `tolerance` of each other.
+    /// Returns the number of vertices removed.
+    ///
+    /// Triangles referencing missing vertices are dropped.
+    pub fn weld_vertices(&mut self, tolerance: WeldTolerance) -> usize {
+        let count = self.vertices.len();
+        let cell_size = tolerance.position.max(f32::EPSILON);
+        let cell = |i: usize| {
+            (self.vertices[i].position.xyz() / cell_size)
+                .floor()
+                .as_ivec3()
+        };
+
+        // spatial hash of the kept vertices, welded ones are at most a cell away
+        let mut cells: HashMap<IVec3, Vec<usize>> = HashMap::new();
+        let mut order: Vec<usize> = vec![];
+        let mut remap: Vec<Option<u32>> = Vec::with_capacity(count);
+
+        for i in 0..count {
+            let key = cell(i);
+            let mut found = None;
+            'search: for x in -1..=1 {
+                for y in -1..=1 {
+                    for z in -1..=1 {
+                        let Some(candidates) = cells.get(&(key + IVec3::new(x, y, z))) else {
+                            continue;
+                        };
+                        if let Some(kept) = candidates
+                            .iter()
+                            .find(|&&kept| self.can_weld(order[kept], i, &tolerance))
+                        {
+                            found = Some(*kept);
+                            break 'search;
+                        }
+                    }
+                }
+            }
+
+            remap.push(Some(match found {
+                Some(kept) => kept as u32,
+                None => {
+                    cells.entry(key).or_default().push(order.len());
+                    order.push(i);
+                    (order.len() - 1) as u32
+                }
+            }));
+        }
+
+        self.reorder_vertices(&order, &remap);
+        count - order.len()
+    }
+
+    /// Removes triangles with repeated or missing vertices, or an area of at most `min_area`.
+    /// Returns the number of triangles removed.
+    pub fn remove_degenerate_triangles(&mut self, min_area: f32) -> usize {
+        let count = self.triangles.len();
+        let vertices = &self.vertices;
+
+        self.triangles.retain(|triangle| {
+            let [a, b, c] = triangle.to_array();
+            if a == b || b == c || c == a {
+                return false;
+            }
+            let positions = [a, b, c].map(|i| vertices.get(i as usize).map(|v| v.position.xyz()));
+            let [Some(pa), Some(pb), Some(pc)] = positions else {
+                return false;
+            };
+            (pb - pa).cross(pc - pa).length() * 0.5 > min_area
+        });
+        self.reset_caches();
+
+        count - self.triangles.len()
+    }
+
+    /// Removes vertices no triangle refers to. Returns the number of vertices removed.
+    pub fn remove_unused_vertices(&mut self) -> usize {
+        let count = self.vertices.len();
+        let mut used = vec![false; count];
+        for index in self.triangles.iter().flat_map(|t| t.to_array()) {
+            if let Some(used) = used.get_mut(index as usize) {
+                *used = true;
+            }
+        }
+
+        let order: Vec<usize> = (0..count).filter(|i| used[*i]).collect();
+        let mut remap = vec![None; count];
+        for (new, old) in order.iter().enumerate() {
+            remap[*old] = Some(new as u32);
+        }
+
+        self.reorder_vertices(&order, &remap);
+        count - order.len()
+    }
+
+    /// Reorders the triangles so consecutive ones share vertices, then the vertices in
+    /// the order the triangles first use them.
+    ///
+    /// Does not change what is drawn, only the order it is processed in.
+    /// Triangles referencing missing vertices are dropped.
+    pub fn optimize_vertex_cache(&mut self) {
+        let count = self.vertices.len();
+        self.triangles
+            .retain(|t| t.to_array().iter().all(|i| (*i as usize) < count));
+
+        let triangle_count = self.triangles.len();
+        let mut vertex_triangles: Vec<Vec<usize>> = vec![vec![]; count];
+        for (t, triangle) in self.triangles.iter().enumerate() {
+            for index in triangle.to_array() {
+                vertex_triangles[index as usize].push(t);
+            }
+        }
+
+        let mut remaining: Vec<usize> = vertex_triangles.iter().map(Vec::len).collect();
+        let mut cache_position: Vec<Option<usize>> = vec![None; count];
+        let mut scores: Vec<f32> = (0..count)
+            .map(|v| vertex_score(None, remaining[v]))
+            .collect();
+        let triangle_score = |scores: &[f32], triangle: &UVec3| -> f32 {
+            triangle
+                .to_array()
+                .iter()
+                .map(|i| scores[*i as usize])
+                .sum()
+        };
+
+        let mut added = vec![false; triangle_count];
+        let mut cache: Vec<u32> = Vec::with_capacity(CACHE_SIZE + 3);
+        let mut order: Vec<UVec3> = Vec::with_capacity(triangle_count);
+        // next triangle to consider when the cache has nothing left to offer
+        let mut cursor = 0;
+
+        while order.len() < triangle_count {
+            // the best triangle touching the cache, otherwise the first one not added yet
+            let best = cache
+                .iter()
+                .flat_map(|v| &vertex_triangles[*v as usize])
+                .filter(|t| !added[**t])
+                .map(|t| (*t, triangle_score(&scores, &self.triangles[*t])))
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(t, _)| t);
+            let best = match best {
+                Some(best) => best,
+                None => {
+                    while added[cursor] {
+                        cursor += 1;
+                    }
+                    cursor
+                }
+            };
+
+            let triangle = self.triangles[best];
+            added[best] = true;
+            order.push(triangle);
+
+            // move the vertices of the triangle to the front of the LRU cache
+            for index in triangle.to_array().iter().rev() {
+                cache.retain(|v| v != index);
+                cache.insert(0, *index);
+                remaining[*index as usize] -= 1;
+            }
+            for evicted in cache.drain(CACHE_SIZE.min(cache.len())..) {
+                cache_position[evicted as usize] = None;
+                scores[evicted as usize] = vertex_score(None, remaining[evicted as usize]);
+            }
+            for (position, vertex) in cache.iter().enumerate() {
+                let vertex = *vertex as usize;
+                cache_position[vertex] = Some(position);
+                scores[vertex] = vertex_score(Some(position), remaining[vertex]);
+            }
+        }
+        self.triangles = order;
+
+        // vertex fetch order: number the vertices as the triangles reach them
+        let mut remap: Vec<Option<u32>> = vec![None; count];
+        let mut vertex_order: Vec<usize> = Vec::with_capacity(count);
+        for index in self.triangles.iter().flat_map(|t| t.to_array()) {
+            if remap[index as usize].is_none() {
+                remap[index as usize] = Some(vertex_order.len() as u32);
+                vertex_order.push(index as usize);
+            }
+        }
+        // unused vertices go last
+        for (index, slot) in remap.iter_mut().enumerate() {
+            if slot.is_none() {
+                *slot = Some(vertex_order.len() as u32);
+                vertex_order.push(index);
+            }
+        }
+
+        self.reorder_vertices(&vertex_order, &remap);
+    }
+
+    /// Average number of vertex cache misses per triangle for a FIFO cache of
+    /// `cache_size` entries; 0.5 is about the best possible, 3 means no reuse at all.
+    pub fn average_cache_miss_ratio(&self, cache_size: usize) -> f32 {
+        if self.triangles.is_empty() {
+            return 0.0;
+        }
+
+        let mut cache: std::collections::VecDeque<u32> = Default::default();
+        let mut misses = 0;
+        for index in self.triangles.iter().flat_map(|t| t.to_array()) {
+            if cache.contains(&index) {
+                continue;
+            }
+            misses += 1;
+            cache.push_back(index);
+            if cache.len() > cache_size {
+                cache.pop_front();
+            }
+        }
+
+        misses as f32 / self.triangles.len() as f32
+    }
+
+    /// Reverses the winding of every triangle, turning front faces into back faces.
+    /// With `flip_normals` the normals are negated too, turning the mesh inside out.
+    pub fn flip_winding(&mut self, flip_normals: bool) {
+        for triangle in &mut self.triangles {
+            *triangle = triangle.xzy();
+        }
+
+        if flip_normals {
+            for vertex in &mut self.vertices {
+                vertex.normal = -vertex.normal;
+            }
+            for target in &mut self.morph_targets {
+                target.normals.iter_mut().for_each(|n| *n = -*n);
+            }
+        }
+    }
+
+    /// Appends `other` with `transform` baked into its positions and normals.
+    ///
+    /// Morph targets are matched by index. The mesh keeps its texture, or takes the one
+    /// of `other` if it has none; a mesh can only hold one.
+    pub fn append(&mut self, other: &Mesh, transform: &Mat4) {
+        let linear = Mat3::from_mat4(*transform);
+        let normal_matrix = linear.inverse().transpose();
+        // a mirroring transform would turn the triangles around
+        let mirrored = linear.determinant() < 0.0;
+
+        let vertices: Vec<_> = other
+            .vertices
+            .iter()
+            .map(|vertex| {
+                let mut vertex = *vertex;
+                vertex.position = *transform * vertex.position;
+                vertex.normal = (normal_matrix * vertex.normal).normalize_or_zero();
+                vertex
+            })
+            .collect();
+        let triangles: Vec<UVec3> = other
+            .triangles
+            .iter()
+            .map(|t| if mirrored { t.xzy() } else { *t })
+            .collect();
+        self.add_section_from_vertices(&triangles, &vertices);
+
+        let targets: Vec<MorphTarget> = other
+            .morph_targets
+            .iter()
+            .map(|target| MorphTarget {
+                positions: target.positions.iter().map(|p| linear * *p).collect(),
+                normals: target.normals.iter().map(|n| normal_matrix * *n).collect(),
+                tangents: target.tangents.iter().map(|t| linear * *t).collect(),
+            })
+            .collect();
+        let first_new = self.morph_targets.len();
+        self.add_morph_targets(&targets, vertices.len());
+        for (weight, default) in self.weights.iter_mut().zip(&other.weights).skip(first_new) {
+            *weight = *default;
+        }
+
+        if self.texture.is_none() {
+            self.texture = other.texture.clone();
+        }
+    }
+
+    /// Combines meshes into one, each placed with its transform. See `Mesh::append`.
+    pub fn merge(parts: &[(&Mesh, Mat4)]) -> Mesh {
+        let mut mesh = Mesh::new();
+        for (part, transform) in parts {
+            mesh.append(part, transform);
+        }
+        mesh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Vertex;
+
+    use glam::{UVec2, Vec2, Vec3, Vec4};
+
+    fn vertex(position: Vec3) -> Vertex {
+        Vertex::new(position.extend(1.0), Vec3::Y, Vec4::ONE, position.xz())
+    }
+
+    // a unit quad facing +y as two triangles that do not share their vertices
+    fn split_quad(offset: f32) -> Mesh {
+        let corners = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+        ];
+        let shifted = |p: Vec3| p + Vec3::X * offset;
+        let vertices = [
+            vertex(corners[0]),
+            vertex(corners[1]),
+            vertex(corners[2]),
+            vertex(shifted(corners[0])),
+            vertex(shifted(corners[2])),
+            vertex(corners[3]),
+        ];
+        Mesh::from_vertices(&[UVec3::new(0, 1, 2), UVec3::new(3, 4, 5)], &vertices)
+    }
+
+    // positions of every triangle, rotated to start at the smallest one so that the
+    // same triangle compares equal whatever vertex it starts at
+    fn triangle_positions(mesh: &Mesh) -> Vec<[[u32; 3]; 3]> {
+        let mut triangles: Vec<_> = mesh
+            .triangles()
+            .iter()
+            .map(|t| {
+                let positions = t.to_array().map(|i| {
+                    let p = mesh.vertices()[i as usize].position;
+                    p.xyz().to_array().map(f32::to_bits)
+                });
+                let start = (0..3).min_by_key(|i| positions[*i]).unwrap();
+                std::array::from_fn(|i| positions[(start + i) % 3])
+            })
+            .collect();
+        triangles.sort();
+        triangles
+    }
+
+    // whether every triangle winds counter-clockwise around its vertex normals
+    fn faces_along_normals(mesh: &Mesh) -> bool {
+        mesh.triangles().iter().all(|t| {
+            let [a, b, c] = mesh.get_vertices_from_triangle(*t);
+            let face = (b.position - a.position)
+                .xyz()
+                .cross((c.position - a.position).xyz());
+            face.dot(a.normal) > 0.0
+        })
+    }
+
+    #[test]
+    fn weld_merges_duplicated_corners_within_tolerance() {
+        let mut mesh = split_quad(0.0);
+        assert_eq!(mesh.weld_vertices(WeldTolerance::default()), 2);
+        assert_eq!(mesh.vertices().len(), 4);
+        assert_eq!(mesh.triangles().len(), 2);
+
+        let mut mesh = split_quad(1e-3);
+        assert_eq!(mesh.weld_vertices(WeldTolerance::default()), 0);
+        assert_eq!(mesh.vertices().len(), 6);
+    }
+
+    #[test]
+    fn removes_zero_area_and_repeated_index_triangles() {
+        let vertices = [
+            vertex(Vec3::new(0.0, 0.0, 0.0)),
+            vertex(Vec3::new(0.0, 0.0, 1.0)),
+            vertex(Vec3::new(1.0, 0.0, 0.0)),
+            vertex(Vec3::new(0.0, 0.0, 2.0)),
+        ];
+        let mut mesh = Mesh::from_vertices(
+            &[
+                UVec3::new(0, 1, 2),
+                UVec3::new(0, 1, 3),
+                UVec3::new(0, 0, 2),
+            ],
+            &vertices,
+        );
+        assert_eq!(mesh.remove_degenerate_triangles(0.0), 2);
+        assert_eq!(mesh.triangles(), &vec![UVec3::new(0, 1, 2)]);
+    }
+
+    #[test]
+    fn remove_unused_vertices_remaps_indices() {
+        let vertices: Vec<Vertex> = (0..5).map(|i| vertex(Vec3::X * i as f32)).collect();
+        let mut mesh = Mesh::from_vertices(&[UVec3::new(4, 1, 3)], &vertices);
+        assert_eq!(mesh.remove_unused_vertices(), 2);
+        assert_eq!(mesh.triangles(), &vec![UVec3::new(2, 0, 1)]);
+        let xs: Vec<f32> = mesh.vertices().iter().map(|v| v.position.x).collect();
+        assert_eq!(xs, [1.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn optimize_vertex_cache_keeps_triangles_and_lowers_misses() {
+        for mut mesh in [
+            Mesh::new_plane(Vec2::ONE, UVec2::new(24, 24)),
+            Mesh::new_uv_sphere(1.0, 24, 16),
+            Mesh::new_torus(1.0, 0.25, 32, 12),
+        ] {
+            let triangles = triangle_positions(&mesh);
+            let before = mesh.average_cache_miss_ratio(16);
+            mesh.optimize_vertex_cache();
+            assert_eq!(triangle_positions(&mesh), triangles);
+            assert!(mesh.average_cache_miss_ratio(16) <= before);
+        }
+    }
+
+    #[test]
+    fn merge_translates_parts() {
+        let plane = Mesh::new_plane(Vec2::ONE, UVec2::ONE);
+        let offset = Vec3::new(2.0, 1.0, 0.0);
+        let merged = Mesh::merge(&[
+            (&plane, Mat4::IDENTITY),
+            (&plane, Mat4::from_translation(offset)),
+        ]);
+
+        let count = plane.vertices().len();
+        assert_eq!(merged.vertices().len(), 2 * count);
+        assert_eq!(merged.triangles().len(), 2 * plane.triangles().len());
+        for (original, moved) in plane.vertices().iter().zip(&merged.vertices()[count..]) {
+            assert_eq!(moved.position.xyz(), original.position.xyz() + offset);
+            assert_eq!(moved.normal, original.normal);
+        }
+        assert!(faces_along_normals(&merged));
+    }
+
+    #[test]
+    fn merge_fixes_winding_of_mirrored_parts() {
+        let cube = Mesh::new_cube(Vec3::ONE, 1);
+        assert!(faces_along_normals(&cube));
+        let mirror = Mat4::from_scale(Vec3::new(-1.0, 1.0, 1.0));
+        let merged = Mesh::merge(&[(&cube, mirror)]);
+
+        assert!(faces_along_normals(&merged));
+        for (original, mirrored) in cube.vertices().iter().zip(merged.vertices()) {
+            let expected = original.normal * Vec3::new(-1.0, 1.0, 1.0);
+            assert!(mirrored.normal.abs_diff_eq(expected, 1e-6));
+        }
+    }
+
+    #[test]
+    fn flip_winding_twice_is_identity() {
+        let sphere = Mesh::new_icosphere(1.0, 1);
+        let mut flipped = sphere.clone();
+        flipped.flip_winding(true);
+        let reversed: Vec<UVec3> = sphere.triangles().iter().map(|t| t.xzy()).collect();
+        assert_eq!(flipped.triangles(), &reversed);
+        flipped.flip_winding(true);
+
+        assert_eq!(flipped.triangles(), sphere.triangles());
+        for (a, b) in flipped.vertices().iter().zip(sphere.vertices()) {
+            assert_eq!(a.normal, b.normal);
+        }
+    }
+}
@@ -0,0 +1,342 @@
+use super::Mesh;
+use crate::geometry::Vertex;
+
+use glam::{DVec3, UVec3, Vec4Swizzles};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::ops::AddAssign;
+
+// boundary edges are held in place by a plane through them, weighted well above
+// the face planes so open borders and UV seams keep their shape
+const BOUNDARY_WEIGHT: f64 = 100.0;
+
+// sum of squared distances to a set of planes, Garland & Heckbert,
+// "Surface Simplification Using Quadric Error Metrics"
+#[derive(Debug, Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(normal: DVec3, d: f64, weight: f64) -> Self {
+        let DVec3 { x: a, y: b, z: c } = normal;
+        Quadric(
+            [
+                a * a,
+                a * b,
+                a * c,
+                a * d,
+                b * b,
+                b * c,
+                b * d,
+                c * c,
+                c * d,
+                d * d,
+            ]
+            .map(|v| v * weight),
+        )
+    }
+
+    fn error(&self, p: DVec3) -> f64 {
+        let [aa, ab, ac, ad, bb, bc, bd, cc, cd, dd] = self.0;
+        p.x * p.x * aa
+            + 2.0 * p.x * p.y * ab
+            + 2.0 * p.x * p.z * ac
+            + 2.0 * p.x * ad
+            + p.y * p.y * bb
+            + 2.0 * p.y * p.z * bc
+            + 2.0 * p.y * bd
+            + p.z * p.z * cc
+            + 2.0 * p.z * cd
+            + dd
+    }
+}
+
+impl AddAssign for Quadric {
+    fn add_assign(&mut self, other: Self) {
+        for (a, b) in self.0.iter_mut().zip(other.0) {
+            *a += b;
+        }
+    }
+}
+
+// collapsing `from` into `into`, the merged vertex sits at `t` along the edge
+struct Collapse {
+    cost: f64,
+    into: u32,
+    from: u32,
+    t: f32,
+    // versions of both vertices when the cost was computed, stale entries are skipped
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost.total_cmp(&other.cost) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// reversed, `BinaryHeap` pops the cheapest collapse first
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+fn lerp_vertex(a: &Vertex, b: &Vertex, t: f32) -> Vertex {
+    Vertex::new(
+        a.position.lerp(b.position, t),
+        a.normal.lerp(b.normal, t).normalize_or_zero(),
+        a.color.lerp(b.color, t),
+        a.uv.lerp(b.uv, t),
+    )
+}
+
+struct Simplifier<'a> {
+    mesh: &'a mut Mesh,
+    quadrics: Vec<Quadric>,
+    versions: Vec<u32>,
+    vertex_triangles: Vec<Vec<usize>>,
+    removed: Vec<bool>,
+    heap: BinaryHeap<Collapse>,
+}
+
+impl<'a> Simplifier<'a> {
+    fn new(mesh: &'a mut Mesh) -> Self {
+        let count = mesh.vertices.len();
+        let mut quadrics = vec![Quadric::default(); count];
+        let mut vertex_triangles: Vec<Vec<usize>> = vec![vec![]; count];
+        let mut edges: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+
+        for (t, triangle) in mesh.triangles.iter().enumerate() {
+            let [p0, p1, p2] = mesh.corner_positions(*triangle).map(|p| p.as_dvec3());
+            let cross = (p1 - p0).cross(p2 - p0);
+            let area = cross.length() * 0.5;
+            if area > 0.0 {
+                let normal = cross.normalize();
+                let quadric = Quadric::from_plane(normal, -normal.dot(p0), area);
+                for index in triangle.to_array() {
+                    quadrics[index as usize] += quadric;
+                }
+            }
+
+            let indices = triangle.to_array();
+            for k in 0..3 {
+                let (a, b) = (indices[k], indices[(k + 1) % 3]);
+                vertex_triangles[a as usize].push(t);
+                edges.entry((a.min(b), a.max(b))).or_default().push(t);
+            }
+        }
+
+        // edges with a single triangle are on a border or a seam
+        for (&(a, b), triangles) in &edges {
+            if triangles.len() != 1 {
+                continue;
+            }
+            let [p0, p1, p2] = mesh
+                .corner_positions(mesh.triangles[triangles[0]])
+                .map(|p| p.as_dvec3());
+            let face_normal = (p1 - p0).cross(p2 - p0).normalize_or_zero();
+            let (pa, pb) = (
+                mesh.vertices[a as usize].position.xyz().as_dvec3(),
+                mesh.vertices[b as usize].position.xyz().as_dvec3(),
+            );
+            let edge = pb - pa;
+            let normal = edge.cross(face_normal).normalize_or_zero();
+            let quadric = Quadric::from_plane(
+                normal,
+                -normal.dot(pa),
+                BOUNDARY_WEIGHT * edge.length_squared(),
+            );
+            quadrics[a as usize] += quadric;
+            quadrics[b as usize] += quadric;
+        }
+
+        let triangle_count = mesh.triangles.len();
+        let mut simplifier = Simplifier {
+            mesh,
+            quadrics,
+            versions: vec![0; count],
+            vertex_triangles,
+            removed: vec![false; triangle_count],
+            heap: BinaryHeap::new(),
+        };
+        for (a, b) in edges.into_keys() {
+            simplifier.push_edge(a, b);
+        }
+        simplifier
+    }
+
+    fn position(&self, index: u32) -> DVec3 {
+        self.mesh.vertices[index as usize].position.xyz().as_dvec3()
+    }
+
+    // queues the cheapest of keeping either end or the middle of the edge
+    fn push_edge(&mut self, a: u32, b: u32) {
+        let mut quadric = self.quadrics[a as usize];
+        quadric += self.quadrics[b as usize];
+        let (pa, pb) = (self.position(a), self.position(b));
+
+        let (cost, t) = [0.0, 0.5, 1.0]
+            .iter()
+            .map(|t| (quadric.error(pa.lerp(pb, *t)), *t as f32))
+            .min_by(|x, y| x.0.total_cmp(&y.0))
+            .unwrap();
+
+        self.heap.push(Collapse {
+            cost,
+            into: a,
+            from: b,
+            t,
+            versions: (self.versions[a as usize], self.versions[b as usize]),
+        });
+    }
+
+    // moving the vertices of the edge to `position` must not turn any remaining triangle over
+    fn flips(&self, into: u32, from: u32, position: DVec3) -> bool {
+        for vertex in [into, from] {
+            for &t in &self.vertex_triangles[vertex as usize] {
+                let triangle = self.mesh.triangles[t];
+                if self.removed[t]
+                    || (triangle.to_array().contains(&into) && triangle.to_array().contains(&from))
+                {
+                    continue;
+                }
+                let before = triangle.to_array().map(|i| self.position(i));
+                let after = triangle.to_array().map(|i| {
+                    if i == vertex {
+                        position
+                    } else {
+                        self.position(i)
+                    }
+                });
+                let normal_before = (before[1] - before[0]).cross(before[2] - before[0]);
+                let normal_after = (after[1] - after[0]).cross(after[2] - after[0]);
+                if normal_before.dot(normal_after) <= 0.0 {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn run(&mut self, target_triangles: usize) {
+        let mut live = self.mesh.triangles.len();
+
+        while live > target_triangles {
+            let Some(collapse) = self.heap.pop() else {
+                break;
+            };
+            let (into, from) = (collapse.into as usize, collapse.from as usize);
+            if collapse.versions != (self.versions[into], self.versions[from]) {
+                continue;
+            }
+
+            let position = self
+                .position(collapse.into)
+                .lerp(self.position(collapse.from), collapse.t as f64);
+            if self.flips(collapse.into, collapse.from, position) {
+                continue;
+            }
+
+            // merge the vertex data, morph targets included
+            let vertices = &mut self.mesh.vertices;
+            vertices[into] = lerp_vertex(&vertices[into], &vertices[from], collapse.t);
+            for target in &mut self.mesh.morph_targets {
+                target.positions[into] =
+                    target.positions[into].lerp(target.positions[from], collapse.t);
+                target.normals[into] = target.normals[into].lerp(target.normals[from], collapse.t);
+                target.tangents[into] =
+                    target.tangents[into].lerp(target.tangents[from], collapse.t);
+            }
+            let quadric = self.quadrics[from];
+            self.quadrics[into] += quadric;
+            self.versions[into] += 1;
+            // never matches again, `from` is gone
+            self.versions[from] = u32::MAX;
+
+            for t in std::mem::take(&mut self.vertex_triangles[from]) {
+                if self.removed[t] {
+                    continue;
+                }
+                let triangle = &mut self.mesh.triangles[t];
+                *triangle = UVec3::from_array(triangle.to_array().map(|i| {
+                    if i == collapse.from {
+                        collapse.into
+                    } else {
+                        i
+                    }
+                }));
+                let [a, b, c] = triangle.to_array();
+                if a == b || b == c || c == a {
+                    self.removed[t] = true;
+                    live -= 1;
+                } else {
+                    self.vertex_triangles[into].push(t);
+                }
+            }
+            let removed = &self.removed;
+            self.vertex_triangles[into].retain(|t| !removed[*t]);
+            self.vertex_triangles[into].sort_unstable();
+            self.vertex_triangles[into].dedup();
+
+            let mut neighbours: Vec<u32> = self.vertex_triangles[into]
+                .iter()
+                .flat_map(|t| self.mesh.triangles[*t].to_array())
+                .filter(|i| *i != collapse.into)
+                .collect();
+            neighbours.sort_unstable();
+            neighbours.dedup();
+            for neighbour in neighbours {
+                self.push_edge(collapse.into, neighbour);
+            }
+        }
+
+        self.mesh.reset_caches();
+        let removed = std::mem::take(&mut self.removed);
+        let mut index = 0;
+        self.mesh.triangles.retain(|_| {
+            index += 1;
+            !removed[index - 1]
+        });
+    }
+}
+
+impl Mesh {
+    /// Reduces the mesh to about `target_triangles` triangles by collapsing the edges
+    /// that change the surface the least (quadric error metric).
+    ///
+    /// Borders and UV seams are preserved as far as possible, vertex attributes and
+    /// morph targets are interpolated along the collapsed edges. Stops early when no
+    /// collapse is left that would not turn a triangle over.
+    pub fn simplify(&self, target_triangles: usize) -> Mesh {
+        let mut mesh = self.clone();
+        let count = mesh.vertices.len();
+        mesh.triangles
+            .retain(|t| t.to_array().iter().all(|i| (*i as usize) < count));
+
+        if mesh.triangles.len() > target_triangles {
+            Simplifier::new(&mut mesh).run(target_triangles);
+            mesh.remove_unused_vertices();
+        }
+        mesh
+    }
+
+    /// `levels` successively simplified copies of the mesh, each with `ratio` times
+    /// the triangles of the one before.
+    pub fn lod_chain(&self, levels: usize, ratio: f32) -> Vec<Mesh> {
+        let mut chain: Vec<Mesh> = Vec::with_capacity(levels);
+        for _ in 0..levels {
+            let previous = chain.last().unwrap_or(self);
+            let target = (previous.triangles.len() as f32 * ratio) as usize;
+            chain.push(previous.simplify(target));
+        }
+        chain
+    }
+}
@@ -1,20 +1,75 @@
+use crate::animation::MorphAnimation;
+use crate::bounds::{Aabb, BoundingSphere, Frustum};
+use crate::error::RusterizerError;
+use crate::lod::Lod;
+use crate::mesh::{Mesh, PolygonMode};
+use crate::texture::{ColorSpace, Texture};
 use crate::transform::Transform;
-use crate::{mesh::Mesh, Object};
-use glam::{Mat4, Quat, Vec2, Vec3};
+use crate::utils::{from_argb8, to_argb8};
+use glam::{Mat4, Quat, Vec2, Vec3, Vec4};
+use std::collections::HashMap;
 use std::path::Path;
+use std::sync::Arc;
 
 #[derive(Debug, Clone)]
 pub struct Model {
-    pub meshes: Vec<Mesh>,
+    /// Meshes are shared between clones of the model, use `Arc::make_mut` to edit one.
+    pub meshes: Vec<Arc<Mesh>>,
     pub transform: Transform,
+    /// Morph target weights of this instance, one array per mesh.
+    pub weights: Vec<Vec<f32>>,
+    pub animations: Vec<MorphAnimation>,
+    /// Simplified meshes, from most to least detailed. See `Model::generate_lods`.
+    pub lods: Vec<Lod>,
+    /// How the meshes are drawn, the LODs included. Lines and points only write colour
+    /// and depth, they are not in the G-buffer.
+    pub polygon_mode: PolygonMode,
 }
 
 impl Model {
-    pub fn new(file_path: &Path) -> Self {
-        let (document, buffers, _images) = gltf::import(file_path).unwrap();
+    /// Loads a model, picking the importer from the file extension (glTF by default).
+    pub fn new(file_path: &Path) -> Result<Self, RusterizerError> {
+        let extension = file_path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
 
-        let mut meshes: Vec<Mesh> = Vec::new();
+        match extension.as_deref() {
+            Some("obj") => Model::new_from_obj(file_path),
+            Some("stl") => Ok(Model::from_meshes(vec![Mesh::new_from_stl(file_path)?])),
+            Some("ply") => Ok(Model::from_meshes(vec![Mesh::new_from_ply(file_path)?])),
+            _ => Model::new_from_gltf(file_path),
+        }
+    }
+
+    /// Wraps meshes built in code (or by the single-mesh importers) in a model.
+    pub fn from_meshes(meshes: Vec<Mesh>) -> Self {
+        let meshes: Vec<Arc<Mesh>> = meshes.into_iter().map(Arc::new).collect();
+        let weights = meshes.iter().map(|m| m.weights().clone()).collect();
+
+        Model {
+            meshes,
+            transform: Transform::IDENTITY,
+            weights,
+            animations: vec![],
+            lods: vec![],
+            polygon_mode: PolygonMode::Fill,
+        }
+    }
+
+    pub fn new_from_gltf(file_path: &Path) -> Result<Self, RusterizerError> {
+        let gltf::Gltf { document, blob } = gltf::Gltf::open(file_path)?;
+        let base = file_path.parent();
+        let buffers = gltf::import_buffers(&document, base, blob)?;
+
+        // images are read and converted the first time a material uses them
+        let mut textures: Vec<Option<Option<Arc<Texture>>>> = vec![None; document.images().len()];
+        let mut texture = |image: gltf::Image| gltf_texture(image, base, &buffers, &mut textures);
+
+        let mut meshes: Vec<Arc<Mesh>> = Vec::new();
         let mut transform: Transform = Transform::new(Vec3::ONE, Quat::IDENTITY, Vec3::ONE);
+        // node index -> index in `meshes`, used to resolve animation targets
+        let mut node_meshes: HashMap<usize, usize> = HashMap::new();
 
         for scene in document.scenes() {
             for node in scene.nodes() {
@@ -39,23 +94,115 @@ impl Model {
                     node.transform().decomposed().2[2],
                 );
 
-                if let Some(mesh) = node.mesh() {
-                    meshes.push(Mesh::new_from_gltf(&mesh, &buffers));
+                if let Some(gltf_mesh) = node.mesh() {
+                    let mut mesh = Mesh::new_from_gltf(&gltf_mesh, &buffers)?;
+
+                    // a mesh holds a single texture, use the base colour of the first primitive
+                    let base_color = gltf_mesh.primitives().find_map(|primitive| {
+                        let info = primitive
+                            .material()
+                            .pbr_metallic_roughness()
+                            .base_color_texture()?;
+                        texture(info.texture().source())
+                    });
+                    if let Some(base_color) = base_color {
+                        mesh.add_texture(base_color);
+                    }
+
+                    // same for the occlusion, its strength is baked into the texture
+                    let occlusion = gltf_mesh.primitives().find_map(|primitive| {
+                        let material = primitive.material();
+                        let info = material.occlusion_texture()?;
+                        let occlusion = texture(info.texture().source())?;
+                        Some(Arc::new(occlusion_texture(&occlusion, info.strength())))
+                    });
+                    mesh.set_occlusion_texture(occlusion);
+
+                    // node weights override the mesh defaults
+                    if let Some(weights) = node.weights() {
+                        let mut weights = weights.to_vec();
+                        weights.resize(mesh.morph_targets().len(), 0.0);
+                        mesh.set_weights(weights);
+                    }
+                    node_meshes.insert(node.index(), meshes.len());
+                    meshes.push(Arc::new(mesh));
                 }
             }
         }
-        Model { meshes, transform }
+
+        let target_counts: Vec<usize> = meshes.iter().map(|m| m.morph_targets().len()).collect();
+        let animations: Vec<MorphAnimation> = document
+            .animations()
+            .filter_map(|animation| {
+                MorphAnimation::new_from_gltf(&animation, &buffers, &node_meshes, &target_counts)
+            })
+            .collect();
+
+        let weights = meshes.iter().map(|m| m.weights().clone()).collect();
+
+        Ok(Model {
+            meshes,
+            transform,
+            weights,
+            animations,
+            lods: vec![],
+            polygon_mode: PolygonMode::Fill,
+        })
+    }
+
+    /// Samples `self.animations[animation]` at `time` (in seconds, looping) into `self.weights`.
+    pub fn animate(&mut self, animation: usize, time: f32) {
+        let Some(animation) = self.animations.get(animation) else {
+            return;
+        };
+
+        let duration = animation.duration();
+        let time = if duration > 0.0 { time % duration } else { 0.0 };
+
+        for channel in &animation.channels {
+            if let Some(weights) = self.weights.get_mut(channel.mesh) {
+                channel.sample(time, weights);
+            }
+        }
+    }
+
+    /// Resets the morph target weights to the defaults of the meshes.
+    pub fn reset_weights(&mut self) {
+        self.weights = self.meshes.iter().map(|m| m.weights().clone()).collect();
+    }
+
+    /// Bounding box of all meshes in model space, the model transform is not applied.
+    pub fn aabb(&self) -> Aabb {
+        self.meshes
+            .iter()
+            .fold(Aabb::EMPTY, |aabb, mesh| aabb.union(&mesh.aabb()))
+    }
+
+    /// Bounding sphere of all meshes in model space, the model transform is not applied.
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        let mut spheres = self.meshes.iter().map(|mesh| mesh.bounding_sphere());
+        let first = spheres
+            .next()
+            .unwrap_or(BoundingSphere::new(Vec3::ZERO, 0.0));
+        spheres.fold(first, |sphere, other| sphere.union(&other))
     }
 
     pub fn draw(
         &self,
-        buffer: &mut Vec<u32>,
-        depth_buffer: &mut Vec<f32>,
+        buffer: &mut [u32],
+        depth_buffer: &mut [f32],
         mvp: &Mat4,
         viewport_size: Vec2,
     ) {
-        for mesh in self.meshes.clone() {
-            mesh.draw(
+        // one test for the whole model, the meshes test themselves again
+        if !Frustum::from_matrix(&(*mvp * self.transform.local())).intersects_aabb(&self.aabb()) {
+            return;
+        }
+
+        for (i, mesh) in self.meshes.iter().enumerate() {
+            mesh.draw_with_weights(
+                self.weights.get(i).unwrap_or(mesh.weights()),
+                self.polygon_mode,
                 buffer,
                 depth_buffer,
                 &self.transform.local(),
@@ -64,4 +211,69 @@ impl Model {
             );
         }
     }
+
+    /// Draws the model once per transform in `instances`, each placed relative to the
+    /// model's own transform. Vertex data is shared by every instance.
+    pub fn draw_instanced(
+        &self,
+        buffer: &mut Vec<u32>,
+        depth_buffer: &mut Vec<f32>,
+        view_projection: &Mat4,
+        instances: &[Transform],
+        tints: Option<&[Vec4]>,
+        viewport_size: Vec2,
+    ) {
+        let local = self.transform.local();
+        let models: Vec<Mat4> = instances.iter().map(|t| t.local() * local).collect();
+
+        for (i, mesh) in self.meshes.iter().enumerate() {
+            mesh.draw_instanced(
+                self.weights.get(i).unwrap_or(mesh.weights()),
+                self.polygon_mode,
+                buffer,
+                depth_buffer,
+                view_projection,
+                &models,
+                tints,
+                viewport_size,
+            );
+        }
+    }
+}
+
+// `image` as a texture, read and converted once then cached in `textures`. Images that
+// are missing or in a format we cannot read are skipped with a warning, leaving the
+// material untextured
+fn gltf_texture(
+    image: gltf::Image,
+    base: Option<&Path>,
+    buffers: &[gltf::buffer::Data],
+    textures: &mut [Option<Option<Arc<Texture>>>],
+) -> Option<Arc<Texture>> {
+    let index = image.index();
+    let slot = textures.get_mut(index)?;
+    slot.get_or_insert_with(|| {
+        let texture = gltf::image::Data::from_source(image.source(), base, buffers)
+            .map_err(RusterizerError::from)
+            .and_then(|data| Texture::from_gltf_image(&data));
+        match texture {
+            Ok(texture) => Some(Arc::new(texture)),
+            Err(err) => {
+                println!("Skipping image #{index}: {err}");
+                None
+            }
+        }
+    })
+    .clone()
+}
+
+// a linear copy of `texture`, its red channel scaled towards 1 by `strength`
+fn occlusion_texture(texture: &Texture, strength: f32) -> Texture {
+    let mut occlusion = texture.clone().with_color_space(ColorSpace::Linear);
+    for argb in &mut occlusion.data {
+        let (a, r, g, b) = from_argb8(*argb);
+        let r = 255.0 - (255.0 - r as f32) * strength.clamp(0.0, 1.0);
+        *argb = to_argb8(a, r.round() as u8, g, b);
+    }
+    occlusion
 }
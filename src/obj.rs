@@ -0,0 +1,384 @@
+use crate::error::RusterizerError;
+use crate::mesh::Mesh;
+use crate::model::Model;
+use crate::texture::Texture;
+
+use glam::{UVec3, Vec2, Vec3, Vec4};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+// a face corner: indices into the position, uv and normal lists
+type Corner = (usize, Option<usize>, Option<usize>);
+
+#[derive(Debug, Clone)]
+struct ObjMaterial {
+    diffuse: Vec4,
+    diffuse_texture: Option<Arc<Texture>>,
+}
+
+impl Default for ObjMaterial {
+    fn default() -> Self {
+        Self {
+            diffuse: Vec4::ONE,
+            diffuse_texture: None,
+        }
+    }
+}
+
+// faces of one group that share a material
+struct Section {
+    material: Option<String>,
+    triangles: Vec<[Corner; 3]>,
+}
+
+fn decode_error(path: &Path, line: usize, message: &str) -> RusterizerError {
+    RusterizerError::Decode(format!("{}:{}: {}", path.display(), line + 1, message))
+}
+
+fn parse_floats<const N: usize>(
+    tokens: &[&str],
+    path: &Path,
+    line: usize,
+) -> Result<[f32; N], RusterizerError> {
+    let mut values = [0.0; N];
+    if tokens.len() < N {
+        return Err(decode_error(path, line, "not enough components"));
+    }
+    for (value, token) in values.iter_mut().zip(tokens) {
+        *value = token
+            .parse()
+            .map_err(|_| decode_error(path, line, &format!("invalid number {token:?}")))?;
+    }
+    Ok(values)
+}
+
+// OBJ indices are 1-based, negative ones count back from the end of the list
+fn resolve_index(
+    token: &str,
+    count: usize,
+    path: &Path,
+    line: usize,
+) -> Result<usize, RusterizerError> {
+    let index: i64 = token
+        .parse()
+        .map_err(|_| decode_error(path, line, &format!("invalid index {token:?}")))?;
+
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(decode_error(
+            path,
+            line,
+            &format!("index {index} out of range, {count} elements defined"),
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_mtl(path: &Path) -> Result<HashMap<String, ObjMaterial>, RusterizerError> {
+    let source = std::fs::read_to_string(path)?;
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, ObjMaterial)> = None;
+
+    for (line_number, line) in source.lines().enumerate() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let Some((keyword, args)) = tokens.split_first() else {
+            continue;
+        };
+
+        if *keyword == "newmtl" {
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material);
+            }
+            current = Some((args.join(" "), ObjMaterial::default()));
+            continue;
+        }
+
+        let Some((_, material)) = current.as_mut() else {
+            continue;
+        };
+
+        match *keyword {
+            "Kd" => {
+                let [r, g, b] = parse_floats::<3>(args, path, line_number)?;
+                material.diffuse = Vec4::new(r, g, b, material.diffuse.w);
+            }
+            "d" => material.diffuse.w = parse_floats::<1>(args, path, line_number)?[0],
+            "Tr" => material.diffuse.w = 1.0 - parse_floats::<1>(args, path, line_number)?[0],
+            "map_Kd" => {
+                // options like `-s 1 1 1` come before the file name
+                if let Some(file) = args.last() {
+                    let texture_path = directory.join(file);
+                    // a missing texture leaves the material untextured, keeping the geometry
+                    match Texture::load(&texture_path) {
+                        Ok(texture) => material.diffuse_texture = Some(Arc::new(texture)),
+                        Err(err) => println!("Skipping texture {}: {err}", texture_path.display()),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Some((name, material)) = current {
+        materials.insert(name, material);
+    }
+
+    Ok(materials)
+}
+
+// Newell's method, robust for non-planar polygons
+fn polygon_normal(points: &[Vec3]) -> Vec3 {
+    let mut normal = Vec3::ZERO;
+    for i in 0..points.len() {
+        let (a, b) = (points[i], points[(i + 1) % points.len()]);
+        normal += Vec3::new(
+            (a.y - b.y) * (a.z + b.z),
+            (a.z - b.z) * (a.x + b.x),
+            (a.x - b.x) * (a.y + b.y),
+        );
+    }
+    normal
+}
+
+/// Splits a polygon into triangles by ear clipping, returns indices into `points`.
+///
+/// Falls back to a fan for degenerate polygons.
+pub fn triangulate_polygon(points: &[Vec3]) -> Vec<[usize; 3]> {
+    if points.len() < 3 {
+        return vec![];
+    }
+    if points.len() == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    let fan = || (1..points.len() - 1).map(|i| [0, i, i + 1]).collect();
+
+    let normal = polygon_normal(points);
+    if normal.length_squared() <= f32::EPSILON {
+        return fan();
+    }
+
+    let is_convex = |a: Vec3, b: Vec3, c: Vec3| (b - a).cross(c - b).dot(normal) > 0.0;
+    let contains = |p: Vec3, a: Vec3, b: Vec3, c: Vec3| {
+        (b - a).cross(p - a).dot(normal) >= 0.0
+            && (c - b).cross(p - b).dot(normal) >= 0.0
+            && (a - c).cross(p - c).dot(normal) >= 0.0
+    };
+
+    let mut remaining: Vec<usize> = (0..points.len()).collect();
+    let mut triangles = Vec::with_capacity(points.len() - 2);
+
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let ear = (0..n).find(|&i| {
+            let (ia, ib, ic) = (
+                remaining[(i + n - 1) % n],
+                remaining[i],
+                remaining[(i + 1) % n],
+            );
+            let (a, b, c) = (points[ia], points[ib], points[ic]);
+            is_convex(a, b, c)
+                && remaining
+                    .iter()
+                    .filter(|&&j| j != ia && j != ib && j != ic)
+                    .all(|&j| !contains(points[j], a, b, c))
+        });
+
+        match ear {
+            Some(i) => {
+                triangles.push([
+                    remaining[(i + n - 1) % n],
+                    remaining[i],
+                    remaining[(i + 1) % n],
+                ]);
+                remaining.remove(i);
+            }
+            // self-intersecting polygon, nothing sensible to do
+            None => return fan(),
+        }
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+
+    triangles
+}
+
+impl Model {
+    /// Loads a Wavefront OBJ file and the MTL libraries it references.
+    ///
+    /// Every group (`g`/`o`) becomes a mesh, split further on `usemtl` since each mesh
+    /// has a single texture. `Kd`/`d` end up in the vertex colours, `map_Kd` in the texture.
+    pub fn new_from_obj(file_path: &Path) -> Result<Self, RusterizerError> {
+        let source = std::fs::read_to_string(file_path)?;
+        let directory = file_path.parent().unwrap_or_else(|| Path::new(""));
+
+        let mut positions: Vec<Vec3> = vec![];
+        let mut colors: Vec<Vec4> = vec![];
+        let mut uvs: Vec<Vec2> = vec![];
+        let mut normals: Vec<Vec3> = vec![];
+        let mut materials: HashMap<String, ObjMaterial> = HashMap::new();
+
+        let mut sections: Vec<Section> = vec![Section {
+            material: None,
+            triangles: vec![],
+        }];
+
+        for (line_number, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let Some((keyword, args)) = tokens.split_first() else {
+                continue;
+            };
+
+            match *keyword {
+                "v" => {
+                    let [x, y, z] = parse_floats::<3>(args, file_path, line_number)?;
+                    positions.push(Vec3::new(x, y, z));
+                    // non standard but common: `v x y z r g b`
+                    if args.len() >= 6 {
+                        let [r, g, b] = parse_floats::<3>(&args[3..], file_path, line_number)?;
+                        colors.push(Vec4::new(r, g, b, 1.0));
+                    } else {
+                        colors.push(Vec4::ONE);
+                    }
+                }
+                "vt" => {
+                    let [u] = parse_floats::<1>(args, file_path, line_number)?;
+                    let v = match args.get(1) {
+                        Some(_) => parse_floats::<2>(args, file_path, line_number)?[1],
+                        None => 0.0,
+                    };
+                    // OBJ has v pointing up, textures are stored top row first
+                    uvs.push(Vec2::new(u, 1.0 - v));
+                }
+                "vn" => {
+                    let [x, y, z] = parse_floats::<3>(args, file_path, line_number)?;
+                    normals.push(Vec3::new(x, y, z));
+                }
+                "f" => {
+                    let mut corners: Vec<Corner> = Vec::with_capacity(args.len());
+                    for arg in args {
+                        let mut parts = arg.split('/');
+                        let position = parts.next().unwrap_or("");
+                        let position =
+                            resolve_index(position, positions.len(), file_path, line_number)?;
+                        let uv = match parts.next() {
+                            Some(uv) if !uv.is_empty() => {
+                                Some(resolve_index(uv, uvs.len(), file_path, line_number)?)
+                            }
+                            _ => None,
+                        };
+                        let normal = match parts.next() {
+                            Some(n) if !n.is_empty() => {
+                                Some(resolve_index(n, normals.len(), file_path, line_number)?)
+                            }
+                            _ => None,
+                        };
+                        corners.push((position, uv, normal));
+                    }
+
+                    let points: Vec<Vec3> = corners.iter().map(|c| positions[c.0]).collect();
+                    let section = sections.last_mut().unwrap();
+                    for [a, b, c] in triangulate_polygon(&points) {
+                        section.triangles.push([corners[a], corners[b], corners[c]]);
+                    }
+                }
+                "g" | "o" => {
+                    let material = sections.last().unwrap().material.clone();
+                    sections.push(Section {
+                        material,
+                        triangles: vec![],
+                    });
+                }
+                "usemtl" => sections.push(Section {
+                    material: Some(args.join(" ")),
+                    triangles: vec![],
+                }),
+                "mtllib" => {
+                    // like missing textures, a broken library leaves its materials at
+                    // the defaults rather than failing the import
+                    for library in args {
+                        let library_path = directory.join(library);
+                        match parse_mtl(&library_path) {
+                            Ok(library) => materials.extend(library),
+                            Err(err) => println!(
+                                "Skipping material library {}: {err}",
+                                library_path.display()
+                            ),
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut meshes: Vec<Mesh> = vec![];
+
+        for section in sections.iter().filter(|s| !s.triangles.is_empty()) {
+            let material = section
+                .material
+                .as_ref()
+                .and_then(|name| materials.get(name))
+                .cloned()
+                .unwrap_or_default();
+
+            // OBJ indexes every attribute separately, build one vertex per distinct corner
+            let mut corner_ids: HashMap<Corner, u32> = HashMap::new();
+            let mut section_positions: Vec<Vec3> = vec![];
+            let mut section_colors: Vec<Vec4> = vec![];
+            let mut section_uvs: Vec<Vec2> = vec![];
+            let mut section_normals: Vec<Vec3> = vec![];
+            let mut triangles: Vec<UVec3> = vec![];
+
+            let has_uvs = section.triangles.iter().flatten().all(|c| c.1.is_some());
+            let has_normals = section.triangles.iter().flatten().all(|c| c.2.is_some());
+
+            for triangle in &section.triangles {
+                let ids = triangle.map(|corner| {
+                    *corner_ids.entry(corner).or_insert_with(|| {
+                        let (position, uv, normal) = corner;
+                        section_positions.push(positions[position]);
+                        section_colors.push(colors[position] * material.diffuse);
+                        if has_uvs {
+                            section_uvs.push(uvs[uv.unwrap()]);
+                        }
+                        if has_normals {
+                            section_normals.push(normals[normal.unwrap()]);
+                        }
+                        (section_positions.len() - 1) as u32
+                    })
+                });
+                triangles.push(UVec3::from_array(ids));
+            }
+
+            let mut mesh = Mesh::new();
+            mesh.add_section_from_buffers(
+                &triangles,
+                &section_positions,
+                &section_normals,
+                &section_colors,
+                &section_uvs,
+            )?;
+            if let Some(texture) = material.diffuse_texture {
+                mesh.add_texture(texture);
+            }
+            meshes.push(mesh);
+        }
+
+        println!(
+            "Loaded {}: {} meshes, {} positions",
+            file_path.display(),
+            meshes.len(),
+            positions.len()
+        );
+
+        Ok(Model::from_meshes(meshes))
+    }
+}
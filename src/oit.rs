@@ -0,0 +1,130 @@
+use crate::model::Model;
+use crate::postprocess::for_each_band;
+use crate::transform::Transform;
+use crate::utils::{from_argb8, linear_to_argb8, srgb_to_linear};
+use crate::ThreadPool;
+
+use glam::{Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
+
+/// Weighted blended order-independent transparency: transparent fragments are summed,
+/// weighted by their alpha and depth, then blended over the opaque image in one pass.
+/// Nothing has to be sorted, and intersecting surfaces blend correctly.
+///
+/// Fragments are accumulated on the calling thread, like opaque triangles, and are not
+/// split into screen bands. Only `OitBuffer::resolve` runs on the `ThreadPool`.
+#[derive(Debug, Clone)]
+pub struct OitBuffer {
+    pub width: usize,
+    pub height: usize,
+    // premultiplied colours and alphas, weighted
+    accumulation: Vec<Vec4>,
+    // how much of the background is still visible, the product of 1 - alpha
+    revealage: Vec<f32>,
+}
+
+impl OitBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            accumulation: vec![Vec4::ZERO; width * height],
+            revealage: vec![1.0; width * height],
+        }
+    }
+
+    pub fn viewport_size(&self) -> Vec2 {
+        Vec2::new(self.width as f32, self.height as f32)
+    }
+
+    /// Call before drawing the transparent geometry of each frame.
+    pub fn clear(&mut self) {
+        self.accumulation.fill(Vec4::ZERO);
+        self.revealage.fill(1.0);
+    }
+
+    /// Adds a fragment of linear `color`, with its alpha in w, at `view_depth`.
+    pub fn add(&mut self, index: usize, color: Vec4, view_depth: f32) {
+        let alpha = color.w.clamp(0.0, 1.0);
+        if alpha <= 0.0 {
+            return;
+        }
+        // closer fragments weigh more, from McGuire and Bavoil's paper
+        let weight = alpha
+            * (10.0 / (1e-5 + (view_depth / 5.0).powi(2) + (view_depth / 200.0).powi(6)))
+                .clamp(1e-2, 3e3);
+        self.accumulation[index] += (color.xyz() * alpha).extend(alpha) * weight;
+        self.revealage[index] *= 1.0 - alpha;
+    }
+
+    /// Blends the transparent fragments over `buffer`, which holds sRGB colours.
+    pub fn resolve(&self, thread_pool: &ThreadPool, buffer: &mut [u32]) {
+        self.resolve_into(thread_pool, buffer, |pixel, color, revealage| {
+            let (a, r, g, b) = from_argb8(*pixel);
+            let decode = |c: u8| srgb_to_linear(c as f32 / 255.0);
+            let background = Vec3::new(decode(r), decode(g), decode(b));
+            let blended = color * (1.0 - revealage) + background * revealage;
+            *pixel = linear_to_argb8(blended.extend(a as f32 / 255.0));
+        });
+    }
+
+    /// Like `resolve`, over the linear colours of a `PostProcess` chain.
+    pub fn resolve_hdr(&self, thread_pool: &ThreadPool, colors: &mut [Vec4]) {
+        self.resolve_into(thread_pool, colors, |pixel, color, revealage| {
+            let blended = color * (1.0 - revealage) + pixel.xyz() * revealage;
+            *pixel = blended.extend(pixel.w);
+        });
+    }
+
+    fn resolve_into<T: Send>(
+        &self,
+        thread_pool: &ThreadPool,
+        buffer: &mut [T],
+        blend: fn(&mut T, Vec3, f32),
+    ) {
+        let width = self.width;
+        for_each_band(thread_pool, buffer, width, |first_row, band| {
+            let first = first_row * width;
+            for (i, pixel) in band.iter_mut().enumerate() {
+                let revealage = self.revealage[first + i];
+                if revealage >= 1.0 {
+                    continue;
+                }
+                let accumulation = self.accumulation[first + i];
+                let color = accumulation.xyz() / accumulation.w.max(1e-5);
+                blend(pixel, color, revealage);
+            }
+        });
+    }
+}
+
+impl Model {
+    /// Draws the model into `oit` like `draw_instanced`, or once at its own transform when
+    /// there are no instances. Draw the opaque geometry into `depth_buffer` first, then
+    /// `OitBuffer::resolve`. Single-threaded, see `OitBuffer`.
+    pub fn draw_transparent(
+        &self,
+        oit: &mut OitBuffer,
+        depth_buffer: &[f32],
+        view_projection: &Mat4,
+        instances: &[Transform],
+        tints: Option<&[Vec4]>,
+    ) {
+        let local = self.transform.local();
+        let models: Vec<Mat4> = if instances.is_empty() {
+            vec![local]
+        } else {
+            instances.iter().map(|t| t.local() * local).collect()
+        };
+
+        for (i, mesh) in self.meshes.iter().enumerate() {
+            mesh.draw_transparent(
+                self.weights.get(i).unwrap_or(mesh.weights()),
+                oit,
+                depth_buffer,
+                view_projection,
+                &models,
+                tints,
+            );
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use crate::bounds::Frustum;
+use crate::camera::Camera;
+use crate::geometry::{ray_aabb_intersection, ray_triangle_intersection, Ray, Triangle, Vertex};
+use crate::mesh::Mesh;
+use crate::model::Model;
+use crate::transform::Transform;
+
+use glam::{Mat4, Vec2, Vec3, Vec4Swizzles};
+
+/// Where a ray hits a mesh.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    /// Distance along the ray, see `Ray`.
+    pub distance: f32,
+    /// Index in `Mesh::triangles`.
+    pub triangle: usize,
+    /// Weights of the three corners of the triangle at the hit.
+    pub barycentric: Vec3,
+}
+
+/// The closest thing under a point of the screen, see `pick`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pick {
+    /// Index of the model in the scene.
+    pub model: usize,
+    /// Index of the instance, 0 for a model drawn without instances.
+    pub instance: usize,
+    /// Index in `Model::meshes`.
+    pub mesh: usize,
+    /// In model space, the distance is still the one along the world space ray.
+    pub hit: RayHit,
+    /// World space position of the hit.
+    pub position: Vec3,
+}
+
+/// What an `IdBuffer` holds for a pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdHit {
+    pub model: usize,
+    pub instance: usize,
+    pub mesh: usize,
+    pub triangle: usize,
+}
+
+// the matrices a model is drawn with, no instances being the model drawn on its own
+fn model_matrices(model: &Model, instances: &[Transform]) -> Vec<Mat4> {
+    let local = model.transform.local();
+    if instances.is_empty() {
+        vec![local]
+    } else {
+        instances.iter().map(|t| t.local() * local).collect()
+    }
+}
+
+fn intersect_triangle(
+    mesh: &Mesh,
+    vertices: &[Vertex],
+    ray: &Ray,
+    t: usize,
+) -> Option<(f32, Vec3)> {
+    let positions = mesh.triangles()[t]
+        .to_array()
+        .map(|i| vertices.get(i as usize).map(|v| v.position.xyz()));
+    let [Some(a), Some(b), Some(c)] = positions else {
+        return None;
+    };
+    ray_triangle_intersection(ray, &[a, b, c])
+}
+
+impl Mesh {
+    /// Closest triangle hit by a model space ray, with the morph targets blended by `weights`.
+    ///
+    /// Uses the cached `bvh` when no morph target is active, tests every triangle otherwise.
+    pub fn intersect_ray(&self, ray: &Ray, weights: &[f32]) -> Option<RayHit> {
+        ray_aabb_intersection(ray, &self.aabb())?;
+
+        let morphed = !self.morph_targets().is_empty() && weights.iter().any(|w| *w != 0.0);
+        if !morphed {
+            let vertices = self.vertices();
+            let (triangle, _) = self.bvh().intersect_ray(ray, |t| {
+                intersect_triangle(self, vertices, ray, t).map(|h| h.0)
+            })?;
+            let (distance, barycentric) = intersect_triangle(self, vertices, ray, triangle)?;
+            return Some(RayHit {
+                distance,
+                triangle,
+                barycentric,
+            });
+        }
+
+        let vertices = self.morphed_vertices(weights);
+        (0..self.triangles().len())
+            .filter_map(|triangle| {
+                let (distance, barycentric) = intersect_triangle(self, &vertices, ray, triangle)?;
+                Some(RayHit {
+                    distance,
+                    triangle,
+                    barycentric,
+                })
+            })
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+}
+
+impl Model {
+    /// Closest hit of a world space ray with the model placed by `model` (a model
+    /// matrix), with the index of the mesh hit.
+    pub fn intersect_ray(&self, ray: &Ray, model: &Mat4) -> Option<(usize, RayHit)> {
+        let ray = ray.transform(&model.inverse());
+        ray_aabb_intersection(&ray, &self.aabb())?;
+
+        self.meshes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, mesh)| {
+                let weights = self.weights.get(i).unwrap_or(mesh.weights());
+                mesh.intersect_ray(&ray, weights).map(|hit| (i, hit))
+            })
+            .min_by(|a, b| a.1.distance.total_cmp(&b.1.distance))
+    }
+}
+
+/// Casts a ray through the point `(x, y)` of the viewport (pixels from the top left)
+/// and returns the closest model it hits.
+///
+/// Every entry of `scene` is a model and the instances it is drawn with (see
+/// `Model::draw_instanced`), no instances meaning the model drawn with `Model::draw`.
+/// Triangles are hit from both sides and always at full detail.
+pub fn pick(
+    camera: &Camera,
+    scene: &[(&Model, &[Transform])],
+    x: f32,
+    y: f32,
+    viewport_size: Vec2,
+) -> Option<Pick> {
+    let ray = camera.screen_ray(x, y, viewport_size);
+
+    let mut closest: Option<Pick> = None;
+    for (model_index, (model, instances)) in scene.iter().enumerate() {
+        for (instance, matrix) in model_matrices(model, instances).iter().enumerate() {
+            let Some((mesh, hit)) = model.intersect_ray(&ray, matrix) else {
+                continue;
+            };
+            if closest.is_none_or(|closest| hit.distance < closest.hit.distance) {
+                closest = Some(Pick {
+                    model: model_index,
+                    instance,
+                    mesh,
+                    hit,
+                    position: ray.at(hit.distance),
+                });
+            }
+        }
+    }
+    closest
+}
+
+/// Picking by rasterizing ids instead of casting rays: exact to the pixel with what the
+/// rasterizer covers, at the cost of drawing the scene a second time.
+pub struct IdBuffer {
+    width: usize,
+    height: usize,
+    // 0 where nothing was drawn, otherwise `first + triangle` of an entry in `draws`
+    ids: Vec<u32>,
+    depth: Vec<f32>,
+    // first id, model, instance and mesh of every mesh drawn, sorted by id
+    draws: Vec<(u32, usize, usize, usize)>,
+    next_id: u32,
+}
+
+impl IdBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            ids: vec![0; width * height],
+            depth: vec![f32::INFINITY; width * height],
+            draws: vec![],
+            next_id: 1,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.ids.fill(0);
+        self.depth.fill(f32::INFINITY);
+        self.draws.clear();
+        self.next_id = 1;
+    }
+
+    /// Draws `model`, entry `model_index` of the scene, like `pick` expects it.
+    pub fn draw(
+        &mut self,
+        camera: &Camera,
+        model_index: usize,
+        model: &Model,
+        instances: &[Transform],
+    ) {
+        let view_projection = camera.projection() * camera.view();
+        let viewport_size = Vec2::new(self.width as f32, self.height as f32);
+
+        for (instance, matrix) in model_matrices(model, instances).iter().enumerate() {
+            let mvp = view_projection * *matrix;
+            if !Frustum::from_matrix(&mvp).intersects_aabb(&model.aabb()) {
+                continue;
+            }
+
+            for (i, mesh) in model.meshes.iter().enumerate() {
+                let weights = model.weights.get(i).unwrap_or(mesh.weights());
+                let vertices = mesh.morphed_vertices(weights);
+                let first = self.next_id;
+                self.draws.push((first, model_index, instance, i));
+                self.next_id += mesh.triangles().len() as u32;
+
+                for (t, triangle) in mesh.triangles().iter().enumerate() {
+                    let corners = triangle
+                        .to_array()
+                        .map(|i| vertices.get(i as usize).copied());
+                    let [Some(a), Some(b), Some(c)] = corners else {
+                        continue;
+                    };
+                    Triangle::new([a, b, c]).draw_id(
+                        &mut self.ids,
+                        &mut self.depth,
+                        &mvp,
+                        first + t as u32,
+                        viewport_size,
+                    );
+                }
+            }
+        }
+    }
+
+    /// What was drawn at pixel `(x, y)`, if anything.
+    pub fn get(&self, x: usize, y: usize) -> Option<IdHit> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let id = self.ids[y * self.width + x];
+        if id == 0 {
+            return None;
+        }
+
+        let draw = self.draws.partition_point(|draw| draw.0 <= id) - 1;
+        let (first, model, instance, mesh) = self.draws[draw];
+        Some(IdHit {
+            model,
+            instance,
+            mesh,
+            triangle: (id - first) as usize,
+        })
+    }
+}
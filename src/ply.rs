@@ -0,0 +1,340 @@
+use crate::error::RusterizerError;
+use crate::mesh::Mesh;
+use crate::obj::triangulate_polygon;
+
+use glam::{UVec3, Vec2, Vec3, Vec4};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    // integer colours are stored in the full range of their type
+    fn color_scale(&self) -> f32 {
+        match self {
+            Scalar::U8 => 255.0,
+            Scalar::U16 => 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Property {
+    Scalar(String, Scalar),
+    List(String, Scalar, Scalar),
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match self {
+            Property::Scalar(name, _) | Property::List(name, _, _) => name,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+fn decode_error(message: String) -> RusterizerError {
+    RusterizerError::Decode(message)
+}
+
+// reads values one at a time from either the ASCII tokens or the binary payload
+struct Values<'a> {
+    format: Format,
+    tokens: std::str::SplitAsciiWhitespace<'a>,
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Values<'a> {
+    fn new(format: Format, body: &'a [u8]) -> Result<Self, RusterizerError> {
+        let text = if format == Format::Ascii {
+            std::str::from_utf8(body).map_err(|e| decode_error(e.to_string()))?
+        } else {
+            ""
+        };
+
+        Ok(Self {
+            format,
+            tokens: text.split_ascii_whitespace(),
+            bytes: body,
+            offset: 0,
+        })
+    }
+
+    // whether `count` more values of `scalar` can be left to read. ASCII values take at
+    // least a byte each
+    fn can_hold(&self, count: usize, scalar: Scalar) -> bool {
+        let size = if self.format == Format::Ascii {
+            1
+        } else {
+            scalar.size()
+        };
+        count
+            .checked_mul(size)
+            .is_some_and(|bytes| bytes <= self.bytes.len() - self.offset)
+    }
+
+    fn read(&mut self, scalar: Scalar) -> Result<f64, RusterizerError> {
+        if self.format == Format::Ascii {
+            let token = self
+                .tokens
+                .next()
+                .ok_or_else(|| decode_error(String::from("unexpected end of PLY data")))?;
+            return token
+                .parse()
+                .map_err(|_| decode_error(format!("invalid PLY value {token:?}")));
+        }
+
+        let size = scalar.size();
+        let Some(raw) = self.bytes.get(self.offset..self.offset + size) else {
+            return Err(decode_error(String::from("unexpected end of PLY data")));
+        };
+        self.offset += size;
+
+        let mut buffer = [0u8; 8];
+        buffer[..size].copy_from_slice(raw);
+        if self.format == Format::BinaryBigEndian {
+            buffer[..size].reverse();
+        }
+
+        Ok(match scalar {
+            Scalar::I8 => buffer[0] as i8 as f64,
+            Scalar::U8 => buffer[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+            Scalar::U32 => u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+            Scalar::F32 => f32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+            Scalar::F64 => f64::from_le_bytes(buffer),
+        })
+    }
+}
+
+fn parse_header(header: &str) -> Result<(Format, Vec<Element>), RusterizerError> {
+    let mut lines = header.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err(decode_error(String::from("missing PLY magic number")));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+
+    for line in lines {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["format", kind, _version] => {
+                format = Some(match *kind {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(decode_error(format!("unknown PLY format {kind}"))),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| decode_error(format!("invalid element count {count:?}")))?,
+                properties: vec![],
+            }),
+            ["property", "list", count_type, item_type, name] => {
+                let (Some(count_type), Some(item_type)) =
+                    (Scalar::parse(count_type), Scalar::parse(item_type))
+                else {
+                    return Err(decode_error(format!("invalid list property {line:?}")));
+                };
+                let Some(element) = elements.last_mut() else {
+                    return Err(decode_error(String::from("property before element")));
+                };
+                element
+                    .properties
+                    .push(Property::List(name.to_string(), count_type, item_type));
+            }
+            ["property", scalar, name] => {
+                let Some(scalar) = Scalar::parse(scalar) else {
+                    return Err(decode_error(format!("invalid property {line:?}")));
+                };
+                let Some(element) = elements.last_mut() else {
+                    return Err(decode_error(String::from("property before element")));
+                };
+                element
+                    .properties
+                    .push(Property::Scalar(name.to_string(), scalar));
+            }
+            _ => {}
+        }
+    }
+
+    let format = format.ok_or_else(|| decode_error(String::from("missing PLY format line")))?;
+    Ok((format, elements))
+}
+
+impl Mesh {
+    /// Loads an ASCII or binary PLY file.
+    ///
+    /// Reads positions, normals, colours (`red`/`green`/`blue`/`alpha`) and texture
+    /// coordinates from the `vertex` element and polygons from the `face` element.
+    pub fn new_from_ply(file_path: &Path) -> Result<Mesh, RusterizerError> {
+        let bytes = std::fs::read(file_path)?;
+
+        let marker = b"end_header";
+        let Some(header_end) = bytes.windows(marker.len()).position(|w| w == marker) else {
+            return Err(decode_error(format!(
+                "{} has no PLY header",
+                file_path.display()
+            )));
+        };
+        // the payload starts after the newline ending the header
+        let body_start = bytes[header_end..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map_or(bytes.len(), |i| header_end + i + 1);
+
+        let header = String::from_utf8_lossy(&bytes[..header_end]);
+        let (format, elements) = parse_header(&header)?;
+        let mut values = Values::new(format, &bytes[body_start..])?;
+
+        let mut positions: Vec<Vec3> = vec![];
+        let mut normals: Vec<Vec3> = vec![];
+        let mut colors: Vec<Vec4> = vec![];
+        let mut uvs: Vec<Vec2> = vec![];
+        let mut triangles: Vec<UVec3> = vec![];
+
+        for element in &elements {
+            let has = |names: &[&str]| element.properties.iter().any(|p| names.contains(&p.name()));
+            let has_normals = has(&["nx"]);
+            let has_colors = has(&["red", "r"]);
+            let has_uvs = has(&["s", "u", "texture_u"]);
+
+            for _ in 0..element.count {
+                let mut position = Vec3::ZERO;
+                let mut normal = Vec3::ZERO;
+                let mut color = Vec4::ONE;
+                let mut uv = Vec2::ZERO;
+
+                for property in &element.properties {
+                    match property {
+                        Property::Scalar(name, scalar) => {
+                            let value = values.read(*scalar)? as f32;
+                            let color_value = value / scalar.color_scale();
+                            match name.as_str() {
+                                "x" => position.x = value,
+                                "y" => position.y = value,
+                                "z" => position.z = value,
+                                "nx" => normal.x = value,
+                                "ny" => normal.y = value,
+                                "nz" => normal.z = value,
+                                "red" | "r" => color.x = color_value,
+                                "green" | "g" => color.y = color_value,
+                                "blue" | "b" => color.z = color_value,
+                                "alpha" | "a" => color.w = color_value,
+                                "s" | "u" | "texture_u" => uv.x = value,
+                                // PLY follows the OpenGL convention of v pointing up
+                                "t" | "v" | "texture_v" => uv.y = 1.0 - value,
+                                _ => {}
+                            }
+                        }
+                        Property::List(name, count_type, item_type) => {
+                            let count = values.read(*count_type)? as usize;
+                            // the count comes from the file, check it before allocating
+                            if !values.can_hold(count, *item_type) {
+                                return Err(decode_error(format!(
+                                    "PLY list of {count} items past the end of the data"
+                                )));
+                            }
+                            let mut items = Vec::with_capacity(count);
+                            for _ in 0..count {
+                                items.push(values.read(*item_type)? as u32);
+                            }
+
+                            let is_face = element.name == "face"
+                                && (name == "vertex_indices" || name == "vertex_index");
+                            if is_face {
+                                let points: Vec<Vec3> = items
+                                    .iter()
+                                    .map(|i| {
+                                        positions.get(*i as usize).copied().unwrap_or_default()
+                                    })
+                                    .collect();
+                                for [a, b, c] in triangulate_polygon(&points) {
+                                    triangles.push(UVec3::new(items[a], items[b], items[c]));
+                                }
+                            }
+                        }
+                    }
+                }
+
+                if element.name == "vertex" {
+                    positions.push(position);
+                    if has_normals {
+                        normals.push(normal);
+                    }
+                    if has_colors {
+                        colors.push(color);
+                    }
+                    if has_uvs {
+                        uvs.push(uv);
+                    }
+                }
+            }
+        }
+
+        println!(
+            "Loaded {}: {} triangles, {} vertices",
+            file_path.display(),
+            triangles.len(),
+            positions.len()
+        );
+
+        let mut mesh = Mesh::new();
+        mesh.add_section_from_buffers(&triangles, &positions, &normals, &colors, &uvs)?;
+
+        Ok(mesh)
+    }
+}
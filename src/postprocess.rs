@@ -0,0 +1,467 @@
+use crate::error::RusterizerError;
+use crate::texture::Texture;
+use crate::utils::{from_argb8, linear_to_srgb, srgb_to_linear, to_argb8};
+use crate::ThreadPool;
+
+use glam::{Vec2, Vec3, Vec4, Vec4Swizzles};
+use std::path::Path;
+use std::sync::Arc;
+
+// rows of pixels given to each job of the thread pool
+const BAND_ROWS: usize = 16;
+
+const LUMA: Vec3 = Vec3::new(0.299, 0.587, 0.114);
+
+// FXAA tuning, from the original console version
+const FXAA_REDUCE_MIN: f32 = 1.0 / 128.0;
+const FXAA_REDUCE_MUL: f32 = 1.0 / 8.0;
+const FXAA_SPAN_MAX: f32 = 8.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMapping {
+    /// `c / (1 + c)`, never quite reaching white.
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve.
+    Aces,
+}
+
+/// One step of a `PostProcess` chain. Colours are linear and unbounded until a tone
+/// mapping brings them into [0, 1].
+#[derive(Debug, Clone)]
+pub enum PostEffect {
+    /// Scales the colour by 2^stops.
+    Exposure(f32),
+    ToneMap(ToneMapping),
+    /// Raises the colour to 1 / gamma.
+    Gamma(f32),
+    /// The sRGB transfer function, for displays expecting sRGB. Clamps to [0, 1].
+    SrgbEncode,
+    /// Fast approximate anti-aliasing, best placed after tone mapping.
+    Fxaa,
+    /// Blurs what is brighter than `threshold` (in luma) and adds it back. The blur
+    /// runs at half resolution, `radius` is in half resolution pixels.
+    Bloom {
+        threshold: f32,
+        intensity: f32,
+        radius: usize,
+    },
+    /// Darkens the corners by up to `strength`, starting at `radius` (a fraction of the
+    /// distance from the centre to a corner).
+    Vignette {
+        strength: f32,
+        radius: f32,
+    },
+    ColorGrading(Arc<Lut3d>),
+    /// Unsharp mask, 0 does nothing.
+    Sharpen(f32),
+}
+
+/// An effect of the chain, that can be switched off without removing it.
+#[derive(Debug, Clone)]
+pub struct PostPass {
+    pub effect: PostEffect,
+    pub enabled: bool,
+}
+
+/// Chain of full-screen effects applied in order to a float colour buffer, each one
+/// split in bands of rows running on a `ThreadPool`.
+#[derive(Debug, Clone, Default)]
+pub struct PostProcess {
+    pub passes: Vec<PostPass>,
+    // copy of the input of neighbourhood effects, and the half resolution bloom buffers
+    scratch: Vec<Vec4>,
+    bloom: Vec<Vec4>,
+    bloom_scratch: Vec<Vec4>,
+}
+
+impl PostProcess {
+    pub fn new(effects: Vec<PostEffect>) -> Self {
+        Self {
+            passes: effects
+                .into_iter()
+                .map(|effect| PostPass {
+                    effect,
+                    enabled: true,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn push(&mut self, effect: PostEffect) {
+        self.passes.push(PostPass {
+            effect,
+            enabled: true,
+        });
+    }
+
+    /// Switches pass `index` on or off, returning whether it is now enabled.
+    pub fn toggle(&mut self, index: usize) -> Option<bool> {
+        let pass = self.passes.get_mut(index)?;
+        pass.enabled = !pass.enabled;
+        Some(pass.enabled)
+    }
+
+    /// Runs the enabled passes over `colors`, `width` pixels wide.
+    pub fn apply(&mut self, thread_pool: &ThreadPool, colors: &mut [Vec4], width: usize) {
+        let height = colors.len() / width;
+        let passes = std::mem::take(&mut self.passes);
+        for pass in passes.iter().filter(|pass| pass.enabled) {
+            self.apply_effect(thread_pool, &pass.effect, colors, width, height);
+        }
+        self.passes = passes;
+    }
+
+    fn apply_effect(
+        &mut self,
+        thread_pool: &ThreadPool,
+        effect: &PostEffect,
+        colors: &mut [Vec4],
+        width: usize,
+        height: usize,
+    ) {
+        match effect {
+            PostEffect::Exposure(stops) => {
+                let scale = stops.exp2();
+                for_each_pixel(thread_pool, colors, width, |_, color| color.xyz() * scale);
+            }
+            PostEffect::ToneMap(ToneMapping::Reinhard) => {
+                for_each_pixel(thread_pool, colors, width, |_, color| {
+                    color.xyz() / (Vec3::ONE + color.xyz())
+                });
+            }
+            PostEffect::ToneMap(ToneMapping::Aces) => {
+                for_each_pixel(thread_pool, colors, width, |_, color| aces(color.xyz()));
+            }
+            PostEffect::Gamma(gamma) => {
+                let exponent = 1.0 / gamma;
+                for_each_pixel(thread_pool, colors, width, |_, color| {
+                    color.xyz().max(Vec3::ZERO).powf(exponent)
+                });
+            }
+            PostEffect::SrgbEncode => {
+                for_each_pixel(thread_pool, colors, width, |_, color| {
+                    let c = color.xyz();
+                    Vec3::new(
+                        linear_to_srgb(c.x),
+                        linear_to_srgb(c.y),
+                        linear_to_srgb(c.z),
+                    )
+                });
+            }
+            PostEffect::Vignette { strength, radius } => {
+                let center = Vec2::new(width as f32, height as f32) * 0.5;
+                for_each_pixel(thread_pool, colors, width, |pixel, color| {
+                    let distance = (pixel - center).length() / center.length();
+                    let t = ((distance - radius) / (1.0 - radius)).clamp(0.0, 1.0);
+                    color.xyz() * (1.0 - strength * t * t * (3.0 - 2.0 * t))
+                });
+            }
+            PostEffect::ColorGrading(lut) => {
+                for_each_pixel(thread_pool, colors, width, |_, color| {
+                    lut.sample(color.xyz())
+                });
+            }
+            PostEffect::Sharpen(amount) => {
+                let source = self.copy_of(colors);
+                for_each_band(thread_pool, colors, width, |first_row, band| {
+                    for (i, color) in band.iter_mut().enumerate() {
+                        let (x, y) = ((i % width) as isize, (first_row + i / width) as isize);
+                        let at = |dx, dy| texel(source, width, height, x + dx, y + dy).xyz();
+                        let neighbours = at(-1, 0) + at(1, 0) + at(0, -1) + at(0, 1);
+                        let center = at(0, 0);
+                        let sharpened = center + (center * 4.0 - neighbours) * *amount;
+                        *color = sharpened.max(Vec3::ZERO).extend(color.w);
+                    }
+                });
+            }
+            PostEffect::Fxaa => {
+                let source = self.copy_of(colors);
+                for_each_band(thread_pool, colors, width, |first_row, band| {
+                    for (i, color) in band.iter_mut().enumerate() {
+                        let (x, y) = (i % width, first_row + i / width);
+                        *color = fxaa(source, width, height, x, y).extend(color.w);
+                    }
+                });
+            }
+            PostEffect::Bloom {
+                threshold,
+                intensity,
+                radius,
+            } => self.bloom(
+                thread_pool,
+                colors,
+                width,
+                height,
+                *threshold,
+                *intensity,
+                *radius,
+            ),
+        }
+    }
+
+    // `colors` copied into the scratch buffer, for effects reading their neighbours
+    fn copy_of(&mut self, colors: &[Vec4]) -> &[Vec4] {
+        self.scratch.clear();
+        self.scratch.extend_from_slice(colors);
+        &self.scratch
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn bloom(
+        &mut self,
+        thread_pool: &ThreadPool,
+        colors: &mut [Vec4],
+        width: usize,
+        height: usize,
+        threshold: f32,
+        intensity: f32,
+        radius: usize,
+    ) {
+        let (half_width, half_height) = (width.div_ceil(2), height.div_ceil(2));
+        self.bloom.resize(half_width * half_height, Vec4::ZERO);
+        self.bloom_scratch
+            .resize(half_width * half_height, Vec4::ZERO);
+
+        // bright pass, each half resolution pixel averaging 2x2 pixels
+        let source: &[Vec4] = colors;
+        for_each_band(
+            thread_pool,
+            &mut self.bloom,
+            half_width,
+            |first_row, band| {
+                for (i, bright) in band.iter_mut().enumerate() {
+                    let (x, y) = ((i % half_width) * 2, (first_row + i / half_width) * 2);
+                    let mut sum = Vec3::ZERO;
+                    for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                        sum += texel(source, width, height, (x + dx) as isize, (y + dy) as isize)
+                            .xyz();
+                    }
+                    let color = sum * 0.25;
+                    let luma = color.dot(LUMA);
+                    let excess = (luma - threshold).max(0.0) / luma.max(1e-4);
+                    *bright = (color * excess).extend(1.0);
+                }
+            },
+        );
+
+        // separable gaussian blur, horizontally into the scratch buffer then vertically back
+        let sigma = (radius as f32 * 0.5).max(0.5);
+        let weights: Vec<f32> = (0..=radius)
+            .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+            .collect();
+        let total = weights[0] + 2.0 * weights[1..].iter().sum::<f32>();
+        let blur = |from: &[Vec4], to: &mut [Vec4], step: (isize, isize)| {
+            for_each_band(thread_pool, to, half_width, |first_row, band| {
+                for (i, blurred) in band.iter_mut().enumerate() {
+                    let (x, y) = (
+                        (i % half_width) as isize,
+                        (first_row + i / half_width) as isize,
+                    );
+                    let mut sum = from[coords(half_width, half_height, x, y)] * weights[0];
+                    for (offset, weight) in weights.iter().enumerate().skip(1) {
+                        let (dx, dy) = (step.0 * offset as isize, step.1 * offset as isize);
+                        sum += from[coords(half_width, half_height, x + dx, y + dy)] * *weight;
+                        sum += from[coords(half_width, half_height, x - dx, y - dy)] * *weight;
+                    }
+                    *blurred = sum / total;
+                }
+            });
+        };
+        blur(&self.bloom, &mut self.bloom_scratch, (1, 0));
+        blur(&self.bloom_scratch, &mut self.bloom, (0, 1));
+
+        // bilinear upsample added to the image
+        let bloom: &[Vec4] = &self.bloom;
+        for_each_pixel(thread_pool, colors, width, |pixel, color| {
+            let glow = sample(bloom, half_width, half_height, pixel * 0.5);
+            color.xyz() + glow.xyz() * intensity
+        });
+    }
+}
+
+/// 3D colour lookup table, sampled with trilinear filtering.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lut3d {
+    size: usize,
+    // red varies fastest, then green, then blue
+    data: Vec<Vec3>,
+}
+
+impl Lut3d {
+    /// A table of `size`³ entries filled by `f`, given the colour each entry stands for.
+    pub fn from_fn(size: usize, f: impl Fn(Vec3) -> Vec3) -> Self {
+        let size = size.max(2);
+        let scale = 1.0 / (size - 1) as f32;
+        let mut data = Vec::with_capacity(size * size * size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.push(f(Vec3::new(r as f32, g as f32, b as f32) * scale));
+                }
+            }
+        }
+        Self { size, data }
+    }
+
+    /// The table leaving colours unchanged.
+    pub fn identity(size: usize) -> Self {
+        Self::from_fn(size, |color| color)
+    }
+
+    /// Reads the usual strip layout: `size` squares of `size`×`size` pixels side by side,
+    /// red growing to the right, green downwards and blue from one square to the next.
+    pub fn from_texture(texture: &Texture) -> Result<Self, RusterizerError> {
+        let size = texture.height;
+        if size < 2 || texture.width != size * size {
+            return Err(RusterizerError::Decode(format!(
+                "a {}x{} image is not a LUT strip",
+                texture.width, texture.height
+            )));
+        }
+
+        let mut data = Vec::with_capacity(size * size * size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let (_, red, green, blue) =
+                        from_argb8(texture.data[g * texture.width + b * size + r]);
+                    data.push(Vec3::new(red as f32, green as f32, blue as f32) / 255.0);
+                }
+            }
+        }
+        Ok(Self { size, data })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, RusterizerError> {
+        Self::from_texture(&Texture::load(path)?)
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The graded colour, `color` being clamped to [0, 1] first.
+    pub fn sample(&self, color: Vec3) -> Vec3 {
+        let max = (self.size - 1) as f32;
+        let position = color.clamp(Vec3::ZERO, Vec3::ONE) * max;
+        let low = position.floor().min(Vec3::splat(max - 1.0));
+        let t = position - low;
+        let (r, g, b) = (low.x as usize, low.y as usize, low.z as usize);
+
+        let at = |dr: usize, dg: usize, db: usize| {
+            self.data[(r + dr) + (g + dg) * self.size + (b + db) * self.size * self.size]
+        };
+        let lerp_r = |dg, db| at(0, dg, db).lerp(at(1, dg, db), t.x);
+        let lerp_g = |db| lerp_r(0, db).lerp(lerp_r(1, db), t.y);
+        lerp_g(0).lerp(lerp_g(1), t.z)
+    }
+}
+
+/// Converts a buffer from the rasterizer, which is sRGB encoded, into linear colours
+/// for `PostProcess::apply`.
+pub fn argb8_to_colors(buffer: &[u32], colors: &mut [Vec4]) {
+    for (color, argb) in colors.iter_mut().zip(buffer) {
+        let (a, r, g, b) = from_argb8(*argb);
+        let decode = |c: u8| srgb_to_linear(c as f32 / 255.0);
+        *color = Vec4::new(decode(r), decode(g), decode(b), a as f32 / 255.0);
+    }
+}
+
+/// Converts colours back for display, clamping them to [0, 1]. They are stored as is,
+/// end the chain with `PostEffect::SrgbEncode` or `PostEffect::Gamma`.
+pub fn colors_to_argb8(colors: &[Vec4], buffer: &mut [u32]) {
+    for (argb, color) in buffer.iter_mut().zip(colors) {
+        let c = color.clamp(Vec4::ZERO, Vec4::ONE) * 255.0;
+        *argb = to_argb8(c.w as u8, c.x as u8, c.y as u8, c.z as u8);
+    }
+}
+
+// runs `f(first_row, band)` over bands of `BAND_ROWS` rows, waiting for all of them
+pub(crate) fn for_each_band<T, F>(thread_pool: &ThreadPool, pixels: &mut [T], width: usize, f: F)
+where
+    T: Send,
+    F: Fn(usize, &mut [T]) + Sync,
+{
+    let f = &f;
+    let jobs: Vec<Box<dyn FnOnce() + Send + '_>> = pixels
+        .chunks_mut(width * BAND_ROWS)
+        .enumerate()
+        .map(|(i, band)| Box::new(move || f(i * BAND_ROWS, band)) as Box<dyn FnOnce() + Send + '_>)
+        .collect();
+    thread_pool.execute_all(jobs);
+}
+
+// replaces the colour of every pixel by `f(pixel centre, colour)`, keeping the alpha
+fn for_each_pixel<F>(thread_pool: &ThreadPool, colors: &mut [Vec4], width: usize, f: F)
+where
+    F: Fn(Vec2, Vec4) -> Vec3 + Sync,
+{
+    for_each_band(thread_pool, colors, width, |first_row, band| {
+        for (i, color) in band.iter_mut().enumerate() {
+            let pixel = Vec2::new((i % width) as f32, (first_row + i / width) as f32) + 0.5;
+            *color = f(pixel, *color).extend(color.w);
+        }
+    });
+}
+
+// index of a pixel, clamped to the edges
+fn coords(width: usize, height: usize, x: isize, y: isize) -> usize {
+    let x = x.clamp(0, width as isize - 1) as usize;
+    let y = y.clamp(0, height as isize - 1) as usize;
+    y * width + x
+}
+
+fn texel(colors: &[Vec4], width: usize, height: usize, x: isize, y: isize) -> Vec4 {
+    colors[coords(width, height, x, y)]
+}
+
+// bilinear filtering, `position` in pixels with pixel centres at +0.5
+fn sample(colors: &[Vec4], width: usize, height: usize, position: Vec2) -> Vec4 {
+    let position = position - 0.5;
+    let low = position.floor();
+    let t = position - low;
+    let (x, y) = (low.x as isize, low.y as isize);
+    let top = texel(colors, width, height, x, y).lerp(texel(colors, width, height, x + 1, y), t.x);
+    let bottom = texel(colors, width, height, x, y + 1)
+        .lerp(texel(colors, width, height, x + 1, y + 1), t.x);
+    top.lerp(bottom, t.y)
+}
+
+fn aces(color: Vec3) -> Vec3 {
+    let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+    ((color * (color * a + b)) / (color * (color * c + d) + e)).clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+// blurs along the edge through the pixel, when there is one
+fn fxaa(colors: &[Vec4], width: usize, height: usize, x: usize, y: usize) -> Vec3 {
+    let (xi, yi) = (x as isize, y as isize);
+    let luma = |dx, dy| {
+        texel(colors, width, height, xi + dx, yi + dy)
+            .xyz()
+            .dot(LUMA)
+    };
+    let (nw, ne, sw, se) = (luma(-1, -1), luma(1, -1), luma(-1, 1), luma(1, 1));
+    let center = texel(colors, width, height, xi, yi).xyz();
+    let m = center.dot(LUMA);
+    let luma_min = m.min(nw.min(ne).min(sw.min(se)));
+    let luma_max = m.max(nw.max(ne).max(sw.max(se)));
+
+    let direction = Vec2::new(-((nw + ne) - (sw + se)), (nw + sw) - (ne + se));
+    let reduce = ((nw + ne + sw + se) * 0.25 * FXAA_REDUCE_MUL).max(FXAA_REDUCE_MIN);
+    let scale = 1.0 / (direction.x.abs().min(direction.y.abs()) + reduce);
+    let direction =
+        (direction * scale).clamp(Vec2::splat(-FXAA_SPAN_MAX), Vec2::splat(FXAA_SPAN_MAX));
+
+    let position = Vec2::new(x as f32, y as f32) + 0.5;
+    let at = |t: f32| sample(colors, width, height, position + direction * t).xyz();
+    let inner = (at(1.0 / 3.0 - 0.5) + at(2.0 / 3.0 - 0.5)) * 0.5;
+    let outer = inner * 0.5 + (at(-0.5) + at(0.5)) * 0.25;
+
+    let outer_luma = outer.dot(LUMA);
+    if outer_luma < luma_min || outer_luma > luma_max {
+        inner
+    } else {
+        outer
+    }
+}
@@ -0,0 +1,374 @@
+use crate::camera::Camera;
+use crate::gbuffer::GBuffer;
+use crate::geometry::FORWARD_AMBIENT;
+use crate::postprocess::for_each_band;
+use crate::utils::{from_argb8, linear_to_argb8, map_to_range, srgb_to_linear};
+use crate::ThreadPool;
+
+use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
+use std::f32::consts::TAU;
+
+/// Screen space ambient occlusion: darkens the ambient light of pixels surrounded by
+/// nearby geometry, found by testing points of a hemisphere against the depth buffer.
+///
+/// Deferred shading reads it through `Ssao::apply`, forward shaded images are darkened
+/// afterwards by `Ssao::apply_forward`.
+#[derive(Debug, Clone)]
+pub struct Ssao {
+    /// Size of the hemisphere, in world units.
+    pub radius: f32,
+    /// Points tested per pixel.
+    pub samples: usize,
+    /// 0 disables the effect, 1 makes fully occluded pixels black.
+    pub intensity: f32,
+    /// World distance a sample must be behind the surface to count, against self-occlusion.
+    pub bias: f32,
+    /// Radius of the bilateral blur removing the noise, in pixels.
+    pub blur_radius: usize,
+    kernel: Vec<Vec3>,
+    // world position and view depth of each pixel, w is infinite without geometry
+    positions: Vec<Vec4>,
+    raw: Vec<f32>,
+    blurred: Vec<f32>,
+    occlusion: Vec<f32>,
+}
+
+impl Default for Ssao {
+    fn default() -> Self {
+        Self {
+            radius: 0.5,
+            samples: 16,
+            intensity: 1.0,
+            bias: 0.02,
+            blur_radius: 4,
+            kernel: vec![],
+            positions: vec![],
+            raw: vec![],
+            blurred: vec![],
+            occlusion: vec![],
+        }
+    }
+}
+
+impl Ssao {
+    /// Computes the occlusion of every pixel of `depth_buffer`, drawn with `camera`.
+    /// Normals are rebuilt from the depth when `normals` is `None`.
+    pub fn compute(
+        &mut self,
+        thread_pool: &ThreadPool,
+        depth_buffer: &[f32],
+        normals: Option<&[Vec3]>,
+        camera: &Camera,
+        width: usize,
+    ) {
+        let height = depth_buffer.len() / width;
+        let view_projection = camera.projection() * camera.view();
+        let ndc_to_world = view_projection.inverse();
+        if self.kernel.len() != self.samples {
+            self.kernel = hemisphere_kernel(self.samples);
+        }
+        self.positions.resize(depth_buffer.len(), Vec4::ZERO);
+        self.raw.resize(depth_buffer.len(), 1.0);
+        self.blurred.resize(depth_buffer.len(), 1.0);
+        self.occlusion.resize(depth_buffer.len(), 1.0);
+
+        for_each_band(
+            thread_pool,
+            &mut self.positions,
+            width,
+            |first_row, band| {
+                for (i, position) in band.iter_mut().enumerate() {
+                    let (x, y) = (i % width, first_row + i / width);
+                    let depth = depth_buffer[y * width + x];
+                    if depth == f32::INFINITY {
+                        *position = Vec4::new(0.0, 0.0, 0.0, f32::INFINITY);
+                        continue;
+                    }
+                    let ndc = Vec4::new(
+                        map_to_range(x as f32 + 0.5, 0.0, width as f32, -1.0, 1.0),
+                        -map_to_range(y as f32 + 0.5, 0.0, height as f32, -1.0, 1.0),
+                        depth,
+                        1.0,
+                    );
+                    // the w of the unprojected point is one over the view depth
+                    let world = ndc_to_world * ndc;
+                    *position = (world.xyz() / world.w).extend(1.0 / world.w);
+                }
+            },
+        );
+
+        let inputs = Inputs {
+            kernel: &self.kernel,
+            radius: self.radius,
+            bias: self.bias,
+            intensity: self.intensity,
+            positions: &self.positions,
+            normals,
+            view_projection,
+            camera_position: camera.transform.translation,
+            width,
+            height,
+        };
+        for_each_band(thread_pool, &mut self.raw, width, |first_row, band| {
+            for (i, occlusion) in band.iter_mut().enumerate() {
+                *occlusion = inputs.occlusion(i % width, first_row + i / width);
+            }
+        });
+
+        let blur = Blur {
+            positions: &self.positions,
+            radius: self.blur_radius as isize,
+            width,
+            height,
+        };
+        for_each_band(thread_pool, &mut self.blurred, width, |first_row, band| {
+            for (i, blurred) in band.iter_mut().enumerate() {
+                *blurred = blur.pixel(&self.raw, i % width, first_row + i / width, (1, 0));
+            }
+        });
+        for_each_band(
+            thread_pool,
+            &mut self.occlusion,
+            width,
+            |first_row, band| {
+                for (i, occlusion) in band.iter_mut().enumerate() {
+                    *occlusion =
+                        blur.pixel(&self.blurred, i % width, first_row + i / width, (0, 1));
+                }
+            },
+        );
+    }
+
+    /// The result of the last `compute`, 1 where unoccluded.
+    pub fn occlusion(&self) -> &[f32] {
+        &self.occlusion
+    }
+
+    /// Multiplies the occlusion into the `Occlusion` attachment of `gbuffer`, which then
+    /// holds both the baked and the screen space terms.
+    pub fn apply(&self, gbuffer: &mut GBuffer) {
+        if let Some(occlusion) = &mut gbuffer.occlusion {
+            for (baked, screen) in occlusion.iter_mut().zip(&self.occlusion) {
+                *baked *= screen;
+            }
+        }
+    }
+
+    /// Takes the occluded share of the ambient light out of a forward shaded `buffer`,
+    /// which holds sRGB colours. `gbuffer` is the one the frame was drawn with, its
+    /// `Occlusion` attachment gives the baked term the ambient light was scaled by (1
+    /// without the attachment).
+    pub fn apply_forward(&self, thread_pool: &ThreadPool, gbuffer: &GBuffer, buffer: &mut [u32]) {
+        self.apply_forward_into(thread_pool, gbuffer, buffer, |pixel, darken| {
+            let (a, r, g, b) = from_argb8(*pixel);
+            let decode = |c: u8| srgb_to_linear(c as f32 / 255.0);
+            let color = Vec3::new(decode(r), decode(g), decode(b)) - darken;
+            *pixel = linear_to_argb8(color.max(Vec3::ZERO).extend(a as f32 / 255.0));
+        });
+    }
+
+    /// Like `apply_forward`, over the linear colours of a `PostProcess` chain.
+    pub fn apply_forward_hdr(
+        &self,
+        thread_pool: &ThreadPool,
+        gbuffer: &GBuffer,
+        colors: &mut [Vec4],
+    ) {
+        self.apply_forward_into(thread_pool, gbuffer, colors, |pixel, darken| {
+            *pixel = (pixel.xyz() - darken).max(Vec3::ZERO).extend(pixel.w);
+        });
+    }
+
+    fn apply_forward_into<T: Send>(
+        &self,
+        thread_pool: &ThreadPool,
+        gbuffer: &GBuffer,
+        buffer: &mut [T],
+        darken: fn(&mut T, Vec3),
+    ) {
+        let width = gbuffer.width;
+        for_each_band(thread_pool, buffer, width, |first_row, band| {
+            let first = first_row * width;
+            for (i, pixel) in band.iter_mut().enumerate() {
+                let Some(screen) = self.occlusion.get(first + i) else {
+                    return;
+                };
+                if *screen >= 1.0 {
+                    continue;
+                }
+                let baked = gbuffer.occlusion.as_ref().map_or(1.0, |o| o[first + i]);
+                darken(pixel, Vec3::splat(FORWARD_AMBIENT * baked * (1.0 - screen)));
+            }
+        });
+    }
+
+    /// Shows the occlusion alone, in shades of grey.
+    pub fn debug_view(&self, buffer: &mut [u32]) {
+        for (pixel, occlusion) in buffer.iter_mut().zip(&self.occlusion) {
+            *pixel = linear_to_argb8(Vec3::splat(*occlusion).extend(1.0));
+        }
+    }
+}
+
+// what the occlusion pass reads, shared by every job
+struct Inputs<'a> {
+    kernel: &'a [Vec3],
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+    positions: &'a [Vec4],
+    normals: Option<&'a [Vec3]>,
+    view_projection: Mat4,
+    camera_position: Vec3,
+    width: usize,
+    height: usize,
+}
+
+impl Inputs<'_> {
+    fn occlusion(&self, x: usize, y: usize) -> f32 {
+        let index = y * self.width + x;
+        let position = self.positions[index];
+        if position.w == f32::INFINITY || self.kernel.is_empty() {
+            return 1.0;
+        }
+        let point = position.xyz();
+        let Some(normal) = self.normal(x, y) else {
+            return 1.0;
+        };
+
+        // the hemisphere is rotated by a per pixel angle, the blur averages the noise
+        let noise =
+            (52.982_918 * (0.067_110_56 * x as f32 + 0.005_837_15 * y as f32).fract()).fract();
+        let (sin, cos) = (noise * TAU).sin_cos();
+        let (tangent, bitangent) = normal.any_orthonormal_pair();
+        let (tangent, bitangent) = (
+            tangent * cos + bitangent * sin,
+            bitangent * cos - tangent * sin,
+        );
+
+        let radius = self.radius;
+        let mut occluded = 0.0;
+        for offset in self.kernel {
+            let sample =
+                point + (tangent * offset.x + bitangent * offset.y + normal * offset.z) * radius;
+            let clip = self.view_projection * sample.extend(1.0);
+            if clip.w <= 0.0 {
+                continue;
+            }
+            let ndc = clip.xy() / clip.w;
+            let sx = map_to_range(ndc.x, -1.0, 1.0, 0.0, self.width as f32);
+            let sy = map_to_range(-ndc.y, -1.0, 1.0, 0.0, self.height as f32);
+            if sx < 0.0 || sy < 0.0 || sx >= self.width as f32 || sy >= self.height as f32 {
+                continue;
+            }
+
+            let scene = self.positions[sy as usize * self.width + sx as usize].w;
+            if scene < clip.w - self.bias {
+                // occluders much closer to the camera than the pixel fade out
+                let range = (radius / (position.w - scene).abs()).min(1.0);
+                occluded += range * range * (3.0 - 2.0 * range);
+            }
+        }
+
+        let occlusion = occluded / self.kernel.len() as f32;
+        (1.0 - occlusion * self.intensity).clamp(0.0, 1.0)
+    }
+
+    // from the normal attachment, or the depth of the neighbours
+    fn normal(&self, x: usize, y: usize) -> Option<Vec3> {
+        let index = y * self.width + x;
+        if let Some(normals) = self.normals {
+            return Some(normals[index]).filter(|normal| *normal != Vec3::ZERO);
+        }
+
+        // the neighbour on the same surface is the one with the closest depth
+        let position = self.positions[index];
+        let neighbour = |index: Option<usize>| {
+            index
+                .map(|index| self.positions[index])
+                .filter(|p| p.w != f32::INFINITY)
+                .map(|p| (p.xyz() - position.xyz(), (p.w - position.w).abs()))
+        };
+        let closest = |forward, backward| match (neighbour(forward), neighbour(backward)) {
+            (Some(f), Some(b)) if f.1 <= b.1 => Some(f.0),
+            (Some(f), None) => Some(f.0),
+            (_, Some(b)) => Some(-b.0),
+            (None, None) => None,
+        };
+        let right = (x + 1 < self.width).then_some(index + 1);
+        let left = (x > 0).then(|| index - 1);
+        let down = (y + 1 < self.height).then_some(index + self.width);
+        let up = (y > 0).then(|| index - self.width);
+        let dx = closest(right, left)?;
+        let dy = closest(down, up)?;
+
+        let normal = dx.cross(dy).normalize_or_zero();
+        if normal == Vec3::ZERO {
+            return None;
+        }
+        // facing the camera
+        let to_camera = self.camera_position - position.xyz();
+        Some(if normal.dot(to_camera) < 0.0 {
+            -normal
+        } else {
+            normal
+        })
+    }
+}
+
+// separable blur that does not mix pixels of different depths
+struct Blur<'a> {
+    positions: &'a [Vec4],
+    radius: isize,
+    width: usize,
+    height: usize,
+}
+
+impl Blur<'_> {
+    fn pixel(&self, source: &[f32], x: usize, y: usize, step: (isize, isize)) -> f32 {
+        let index = y * self.width + x;
+        let depth = self.positions[index].w;
+        if depth == f32::INFINITY {
+            return 1.0;
+        }
+
+        let sigma = (self.radius as f32 * 0.5).max(0.5);
+        let mut sum = 0.0;
+        let mut total = 0.0;
+        for offset in -self.radius..=self.radius {
+            let sx = x as isize + step.0 * offset;
+            let sy = y as isize + step.1 * offset;
+            if sx < 0 || sy < 0 || sx >= self.width as isize || sy >= self.height as isize {
+                continue;
+            }
+            let sample = sy as usize * self.width + sx as usize;
+            let difference = (self.positions[sample].w - depth) / (depth * 0.05);
+            let weight = (-(offset * offset) as f32 / (2.0 * sigma * sigma)).exp()
+                * (-difference * difference).exp();
+            sum += source[sample] * weight;
+            total += weight;
+        }
+        sum / total
+    }
+}
+
+// points in the hemisphere around +z, denser near the centre
+fn hemisphere_kernel(samples: usize) -> Vec<Vec3> {
+    // xorshift, the kernel only needs to be well spread and the same on every run
+    let mut state = 0x9e37_79b9_u32;
+    let mut random = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as f32 / u32::MAX as f32
+    };
+
+    (0..samples)
+        .map(|i| {
+            let direction =
+                Vec3::new(random() * 2.0 - 1.0, random() * 2.0 - 1.0, random()).normalize_or_zero();
+            let scale = (i as f32 + 1.0) / samples as f32;
+            direction * random() * (0.1 + 0.9 * scale * scale)
+        })
+        .collect()
+}
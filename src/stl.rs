@@ -0,0 +1,130 @@
+use crate::error::RusterizerError;
+use crate::mesh::{Mesh, NormalWeighting};
+
+use glam::{UVec3, Vec3};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Faces meeting at a sharper angle than this keep a hard edge when generating normals.
+pub const STL_CREASE_ANGLE: f32 = std::f32::consts::PI / 6.0;
+
+const BINARY_HEADER_SIZE: usize = 84;
+const BINARY_TRIANGLE_SIZE: usize = 50;
+
+fn read_f32(bytes: &[u8], offset: usize) -> f32 {
+    f32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+fn read_binary(bytes: &[u8]) -> Vec<[Vec3; 3]> {
+    let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+
+    (0..count)
+        .map(|i| {
+            // skip the facet normal, most exporters leave it zeroed anyway
+            let start = BINARY_HEADER_SIZE + i * BINARY_TRIANGLE_SIZE + 12;
+            [0, 1, 2].map(|v| {
+                let offset = start + v * 12;
+                Vec3::new(
+                    read_f32(bytes, offset),
+                    read_f32(bytes, offset + 4),
+                    read_f32(bytes, offset + 8),
+                )
+            })
+        })
+        .collect()
+}
+
+fn read_ascii(source: &str, path: &Path) -> Result<Vec<[Vec3; 3]>, RusterizerError> {
+    let mut triangles = vec![];
+    let mut facet: Vec<Vec3> = vec![];
+
+    for (line_number, line) in source.lines().enumerate() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.first() {
+            Some(&"vertex") => {
+                let mut coords = [0.0; 3];
+                for (coord, token) in coords.iter_mut().zip(tokens.iter().skip(1)) {
+                    *coord = token.parse().map_err(|_| {
+                        RusterizerError::Decode(format!(
+                            "{}:{}: invalid number {token:?}",
+                            path.display(),
+                            line_number + 1
+                        ))
+                    })?;
+                }
+                facet.push(Vec3::from_array(coords));
+            }
+            Some(&"endloop") => {
+                // polygons are allowed by the grammar, fan them
+                for i in 1..facet.len().saturating_sub(1) {
+                    triangles.push([facet[0], facet[i], facet[i + 1]]);
+                }
+                facet.clear();
+            }
+            _ => {}
+        }
+    }
+
+    Ok(triangles)
+}
+
+impl Mesh {
+    /// Loads an ASCII or binary STL file.
+    ///
+    /// STL stores a triangle soup, vertices are welded by position and normals are
+    /// regenerated with a crease angle of `STL_CREASE_ANGLE`.
+    pub fn new_from_stl(file_path: &Path) -> Result<Mesh, RusterizerError> {
+        let bytes = std::fs::read(file_path)?;
+
+        // binary files may start with "solid" as well, trust the size first
+        let is_binary = bytes.len() >= BINARY_HEADER_SIZE && {
+            let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+            bytes.len() == BINARY_HEADER_SIZE + count * BINARY_TRIANGLE_SIZE
+        };
+
+        let soup = if is_binary {
+            read_binary(&bytes)
+        } else if bytes.starts_with(b"solid") {
+            read_ascii(&String::from_utf8_lossy(&bytes), file_path)?
+        } else {
+            return Err(RusterizerError::Decode(format!(
+                "{} is neither an ASCII nor a binary STL file",
+                file_path.display()
+            )));
+        };
+
+        let mut ids: HashMap<[u32; 3], u32> = HashMap::new();
+        let mut positions: Vec<Vec3> = vec![];
+        let triangles: Vec<UVec3> = soup
+            .iter()
+            .map(|triangle| {
+                UVec3::from_array(triangle.map(|position| {
+                    *ids.entry(position.to_array().map(f32::to_bits))
+                        .or_insert_with(|| {
+                            positions.push(position);
+                            (positions.len() - 1) as u32
+                        })
+                }))
+            })
+            .collect();
+
+        println!(
+            "Loaded {}: {} triangles, {} vertices",
+            file_path.display(),
+            triangles.len(),
+            positions.len()
+        );
+
+        let normals = vec![Vec3::ZERO; positions.len()];
+        let mut mesh = Mesh::new();
+        mesh.add_section_from_buffers(&triangles, &positions, &normals, &[], &[])?;
+        mesh.compute_smooth_normals(NormalWeighting::Angle, Some(STL_CREASE_ANGLE));
+
+        Ok(mesh)
+    }
+}
@@ -1,7 +1,18 @@
+use crate::error::RusterizerError;
 use crate::utils::*;
 use glam::Vec4;
 use image::{self, GenericImageView};
 use std::path::Path;
+use std::sync::OnceLock;
+
+/// How the 8-bit channels of a texture map to values used in shading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// sRGB encoded colours, like base colour images. Decoded to linear when sampled.
+    Srgb,
+    /// Data read as is, like normal, occlusion or metallic-roughness maps.
+    Linear,
+}
 
 #[derive(Debug, Clone)]
 pub struct Texture {
@@ -9,12 +20,14 @@ pub struct Texture {
     pub width: usize,
     pub height: usize,
     pub data: Vec<u32>,
+    /// `Srgb` for every constructor, images usually being colours.
+    pub color_space: ColorSpace,
     //pub depth: usize,
 }
 
 impl Texture {
-    pub fn load(path: &Path) -> Self {
-        let decoded_image = image::open(path).expect("File not found");
+    pub fn load(path: &Path) -> Result<Self, RusterizerError> {
+        let decoded_image = image::open(path)?;
 
         let image_pixels = decoded_image.pixels();
 
@@ -28,13 +41,80 @@ impl Texture {
             data.insert(index, to_argb8(a, r, g, b));
         }
 
-        Texture {
+        Ok(Texture {
             width: decoded_image.width() as usize,
             height: decoded_image.height() as usize,
             data,
+            color_space: ColorSpace::Srgb,
+        })
+    }
+
+    /// Builds a texture from tightly packed 8-bit RGBA pixels, top row first.
+    pub fn from_rgba8(width: usize, height: usize, pixels: &[u8]) -> Self {
+        let data = pixels
+            .chunks_exact(4)
+            .map(|p| to_argb8(p[3], p[0], p[1], p[2]))
+            .collect();
+
+        Texture {
+            width,
+            height,
+            data,
+            color_space: ColorSpace::Srgb,
         }
     }
 
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+
+    /// Converts an image decoded by `gltf::import`.
+    pub fn from_gltf_image(image: &gltf::image::Data) -> Result<Self, RusterizerError> {
+        use gltf::image::Format;
+
+        let rgba: Vec<u8> = match image.format {
+            Format::R8G8B8A8 => image.pixels.clone(),
+            Format::R8G8B8 => image
+                .pixels
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 255])
+                .collect(),
+            Format::R8G8 => image
+                .pixels
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[1], 0, 255])
+                .collect(),
+            Format::R8 => image
+                .pixels
+                .iter()
+                .flat_map(|p| [*p, *p, *p, 255])
+                .collect(),
+            format => {
+                return Err(RusterizerError::UnsupportedGltf(format!(
+                    "image format {format:?}"
+                )))
+            }
+        };
+
+        Ok(Texture::from_rgba8(
+            image.width as usize,
+            image.height as usize,
+            &rgba,
+        ))
+    }
+
+    /// The pixels as tightly packed 8-bit RGBA, top row first.
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        self.data
+            .iter()
+            .flat_map(|argb| {
+                let (a, r, g, b) = from_argb8(*argb);
+                [r, g, b, a]
+            })
+            .collect()
+    }
+
     pub fn uv_to_index(&self, u: f32, v: f32) -> usize {
         let (u, v) = (u * self.width as f32, v * self.height as f32);
         coords_to_index(
@@ -67,4 +147,32 @@ impl Texture {
             Vec4::new(1.0, 1.0, 0.0, 1.0)
         }
     }
+
+    /// Linear RGBA at `uv`, decoding sRGB textures. Alpha is always linear.
+    pub fn color_at_uv(&self, u: f32, v: f32) -> Vec4 {
+        let id = self.uv_to_index(u, v);
+        let Some(argb) = self.data.get(id) else {
+            return Vec4::new(1.0, 0.0, 1.0, 1.0);
+        };
+        let (a, r, g, b) = from_argb8(*argb);
+        let alpha = a as f32 / 255.0;
+        match self.color_space {
+            ColorSpace::Linear => Vec4::new(r as f32, g as f32, b as f32, a as f32) / 255.0,
+            ColorSpace::Srgb => {
+                let decode = srgb_decode_table();
+                Vec4::new(
+                    decode[r as usize],
+                    decode[g as usize],
+                    decode[b as usize],
+                    alpha,
+                )
+            }
+        }
+    }
+}
+
+// linear value of every 8-bit sRGB channel
+fn srgb_decode_table() -> &'static [f32; 256] {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| std::array::from_fn(|i| srgb_to_linear(i as f32 / 255.0)))
 }
@@ -27,6 +27,37 @@ pub fn from_argb8(argb: u32) -> (u8, u8, u8, u8) {
     (a, r, g, b)
 }
 
+/// The sRGB transfer function, from a linear [0, 1] channel to its encoded value.
+pub fn linear_to_srgb(linear: f32) -> f32 {
+    let linear = linear.clamp(0.0, 1.0);
+    if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Inverse of `linear_to_srgb`.
+pub fn srgb_to_linear(encoded: f32) -> f32 {
+    let encoded = encoded.clamp(0.0, 1.0);
+    if encoded <= 0.040_45 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Packs a linear colour for display, encoding it to sRGB. Alpha is stored as is.
+pub fn linear_to_argb8(color: Vec4) -> u32 {
+    let encode = |linear: f32| (linear_to_srgb(linear) * 255.0 + 0.5) as u8;
+    to_argb8(
+        (color.w.clamp(0.0, 1.0) * 255.0 + 0.5) as u8,
+        encode(color.x),
+        encode(color.y),
+        encode(color.z),
+    )
+}
+
 pub fn lerp<T>(start: T, end: T, alpha: f32) -> T
 where
     T: std::ops::Sub<Output = T>